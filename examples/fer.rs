@@ -0,0 +1,13 @@
+//! Print the frame error rate for a soft decision AWGN channel as csv.
+//!
+//! Run with `cargo run --release --example fer --features sim`.
+
+use linkiq::{sim::AwgnChannel, sim::Sweep, stack::Stack};
+
+fn main() {
+    let stack = Stack::new();
+    let mut sweep = Sweep::new(AwgnChannel::new);
+    sweep.levels = (0..=8).map(|level| level as f64 * 0.5).collect();
+
+    sweep.write_csv(&stack, &mut std::io::stdout()).unwrap();
+}
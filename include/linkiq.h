@@ -0,0 +1,119 @@
+#ifndef LINKIQ_H
+#define LINKIQ_H
+
+/* Generated with cbindgen from src/ffi.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+// The maximum length of an encoded frame
+#define LINKIQ_MAX_FRAME_LENGTH 777
+
+#define LINKIQ_OK 0
+
+// A pointer was null or a packet field has an invalid value
+#define LINKIQ_ERROR_INVALID_ARGUMENT -1
+
+#define LINKIQ_ERROR_CAPACITY -2
+
+#define LINKIQ_ERROR_NOT_ENOUGH_BYTES -3
+
+#define LINKIQ_ERROR_PHL_DECODE -4
+
+#define LINKIQ_ERROR_RECEPTION_MISMATCH -5
+
+#define LINKIQ_ERROR_MBAL_CRC -6
+
+#define LINKIQ_ERROR_MBAL_CONTROL -7
+
+#define LINKIQ_ERROR_MBAL_ADDRESS -8
+
+#define LINKIQ_ERROR_MBAL_COMMAND -9
+
+#define LINKIQ_ERROR_FILTERED -10
+
+#define LINKIQ_ERROR_WMBUS_LENGTH -11
+
+#define LINKIQ_ERROR_WMBUS_CRC -12
+
+#define LINKIQ_CODE_RATE_ONE_THIRD 0
+
+#define LINKIQ_CODE_RATE_ONE_HALF 1
+
+// SND-NR
+#define LINKIQ_FUNCTION_CODE_SND_NR 4
+
+// SND-IR
+#define LINKIQ_FUNCTION_CODE_SND_IR 6
+
+// An opaque protocol stack
+typedef struct LinkiqStack LinkiqStack;
+
+// A packet in a C friendly representation
+typedef struct LinkiqPacket {
+  // One of the `LINKIQ_CODE_RATE_` constants
+  uint8_t code_rate;
+  bool is_prioritized;
+  // The address bytes in the order they are transmitted
+  uint8_t address[8];
+  // One of the `LINKIQ_FUNCTION_CODE_` constants
+  uint8_t function_code;
+  // The M-Bus data
+  const uint8_t *payload;
+  size_t payload_len;
+  // The reception statistics, which are ignored when encoding
+  uint32_t header_distance;
+  uint32_t decode_iterations;
+  uint32_t decode_distance;
+  uint32_t erased_bits;
+  uint32_t bit_flips;
+} LinkiqPacket;
+
+// Create a stack that must be freed with [`linkiq_stack_free`]
+LinkiqStack *linkiq_stack_new(void);
+
+// Free a stack
+//
+// # Safety
+// The stack must be null or created by [`linkiq_stack_new`] and not already freed.
+void linkiq_stack_free(LinkiqStack *stack);
+
+// Set the maximum number of turbo decoder iterations
+//
+// # Safety
+// The stack must be created by [`linkiq_stack_new`].
+int32_t linkiq_stack_set_max_iterations(LinkiqStack *stack, uint32_t max_iterations);
+
+// Get the length of a frame from its first bytes
+//
+// # Safety
+// The buffer must hold `length` bytes.
+int32_t linkiq_frame_length(const uint8_t *buffer, size_t length, size_t *frame_length);
+
+// Decode a frame in place.
+// The payload of the packet points into the frame buffer, which is corrected if decoding was needed.
+//
+// # Safety
+// The stack must be created by [`linkiq_stack_new`], the frame must hold `length` bytes,
+// and the packet must point to writable memory.
+int32_t linkiq_stack_decode(const LinkiqStack *stack,
+                            uint8_t *frame,
+                            size_t length,
+                            LinkiqPacket *packet);
+
+// Encode a packet into a buffer with room for `capacity` bytes
+//
+// # Safety
+// The stack must be created by [`linkiq_stack_new`], the payload of the packet must hold `payload_len` bytes,
+// the buffer must have room for `capacity` bytes, and `length` must point to writable memory.
+int32_t linkiq_stack_encode(const LinkiqStack *stack,
+                            const LinkiqPacket *packet,
+                            uint8_t *buffer,
+                            size_t capacity,
+                            size_t *length);
+
+// Get a static description of a status code
+const char *linkiq_status_str(int32_t status);
+
+#endif  /* LINKIQ_H */
@@ -0,0 +1,143 @@
+//! Helpers shared by the command-line tools.
+
+// Each tool only uses some of the helpers
+#![allow(dead_code)]
+
+use std::fmt::Write;
+
+use linkiq::{
+    fec::CodeRate,
+    stack::mbal::{self, MbalFunctionCode},
+};
+use wmbus::WMBusAddress;
+
+/// Parse a line of hex digits where whitespace, colons and dashes between bytes are ignored
+pub fn parse_hex(line: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = line
+        .trim()
+        .trim_start_matches("0x")
+        .bytes()
+        .filter(|c| !c.is_ascii_whitespace() && *c != b':' && *c != b'-')
+        .collect();
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".into());
+    }
+
+    digits
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|_| "invalid hex digit".to_string())?;
+            u8::from_str_radix(pair, 16).map_err(|_| format!("invalid hex byte '{pair}'"))
+        })
+        .collect()
+}
+
+/// Format bytes as space separated hex
+pub fn format_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(3 * bytes.len());
+    for (index, byte) in bytes.iter().enumerate() {
+        if index > 0 {
+            hex.push(' ');
+        }
+        write!(hex, "{byte:02X}").unwrap();
+    }
+    hex
+}
+
+pub fn code_rate_name(code_rate: CodeRate) -> &'static str {
+    match code_rate {
+        CodeRate::OneThird => "1/3",
+        CodeRate::OneHalf => "1/2",
+    }
+}
+
+pub fn function_code_name(function_code: MbalFunctionCode) -> &'static str {
+    function_code.name()
+}
+
+/// The parts of a wM-Bus address
+pub struct AddressParts {
+    /// The three letter manufacturer code, or the code in hex with a 0x prefix if it has no letters
+    pub manufacturer: String,
+    /// The identification number as the eight digits of its BCD encoding
+    pub id: String,
+    pub version: u8,
+    pub device_type: u8,
+}
+
+impl AddressParts {
+    pub fn new(address: &WMBusAddress) -> Self {
+        let bytes = address.get_bytes();
+        let bytes = bytes.as_slice();
+        let manufacturer = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let id = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]);
+        Self {
+            manufacturer: match mbal::manufacturer_letters(manufacturer) {
+                Some(letters) => letters.iter().map(|&letter| letter as char).collect(),
+                None => format!("0x{manufacturer:04x}"),
+            },
+            id: format!("{id:08x}"),
+            version: bytes[6],
+            device_type: bytes[7],
+        }
+    }
+}
+
+pub fn parse_code_rate(name: &str) -> Result<CodeRate, String> {
+    match name {
+        "1/3" => Ok(CodeRate::OneThird),
+        "1/2" => Ok(CodeRate::OneHalf),
+        _ => Err(format!("invalid code rate '{name}', expected 1/3 or 1/2")),
+    }
+}
+
+pub fn parse_function_code(name: &str) -> Result<MbalFunctionCode, String> {
+    match name {
+        "SND-NR" => Ok(MbalFunctionCode::SendUnsolicitedApplicationData),
+        "SND-IR" => Ok(MbalFunctionCode::SendInstallationRequest),
+        _ => Err(format!(
+            "invalid function code '{name}', expected SND-NR or SND-IR"
+        )),
+    }
+}
+
+/// Parse a byte given in decimal or as hex with a 0x prefix
+pub fn parse_byte(value: &str) -> Result<u8, String> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+    .map_err(|_| format!("invalid byte '{value}'"))
+}
+
+impl AddressParts {
+    pub fn to_address(&self) -> Result<WMBusAddress, String> {
+        let manufacturer = match self.manufacturer.strip_prefix("0x") {
+            Some(hex) => u16::from_str_radix(hex, 16).ok(),
+            None => mbal::manufacturer_from_letters(self.manufacturer.as_bytes()),
+        }
+        .ok_or_else(|| {
+            format!(
+                "invalid manufacturer '{}', expected three letters from @ to _ or a 0x prefixed code",
+                self.manufacturer
+            )
+        })?;
+
+        if self.id.len() != 8 || !self.id.bytes().all(|c| c.is_ascii_digit()) {
+            return Err(format!(
+                "invalid id '{}', expected eight decimal digits",
+                self.id
+            ));
+        }
+        let id = u32::from_str_radix(&self.id, 16).unwrap();
+
+        let mut bytes = [0; 8];
+        bytes[..2].copy_from_slice(&manufacturer.to_le_bytes());
+        bytes[2..6].copy_from_slice(&id.to_le_bytes());
+        bytes[6] = self.version;
+        bytes[7] = self.device_type;
+
+        WMBusAddress::from_bytes(bytes.as_slice().try_into().unwrap())
+            .map_err(|_| "invalid address".to_string())
+    }
+}
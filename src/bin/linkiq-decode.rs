@@ -0,0 +1,294 @@
+//! Decode LinkIQ frames and print their fields.
+//!
+//! Frames are read as hex lines, as a binary file of back-to-back frames, from a pcapng capture or from a frame log.
+//! The exit code is 0 if all frames were decoded, 1 if any frame failed to decode, and 2 on invalid input.
+//!
+//! Run with `cargo run --features cli --bin linkiq-decode -- --help`.
+
+mod common;
+
+use std::{
+    fs,
+    io::{self, BufRead, Read},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::{Parser, ValueEnum};
+use linkiq::{
+    framelog::LogReader,
+    pcap::PcapReader,
+    stack::{dissect::Dissection, phl, Packet, ReadError, Stack},
+};
+use serde_json::json;
+
+use common::{code_rate_name, format_hex, function_code_name, parse_hex, AddressParts};
+
+#[derive(Parser)]
+#[command(version, about = "Decode LinkIQ frames")]
+struct Args {
+    /// The input file, or standard input if omitted
+    input: Option<PathBuf>,
+
+    /// The format of the input
+    #[arg(short, long, value_enum, default_value_t = InputFormat::Hex)]
+    format: InputFormat,
+
+    /// The format of the output
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// The maximum number of turbo decoder iterations
+    #[arg(long, default_value_t = 10)]
+    max_iterations: usize,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum InputFormat {
+    /// One frame per line as hex digits. Empty lines and lines starting with '#' are skipped
+    Hex,
+    /// Back-to-back binary frames
+    Binary,
+    /// A pcapng capture with LinkIQ frames
+    Pcapng,
+    /// A binary frame log. Invalid records are skipped
+    Log,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// Human-readable fields
+    Text,
+    /// One JSON object per line
+    Json,
+    /// The tree of frame fields with the bits corrected by the decoder
+    Dissect,
+}
+
+const EXIT_DECODE_FAILURE: u8 = 1;
+const EXIT_INVALID_INPUT: u8 = 2;
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    let frames = match read_frames(&args) {
+        Ok(frames) => frames,
+        Err(error) => {
+            eprintln!("error: {error}");
+            return ExitCode::from(EXIT_INVALID_INPUT);
+        }
+    };
+
+    let mut stack = Stack::new();
+    stack.phl_mut().max_decode_iterations = args.max_iterations;
+
+    let mut failures = 0;
+    for (index, frame) in frames.iter().enumerate() {
+        let is_decoded = match args.output {
+            OutputFormat::Text => {
+                let result = stack.read(frame);
+                print_text(index, frame, &result);
+                result.is_ok()
+            }
+            OutputFormat::Json => {
+                let result = stack.read(frame);
+                print_json(index, frame, &result);
+                result.is_ok()
+            }
+            OutputFormat::Dissect => {
+                let result = stack.dissect(frame);
+                print_dissection(index, frame, &result);
+                result.is_ok()
+            }
+        };
+        if !is_decoded {
+            failures += 1;
+        }
+    }
+
+    if failures > 0 {
+        ExitCode::from(EXIT_DECODE_FAILURE)
+    } else {
+        ExitCode::SUCCESS
+    }
+}
+
+fn read_frames(args: &Args) -> Result<Vec<Vec<u8>>, String> {
+    let input = match &args.input {
+        Some(path) => fs::read(path).map_err(|error| format!("{}: {error}", path.display()))?,
+        None => {
+            let mut input = Vec::new();
+            io::stdin()
+                .read_to_end(&mut input)
+                .map_err(|error| error.to_string())?;
+            input
+        }
+    };
+
+    match args.format {
+        InputFormat::Hex => read_hex_frames(&input),
+        InputFormat::Binary => read_binary_frames(&input),
+        InputFormat::Pcapng => read_pcapng_frames(&input),
+        InputFormat::Log => read_log_frames(&input),
+    }
+}
+
+fn read_hex_frames(input: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut frames = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        frames.push(parse_hex(line).map_err(|error| format!("line {}: {error}", number + 1))?);
+    }
+    Ok(frames)
+}
+
+fn read_binary_frames(mut input: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut frames = Vec::new();
+    let mut offset = 0;
+    while !input.is_empty() {
+        let length = phl::get_frame_length(input)
+            .map_err(|error| format!("offset {offset}: no frame header ({error:?})"))?;
+        let length = length.min(input.len());
+
+        frames.push(input[..length].to_vec());
+        input = &input[length..];
+        offset += length;
+    }
+    Ok(frames)
+}
+
+fn read_pcapng_frames(input: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    PcapReader::new(input)
+        .and_then(|reader| reader.map(|record| record.map(|record| record.frame)).collect())
+        .map_err(|error| format!("pcapng: {error}"))
+}
+
+fn read_log_frames(input: &[u8]) -> Result<Vec<Vec<u8>>, String> {
+    let mut reader = LogReader::new(input).map_err(|error| format!("log: {error}"))?;
+    let frames = reader.by_ref().map(|record| record.frame).collect();
+    if reader.skipped_bytes() > 0 {
+        eprintln!("warning: skipped {} invalid log bytes", reader.skipped_bytes());
+    }
+    Ok(frames)
+}
+
+fn print_text(index: usize, frame: &[u8], result: &Result<Packet, ReadError>) {
+    println!("frame {}: {} bytes", index + 1, frame.len());
+
+    let packet = match result {
+        Ok(packet) => packet,
+        Err(error) => {
+            println!("  decode failed:     {error:?}");
+            return;
+        }
+    };
+
+    if let Some(phl) = &packet.phl {
+        println!("  code rate:         {}", code_rate_name(phl.code_rate));
+    }
+    if let Some(rx) = &packet.rx {
+        println!("  header distance:   {}", rx.header_distance);
+        println!("  decode iterations: {}", rx.decode_iterations);
+        println!("  decode distance:   {}", rx.decode_distance);
+    }
+    if let Some(mbal) = &packet.mbal {
+        let address = AddressParts::new(&mbal.address);
+        println!(
+            "  control:           {}",
+            match mbal.control.is_prioritized {
+                true => "prioritized",
+                false => "normal",
+            }
+        );
+        println!(
+            "  address:           {} {} version {} type 0x{:02X}",
+            address.manufacturer, address.id, address.version, address.device_type
+        );
+        println!(
+            "  function code:     {}",
+            function_code_name(mbal.command.function_code)
+        );
+    }
+    println!("  payload:           {}", format_hex(&packet.mbus_data));
+}
+
+fn print_json(index: usize, frame: &[u8], result: &Result<Packet, ReadError>) {
+    let line = match result {
+        Ok(packet) => {
+            let phl = packet.phl.as_ref().unwrap();
+            let rx = packet.rx.as_ref().unwrap();
+            let mbal = packet.mbal.as_ref().unwrap();
+            let address = AddressParts::new(&mbal.address);
+            json!({
+                "frame": index + 1,
+                "length": frame.len(),
+                "code_rate": code_rate_name(phl.code_rate),
+                "header_distance": rx.header_distance,
+                "decode_iterations": rx.decode_iterations,
+                "decode_distance": rx.decode_distance,
+                "is_prioritized": mbal.control.is_prioritized,
+                "address": {
+                    "manufacturer": address.manufacturer,
+                    "id": address.id,
+                    "version": address.version,
+                    "device_type": address.device_type,
+                },
+                "function_code": function_code_name(mbal.command.function_code),
+                "payload": format_hex(&packet.mbus_data).replace(' ', ""),
+            })
+        }
+        Err(error) => json!({
+            "frame": index + 1,
+            "length": frame.len(),
+            "error": format!("{error:?}"),
+        }),
+    };
+
+    println!("{line}");
+}
+
+fn print_dissection(index: usize, frame: &[u8], result: &Result<Dissection, ReadError>) {
+    println!("frame {}: {} bytes", index + 1, frame.len());
+
+    let dissection = match result {
+        Ok(dissection) => dissection,
+        Err(error) => {
+            println!("  decode failed: {error:?}");
+            return;
+        }
+    };
+
+    for field in &dissection.fields {
+        let mut line = format!(
+            "{:indent$}{} [bits {}..{}]",
+            "",
+            field.name,
+            field.bit_offset,
+            field.bit_offset + field.bit_length,
+            indent = 2 + 2 * field.depth
+        );
+        if let Some(value) = field.value {
+            line += &format!(" = 0x{value:X}");
+        }
+        if !field.interpretation.is_empty() {
+            line += &format!(": {}", field.interpretation);
+        }
+        if field.corrected_bits > 0 {
+            line += &format!(" ({} bits corrected)", field.corrected_bits);
+        }
+        println!("{line}");
+    }
+
+    let corrected: Vec<String> = (0..8 * dissection.received_frame().len())
+        .filter(|&bit| dissection.is_corrected(bit))
+        .map(|bit| bit.to_string())
+        .collect();
+    if !corrected.is_empty() {
+        println!("  corrected bits: {}", corrected.join(" "));
+    }
+}
@@ -0,0 +1,256 @@
+//! Build LinkIQ frames for bench testing receivers, optionally with injected errors.
+//!
+//! A frame is described by the command-line arguments, or by JSON lines with the same fields
+//! as the JSON output of `linkiq-decode`. IQ output is not supported as there is no modulator.
+//!
+//! Run with `cargo run --features cli --bin linkiq-encode -- --help`.
+
+mod common;
+
+use std::{
+    fs,
+    io::{self, BufRead, Read, Write},
+    path::PathBuf,
+    process::ExitCode,
+};
+
+use clap::{Parser, ValueEnum};
+use linkiq::{
+    fec::CodeRate,
+    sim::{BinarySymmetricChannel, BurstChannel, ChannelModel},
+    stack::{mbal::MbalFunctionCode, Packet, PacketBuilder, Stack},
+};
+use rand::{rngs::StdRng, RngCore, SeedableRng};
+use serde_json::Value;
+
+use common::{
+    format_hex, parse_byte, parse_code_rate, parse_function_code, parse_hex, AddressParts,
+};
+
+#[derive(Parser)]
+#[command(version, about = "Build LinkIQ frames")]
+struct Args {
+    /// JSON lines describing the frames, or '-' for standard input.
+    /// A single frame is described by the arguments if omitted
+    #[arg(long, value_name = "FILE")]
+    json: Option<PathBuf>,
+
+    /// The code rate, 1/3 or 1/2
+    #[arg(long, default_value = "1/3", value_parser = parse_code_rate)]
+    code_rate: CodeRate,
+
+    /// Mark the frame as prioritized
+    #[arg(long)]
+    prioritized: bool,
+
+    /// The three letter manufacturer code, or the code in hex with a 0x prefix
+    #[arg(long, default_value = "KAM")]
+    manufacturer: String,
+
+    /// The eight digit identification number
+    #[arg(long, default_value = "00000000")]
+    id: String,
+
+    /// The version, in decimal or as hex with a 0x prefix
+    #[arg(long, default_value = "1", value_parser = parse_byte)]
+    version: u8,
+
+    /// The device type, in decimal or as hex with a 0x prefix
+    #[arg(long, default_value = "0x16", value_parser = parse_byte)]
+    device_type: u8,
+
+    /// The function code, SND-NR or SND-IR
+    #[arg(long, default_value = "SND-NR", value_parser = parse_function_code)]
+    function_code: MbalFunctionCode,
+
+    /// The M-Bus data as hex digits
+    #[arg(long, default_value = "")]
+    payload: String,
+
+    /// The probability of flipping each bit
+    #[arg(long, default_value_t = 0.0)]
+    ber: f64,
+
+    /// The probability that an error burst starts at any given bit
+    #[arg(long, default_value_t = 0.0)]
+    burst_probability: f64,
+
+    /// The mean length of an error burst in bits
+    #[arg(long, default_value_t = 16.0)]
+    burst_length: f64,
+
+    /// The number of bytes to remove from the end of the frame
+    #[arg(long, default_value_t = 0)]
+    truncate: usize,
+
+    /// The seed of the random errors
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+
+    /// The format of the output
+    #[arg(short, long, value_enum, default_value_t = OutputFormat::Hex)]
+    output: OutputFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    /// One frame per line as hex digits
+    Hex,
+    /// Back-to-back binary frames
+    Binary,
+}
+
+/// The fields of a frame to build
+struct Description {
+    code_rate: CodeRate,
+    is_prioritized: bool,
+    address: AddressParts,
+    function_code: MbalFunctionCode,
+    payload: Vec<u8>,
+}
+
+const EXIT_INVALID_INPUT: u8 = 2;
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+
+    match run(&args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::from(EXIT_INVALID_INPUT)
+        }
+    }
+}
+
+fn run(args: &Args) -> Result<(), String> {
+    let descriptions = match &args.json {
+        Some(path) => read_json_descriptions(path)?,
+        None => vec![Description {
+            code_rate: args.code_rate,
+            is_prioritized: args.prioritized,
+            address: AddressParts {
+                manufacturer: args.manufacturer.clone(),
+                id: args.id.clone(),
+                version: args.version,
+                device_type: args.device_type,
+            },
+            function_code: args.function_code,
+            payload: parse_hex(&args.payload)?,
+        }],
+    };
+
+    let stack = Stack::new();
+    let mut rng = StdRng::seed_from_u64(args.seed);
+    let mut stdout = io::stdout().lock();
+
+    for description in descriptions {
+        let frame = encode(&stack, &description)?;
+        let frame = inject_errors(args, &mut rng, frame, description.code_rate);
+
+        match args.output {
+            OutputFormat::Hex => writeln!(stdout, "{}", format_hex(&frame).replace(' ', "")),
+            OutputFormat::Binary => stdout.write_all(&frame),
+        }
+        .map_err(|error| error.to_string())?;
+    }
+
+    Ok(())
+}
+
+fn read_json_descriptions(path: &PathBuf) -> Result<Vec<Description>, String> {
+    let input = if path.as_os_str() == "-" {
+        let mut input = Vec::new();
+        io::stdin()
+            .read_to_end(&mut input)
+            .map_err(|error| error.to_string())?;
+        input
+    } else {
+        fs::read(path).map_err(|error| format!("{}: {error}", path.display()))?
+    };
+
+    let mut descriptions = Vec::new();
+    for (number, line) in input.lines().enumerate() {
+        let line = line.map_err(|error| error.to_string())?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let description = serde_json::from_str(&line)
+            .map_err(|error| error.to_string())
+            .and_then(|value| parse_description(&value))
+            .map_err(|error| format!("line {}: {error}", number + 1))?;
+        descriptions.push(description);
+    }
+    Ok(descriptions)
+}
+
+fn parse_description(value: &Value) -> Result<Description, String> {
+    let string = |value: &Value, name: &str| {
+        value[name]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| format!("missing string '{name}'"))
+    };
+    let byte = |value: &Value, name: &str| {
+        value[name]
+            .as_u64()
+            .and_then(|byte| u8::try_from(byte).ok())
+            .ok_or_else(|| format!("missing byte '{name}'"))
+    };
+
+    let address = &value["address"];
+    Ok(Description {
+        code_rate: parse_code_rate(&string(value, "code_rate")?)?,
+        is_prioritized: value["is_prioritized"].as_bool().unwrap_or(false),
+        address: AddressParts {
+            manufacturer: string(address, "manufacturer")?,
+            id: string(address, "id")?,
+            version: byte(address, "version")?,
+            device_type: byte(address, "device_type")?,
+        },
+        function_code: match value["function_code"].as_str() {
+            Some(name) => parse_function_code(name)?,
+            None => MbalFunctionCode::SendUnsolicitedApplicationData,
+        },
+        payload: parse_hex(&string(value, "payload")?)?,
+    })
+}
+
+fn encode(stack: &Stack, description: &Description) -> Result<Vec<u8>, String> {
+    let packet: Packet = PacketBuilder::new()
+        .code_rate(description.code_rate)
+        .prioritized(description.is_prioritized)
+        .address(description.address.to_address()?)
+        .function_code(description.function_code)
+        .mbus_data(&description.payload)
+        .build()
+        .map_err(|_| "payload is too long".to_string())?;
+
+    let mut frame = Vec::new();
+    stack
+        .write(&mut frame, &packet)
+        .map_err(|error| format!("{error:?}"))?;
+    Ok(frame)
+}
+
+fn inject_errors(
+    args: &Args,
+    rng: &mut dyn RngCore,
+    mut frame: Vec<u8>,
+    rate: CodeRate,
+) -> Vec<u8> {
+    if args.ber > 0.0 {
+        frame = BinarySymmetricChannel::new(args.ber)
+            .transmit(rng, &frame, rate)
+            .to_bytes();
+    }
+    if args.burst_probability > 0.0 {
+        frame = BurstChannel::new(args.burst_probability, args.burst_length)
+            .transmit(rng, &frame, rate)
+            .to_bytes();
+    }
+
+    frame.truncate(frame.len().saturating_sub(args.truncate));
+    frame
+}
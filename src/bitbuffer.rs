@@ -0,0 +1,138 @@
+use bitvec::prelude::*;
+
+/// Fixed capacity bit buffer backed by `N` bytes where the bits are stored msb first
+pub(crate) struct BitBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> BitBuffer<N> {
+    pub const fn new() -> Self {
+        Self {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.bytes.fill(0);
+        self.len = 0;
+    }
+
+    /// Append a bit.
+    /// Panics if the buffer is full.
+    pub fn push(&mut self, bit: bool) {
+        self.bytes.view_bits_mut::<Msb0>().set(self.len, bit);
+        self.len += 1;
+    }
+
+    pub fn extend_from_bitslice<T: BitStore, O: BitOrder>(&mut self, bits: &BitSlice<T, O>) {
+        for bit in bits.iter().by_vals() {
+            self.push(bit);
+        }
+    }
+
+    /// Grow the buffer with `value` bits, or shrink it and clear the removed bits
+    pub fn resize(&mut self, len: usize, value: bool) {
+        while self.len < len {
+            self.push(value);
+        }
+        if len < self.len {
+            self.bytes.view_bits_mut::<Msb0>()[len..self.len].fill(false);
+        }
+        self.len = len;
+    }
+
+    pub fn any(&self) -> bool {
+        self.as_bitslice().any()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        self.as_bitslice().iter().by_vals()
+    }
+
+    pub fn as_bitslice(&self) -> &BitSlice<u8, Msb0> {
+        &self.bytes.view_bits()[..self.len]
+    }
+
+    pub fn as_mut_bitslice(&mut self) -> &mut BitSlice<u8, Msb0> {
+        &mut self.bytes.view_bits_mut()[..self.len]
+    }
+
+    /// Get the bytes holding the bits, where unused bits in the last byte are zero
+    pub fn as_raw_slice(&self) -> &[u8] {
+        &self.bytes[..self.len.div_ceil(8)]
+    }
+
+    pub fn as_raw_mut_slice(&mut self) -> &mut [u8] {
+        &mut self.bytes[..self.len.div_ceil(8)]
+    }
+}
+
+impl<const N: usize> Default for BitBuffer<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_push_bits() {
+        // Given
+        let mut buffer = BitBuffer::<2>::new();
+
+        // When
+        for bit in [true, false, true, true, false, false, true, false, true] {
+            buffer.push(bit);
+        }
+
+        // Then
+        assert_eq!(9, buffer.len());
+        assert_eq!([0xB2, 0x80], buffer.as_raw_slice());
+    }
+
+    #[test]
+    fn can_clear_and_resize() {
+        // Given
+        let mut buffer = BitBuffer::<2>::new();
+        buffer.resize(12, true);
+
+        // When
+        buffer.clear();
+        buffer.push(true);
+        buffer.resize(3, false);
+
+        // Then
+        assert_eq!(3, buffer.len());
+        assert_eq!([0x80], buffer.as_raw_slice());
+        assert!(buffer.any());
+    }
+
+    #[test]
+    fn can_shrink() {
+        // Given
+        let mut buffer = BitBuffer::<2>::new();
+        buffer.resize(12, true);
+
+        // When
+        buffer.resize(5, true);
+
+        // Then
+        assert_eq!(5, buffer.len());
+        assert_eq!([0xF8], buffer.as_raw_slice());
+
+        buffer.resize(9, false);
+        assert_eq!([0xF8, 0x00], buffer.as_raw_slice());
+    }
+}
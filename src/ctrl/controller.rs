@@ -1,10 +1,13 @@
 use embassy_time::{with_timeout, Duration, TimeoutError, Timer};
 use futures::Stream;
-use futures_async_stream::stream;
+use futures_async_stream::try_stream;
 
 use crate::{
     ctrl::traits::RxToken,
-    stack::{phl, Channel, ReadError, Rssi},
+    stack::{
+        filter::{AcceptAll, AddressFilter},
+        mbal, phl, Channel, ReadError, Rssi,
+    },
 };
 
 use super::{noicefloor::NoiceFloor, traits, Frame};
@@ -12,22 +15,43 @@ use super::{noicefloor::NoiceFloor, traits, Frame};
 const CHANNEL_COUNT: usize = 4;
 
 /// LinkIQ Transceiver Controller
-pub struct Controller<Transceiver: traits::Transceiver> {
+///
+/// Received frames have room for `FRAME_LENGTH` bytes, and longer frames are ignored.
+/// The length for frames with at most `MBAL_MAX` MBAL bytes is [`phl::max_frame_length`]`(MBAL_MAX)`.
+pub struct Controller<
+    Transceiver: traits::Transceiver,
+    Filter: AddressFilter = AcceptAll,
+    const FRAME_LENGTH: usize = { phl::MAX_FRAME_LENGTH },
+> {
     transceiver: Transceiver,
+    filter: Filter,
     listening: bool,
     current_channel: Channel,
     min_snr: i8,
     noise_floor: [NoiceFloor; CHANNEL_COUNT],
+    early_completion: bool,
+    truncated_frames: bool,
 }
 
 impl<Transceiver> Controller<Transceiver>
 where
     Transceiver: traits::Transceiver,
 {
-    /// Create a new controller
+    /// Create a new controller for frames of any length
     pub const fn new(transceiver: Transceiver) -> Self {
+        Self::new_sized(transceiver)
+    }
+}
+
+impl<Transceiver, const FRAME_LENGTH: usize> Controller<Transceiver, AcceptAll, FRAME_LENGTH>
+where
+    Transceiver: traits::Transceiver,
+{
+    /// Create a new controller for frames of at most `FRAME_LENGTH` bytes
+    pub const fn new_sized(transceiver: Transceiver) -> Self {
         Self {
             transceiver,
+            filter: AcceptAll,
             listening: false,
             current_channel: Channel::A,
             min_snr: 4,
@@ -37,8 +61,48 @@ where
                 NoiceFloor::new(-110),
                 NoiceFloor::new(-110),
             ],
+            early_completion: false,
+            truncated_frames: false,
         }
     }
+}
+
+impl<Transceiver, Filter, const FRAME_LENGTH: usize> Controller<Transceiver, Filter, FRAME_LENGTH>
+where
+    Transceiver: traits::Transceiver,
+    Filter: AddressFilter,
+{
+    /// Use an address filter to abort the reception of frames from unwanted meters.
+    /// The filter is applied as soon as the address is received in the systematic block.
+    pub fn with_address_filter<F: AddressFilter>(
+        self,
+        filter: F,
+    ) -> Controller<Transceiver, F, FRAME_LENGTH> {
+        Controller {
+            transceiver: self.transceiver,
+            filter,
+            listening: self.listening,
+            current_channel: self.current_channel,
+            min_snr: self.min_snr,
+            noise_floor: self.noise_floor,
+            early_completion: self.early_completion,
+            truncated_frames: self.truncated_frames,
+        }
+    }
+
+    /// Enable or disable early frame completion.
+    /// When enabled, the reception of a frame ends as soon as its systematic block passes the CRC,
+    /// and the frame is yielded without the parity.
+    pub fn set_early_completion(&mut self, enabled: bool) {
+        self.early_completion = enabled;
+    }
+
+    /// Enable or disable the delivery of truncated frames.
+    /// When enabled, a frame whose reception fails after its systematic block is yielded
+    /// without the missing parity, see [`Frame::is_truncated`].
+    pub fn set_truncated_frames(&mut self, enabled: bool) {
+        self.truncated_frames = enabled;
+    }
 
     pub fn noise_floor(&self) -> [Rssi; CHANNEL_COUNT] {
         let mut res = [0; CHANNEL_COUNT];
@@ -72,9 +136,14 @@ where
 
     /// Start and run receiver.
     /// Note that the receiver is _not_ stopped when the stream is dropped, so idle() must be called manually after the stream is dropped.
+    /// The stream ends after it yields an error of the transceiver.
+    /// A failed read of a frame is not an error, as it only ends the reception of that frame.
     pub async fn receive<'a>(
         &'a mut self,
-    ) -> Result<impl Stream<Item = Frame> + 'a, Transceiver::Error> {
+    ) -> Result<
+        impl Stream<Item = Result<Frame<FRAME_LENGTH>, Transceiver::Error>> + 'a,
+        Transceiver::Error,
+    > {
         assert!(!self.listening);
         self.transceiver.set_channel(self.current_channel).await?;
 
@@ -85,19 +154,16 @@ where
         Ok(self.receive_stream())
     }
 
-    #[stream(item = Frame)]
+    #[try_stream(ok = Frame<FRAME_LENGTH>, error = Transceiver::Error)]
     async fn receive_stream(&mut self) {
         loop {
             // Make time for test to yield as all mocked futures are completed
             #[cfg(test)]
             Timer::after(Duration::from_ticks(0)).await;
 
-            let rssi = loop {
-                if let Ok(rssi) = self.transceiver.get_rssi().await {
-                    break rssi;
-                }
-            };
+            let rssi = self.transceiver.get_rssi().await?;
             let noicefloor = &mut self.noise_floor[self.current_channel as usize];
+            let noise_floor = noicefloor.value();
             let mut token = if rssi > noicefloor.value() + self.min_snr as Rssi {
                 let token = {
                     match with_timeout(
@@ -106,7 +172,8 @@ where
                     )
                     .await
                     {
-                        Ok(token) => Some(token.unwrap()),
+                        Ok(Ok(token)) => Some(token),
+                        Ok(Err(error)) => return Err(error),
                         Err(TimeoutError) => None,
                     }
                 };
@@ -115,13 +182,13 @@ where
                     token
                 } else {
                     // Timeout
-                    self.set_next_channel().await.unwrap();
+                    self.set_next_channel().await?;
                     continue;
                 }
             } else {
                 noicefloor.add(rssi);
 
-                self.set_next_channel().await.unwrap();
+                self.set_next_channel().await?;
                 continue;
             };
 
@@ -129,9 +196,13 @@ where
             let mut frame = Frame {
                 timestamp: token.timestamp(),
                 rssi: Some(rssi),
+                channel: self.current_channel,
+                noise_floor: Some(noise_floor),
                 ..Default::default()
             };
 
+            let mut address_checked = false;
+
             loop {
                 let buffer = &mut frame.buffer[frame.received..];
                 let received = self.transceiver.read(&mut token, buffer).await;
@@ -141,33 +212,71 @@ where
 
                     if frame.len.is_none() {
                         match phl::get_frame_length(&frame.buffer[..frame.received]) {
-                            Ok(length) => {
-                                self.transceiver.accept(&mut token, length).await.unwrap();
+                            Ok(length) if length <= frame.buffer.len() => {
+                                self.transceiver.accept(&mut token, length).await?;
                                 frame.len = Some(length);
                             }
                             Err(ReadError::NotEnoughBytes) => {
                                 // We need more bytes to derive the frame length
                                 continue;
                             }
-                            Err(_) => {
-                                // Invalid frame length - wait for a new frame to be received
+                            _ => {
+                                // Invalid or too long frame length - wait for a new frame to be received
                                 break;
                             }
                         }
                     }
 
+                    if !address_checked
+                        && let Ok(block) = phl::peek_block(&frame.buffer[..frame.received])
+                        && let Ok(address) = mbal::peek_address_bytes(block)
+                    {
+                        address_checked = true;
+
+                        if !self.filter.accepts(&address) {
+                            // Unwanted meter - restart receiver
+                            self.transceiver.idle().await?;
+                            self.transceiver.listen().await?;
+                            break;
+                        }
+                    }
+
                     if let Some(frame_length) = frame.len
                         && frame.received >= frame_length
                     {
                         // Frame is fully received
                         yield frame;
-                        self.set_next_channel().await.unwrap();
+                        self.set_next_channel().await?;
+                        break;
+                    }
+
+                    if self.early_completion
+                        && let Ok(true) =
+                            phl::is_systematic_crc_valid(&frame.buffer[..frame.received])
+                    {
+                        // The parity is not needed - stop the reception and restart the receiver
+                        self.transceiver.idle().await?;
+                        self.set_next_channel().await?;
+                        self.transceiver.listen().await?;
+
+                        frame.parity_absent = true;
+                        yield frame;
                         break;
                     }
                 } else {
                     // Error during read - restart receiver
-                    self.transceiver.idle().await.unwrap();
-                    self.transceiver.listen().await.unwrap();
+                    self.transceiver.idle().await?;
+                    self.transceiver.listen().await?;
+
+                    if self.truncated_frames
+                        && frame.len.is_some()
+                        && let Ok(systematic_length) =
+                            phl::get_systematic_length(&frame.buffer[..frame.received])
+                        && frame.received >= systematic_length
+                    {
+                        // Only parity is missing - the frame may still be decoded
+                        yield frame;
+                    }
                     break;
                 }
             }
@@ -205,7 +314,13 @@ mod tests {
     use futures::{pin_mut, prelude::*};
     use mockall::{predicate::eq, Sequence};
 
-    use crate::ctrl::traits::{stubs::RxTokenStub, MockTransceiver};
+    use wmbus::WMBusAddress;
+
+    use crate::{
+        ctrl::traits::{stubs::RxTokenStub, MockTransceiver},
+        fec::CodeRate,
+        stack::{filter::Whitelist, mbal::MbalFunctionCode, Packet, PacketBuilder, Stack},
+    };
 
     use super::*;
 
@@ -320,6 +435,38 @@ mod tests {
         assert!(!ctrl.listening);
     }
 
+    #[tokio::test]
+    async fn can_end_receive_on_transceiver_error() {
+        // Given
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .withf(|_channel| true)
+            .return_const(Ok(()));
+        transceiver.expect_listen().return_const(Ok(()));
+        transceiver.expect_get_rssi().return_const(Ok(-100));
+        transceiver
+            .expect_receive()
+            .times(1)
+            .returning(|_min_frame_length| Err(()));
+        transceiver.expect_idle().return_const(Ok(()));
+
+        let mut ctrl = Controller::new(transceiver);
+
+        // When
+        let (received, next) = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+
+            (stream.next().await, stream.next().await)
+        };
+        ctrl.idle().await.unwrap();
+
+        // Then
+        assert!(matches!(received, Some(Err(()))));
+        assert!(next.is_none());
+    }
+
     #[tokio::test]
     async fn can_receive_frame() {
         // Given
@@ -361,8 +508,331 @@ mod tests {
         assert!(!ctrl.listening);
 
         // Then
-        let frame = received.unwrap();
+        let frame = received.unwrap().unwrap();
         assert_eq!(72, frame.len.unwrap());
         assert_eq!(80, frame.received);
     }
+
+    #[tokio::test]
+    async fn can_receive_truncated_frame() {
+        // Given
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .withf(|_channel| true)
+            .return_const(Ok(()));
+        transceiver.expect_listen().return_const(Ok(()));
+        transceiver.expect_get_rssi().return_const(Ok(-100));
+        transceiver
+            .expect_receive()
+            .times(1)
+            .returning(|_min_frame_length| Ok(RxTokenStub(Instant::now())));
+        let mut reads = 0;
+        transceiver
+            .expect_read()
+            .times(6)
+            .returning(move |_token, _buffer| {
+                reads += 1;
+                if reads <= 5 {
+                    Ok(10)
+                } else {
+                    Err(())
+                }
+            });
+        transceiver
+            .expect_accept()
+            .times(1)
+            .withf(|_token, length| *length == 72)
+            .return_const(Ok(()));
+        transceiver.expect_idle().return_const(Ok(()));
+
+        let mut ctrl = Controller::new(transceiver);
+        ctrl.set_truncated_frames(true);
+
+        // When
+        let received = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+
+            stream.next().await
+        };
+        ctrl.idle().await.unwrap();
+
+        // Then
+        let frame = received.unwrap().unwrap();
+        assert_eq!(72, frame.len());
+        assert!(frame.is_truncated());
+        assert_eq!(50, frame.bytes().len());
+    }
+
+    #[tokio::test]
+    async fn can_drop_truncated_frame_by_default() {
+        // Given
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .withf(|_channel| true)
+            .return_const(Ok(()));
+        transceiver.expect_listen().return_const(Ok(()));
+        transceiver.expect_get_rssi().return_const(Ok(-100));
+        transceiver
+            .expect_receive()
+            .returning(|_min_frame_length| Ok(RxTokenStub(Instant::now())));
+        let mut reads = 0;
+        transceiver.expect_read().returning(move |_token, _buffer| {
+            reads += 1;
+            if reads <= 5 {
+                Ok(10)
+            } else {
+                Err(())
+            }
+        });
+        transceiver
+            .expect_accept()
+            .times(1)
+            .withf(|_token, length| *length == 72)
+            .return_const(Ok(()));
+        transceiver.expect_idle().return_const(Ok(()));
+
+        let mut ctrl = Controller::new(transceiver);
+
+        // When
+        let received = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+
+            with_timeout(Duration::from_millis(50), stream.next()).await
+        };
+        ctrl.idle().await.unwrap();
+
+        // Then
+        assert!(received.is_err());
+    }
+
+    /// The header and the systematic block of example 4.1 in the specification
+    const SYSTEMATIC_FRAME: [u8; 65] = [
+        0xCC, 0x48, 0xDE, 0x49, 0x5C, 0xD1, 0x75, 0x12, 0x40, 0x2F, 0x09, 0x32, 0x01, 0x37, 0x2C,
+        0x34, 0x12, 0x34, 0x12, 0x1B, 0x16, 0x60, 0x16, 0x61, 0x7A, 0x01, 0x00, 0x20, 0x05, 0x19,
+        0x32, 0x29, 0xBC, 0xE6, 0x4D, 0x65, 0x1F, 0x1D, 0xED, 0x42, 0x68, 0x73, 0x03, 0xB2, 0x9A,
+        0xF6, 0xA6, 0x80, 0x53, 0x36, 0x08, 0x4A, 0x0C, 0xC4, 0xB4, 0xB9, 0x23, 0x71, 0xA3, 0xCA,
+        0xB9, 0xFC, 0x9B, 0x4F, 0xFE,
+    ];
+
+    #[tokio::test]
+    async fn can_complete_frame_early() {
+        // Given
+        let mut seq = Sequence::new();
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .with(eq(Channel::A))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(()));
+        transceiver
+            .expect_listen()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(()));
+        transceiver.expect_get_rssi().return_const(Ok(-100));
+        transceiver
+            .expect_receive()
+            .times(1)
+            .returning(|_min_frame_length| Ok(RxTokenStub(Instant::now())));
+        let mut offset = 0;
+        transceiver
+            .expect_read()
+            .times(5)
+            .returning(move |_token, buffer| {
+                buffer[..13].copy_from_slice(&SYSTEMATIC_FRAME[offset..offset + 13]);
+                offset += 13;
+                Ok(13)
+            });
+        transceiver
+            .expect_accept()
+            .times(1)
+            .withf(|_token, length| *length == 118)
+            .return_const(Ok(()));
+        transceiver
+            .expect_idle()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(()));
+        transceiver
+            .expect_set_channel()
+            .with(eq(Channel::B))
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(()));
+        transceiver
+            .expect_listen()
+            .times(1)
+            .in_sequence(&mut seq)
+            .return_const(Ok(()));
+
+        let mut ctrl = Controller::new(transceiver);
+        ctrl.set_early_completion(true);
+
+        // When
+        let received = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+
+            stream.next().await
+        };
+
+        // Then
+        let frame = received.unwrap().unwrap();
+        assert_eq!(118, frame.len());
+        assert!(frame.is_parity_absent());
+        assert_eq!(&SYSTEMATIC_FRAME, frame.bytes());
+    }
+
+    #[tokio::test]
+    async fn can_abort_filtered_frame() {
+        // Given
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .withf(|_channel| true)
+            .return_const(Ok(()));
+        transceiver.expect_listen().times(2).return_const(Ok(()));
+        let mut rssi = -100;
+        transceiver.expect_get_rssi().returning(move || {
+            // Only the first frame is above the noise floor
+            let current = rssi;
+            rssi = -120;
+            Ok(current)
+        });
+        transceiver
+            .expect_receive()
+            .times(1)
+            .returning(|_min_frame_length| Ok(RxTokenStub(Instant::now())));
+        let mut offset = 0;
+        transceiver
+            .expect_read()
+            .times(2)
+            .returning(move |_token, buffer| {
+                buffer[..13].copy_from_slice(&SYSTEMATIC_FRAME[offset..offset + 13]);
+                offset += 13;
+                Ok(13)
+            });
+        transceiver
+            .expect_accept()
+            .times(1)
+            .withf(|_token, length| *length == 118)
+            .return_const(Ok(()));
+        transceiver.expect_idle().times(2).return_const(Ok(()));
+
+        let addresses = [WMBusAddress::new(
+            0x2c2d.try_into().unwrap(),
+            71006389,
+            0x34,
+            0x04.try_into().unwrap(),
+        )];
+        let mut ctrl =
+            Controller::new(transceiver).with_address_filter(Whitelist::new(&addresses));
+
+        // When
+        let received = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+            let timeout = Timer::after(Duration::from_millis(50));
+            pin_mut!(timeout);
+
+            match future::select(stream.next(), timeout).await {
+                future::Either::Left((frame, _)) => frame,
+                future::Either::Right(_) => None,
+            }
+        };
+        ctrl.idle().await.unwrap();
+
+        // Then
+        assert!(received.is_none());
+    }
+
+    #[tokio::test]
+    async fn can_receive_longest_frame_for_mbal_max() {
+        // Given
+        let packet: Packet = PacketBuilder::new()
+            .code_rate(CodeRate::OneThird)
+            .address(WMBusAddress::new(
+                0x2c2d.try_into().unwrap(),
+                71006389,
+                0x34,
+                0x04.try_into().unwrap(),
+            ))
+            .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+            .mbus_data(&[0x7A; 32 - mbal::HEADER_SIZE])
+            .build()
+            .unwrap();
+        let mut frame = std::vec::Vec::new();
+        Stack::new().write(&mut frame, &packet).unwrap();
+        let length = frame.len();
+        assert_eq!(phl::max_frame_length(32), length);
+
+        let mut transceiver = MockTransceiver::new();
+        transceiver
+            .expect_set_channel()
+            .withf(|_channel| true)
+            .return_const(Ok(()));
+        transceiver.expect_listen().return_const(Ok(()));
+        transceiver.expect_get_rssi().return_const(Ok(-100));
+        transceiver
+            .expect_receive()
+            .times(1)
+            .returning(|_min_frame_length| Ok(RxTokenStub(Instant::now())));
+        let bytes = frame.clone();
+        transceiver
+            .expect_read()
+            .times(1)
+            .returning(move |_token, buffer| {
+                buffer[..length].copy_from_slice(&bytes);
+                Ok(length)
+            });
+        transceiver
+            .expect_accept()
+            .times(1)
+            .withf(move |_token, frame_length| *frame_length == length)
+            .return_const(Ok(()));
+        transceiver.expect_idle().return_const(Ok(()));
+
+        let mut ctrl =
+            Controller::<_, AcceptAll, { phl::max_frame_length(32) }>::new_sized(transceiver);
+
+        // When
+        let received = {
+            let stream = ctrl.receive().await.unwrap();
+            pin_mut!(stream);
+
+            stream.next().await
+        };
+        ctrl.idle().await.unwrap();
+
+        // Then
+        let received = received.unwrap().unwrap();
+        assert_eq!(length, received.len());
+        assert!(!received.is_truncated());
+        assert_eq!(frame.as_slice(), received.bytes());
+    }
+
+    #[test]
+    fn frame_size_follows_frame_length() {
+        // Given
+        fn overhead<const FRAME_LENGTH: usize>() -> usize {
+            core::mem::size_of::<Frame<FRAME_LENGTH>>() - FRAME_LENGTH
+        }
+        let bound = core::mem::size_of::<Frame<0>>() + core::mem::align_of::<Frame>();
+
+        // When
+        let overheads = [
+            overhead::<{ phl::max_frame_length(32) }>(),
+            overhead::<{ phl::max_frame_length(64) }>(),
+            overhead::<{ phl::max_frame_length(128) }>(),
+            overhead::<{ phl::MAX_FRAME_LENGTH }>(),
+        ];
+
+        // Then
+        assert!(overheads.iter().all(|&overhead| overhead < bound));
+    }
 }
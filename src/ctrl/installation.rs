@@ -0,0 +1,711 @@
+//! Installation of meters from their installation requests (SND-IR)
+
+use embassy_time::{Duration, Instant, Timer};
+use futures::{pin_mut, StreamExt};
+use heapless::Vec;
+use wmbus::WMBusAddress;
+
+use crate::stack::{
+    filter::AddressFilter, mbal::MbalFunctionCode, Channel, Layer, Rssi, RxMetadata, Stack,
+    WriteError, Writer,
+};
+
+use super::{traits, Controller};
+
+/// A meter that was installed from its installation request
+#[derive(Debug, Clone)]
+pub struct InstalledMeter {
+    pub address: WMBusAddress,
+    /// The channel the installation request was received on
+    pub channel: Channel,
+    /// The start of the installation request
+    pub timestamp: Instant,
+    pub rssi: Option<Rssi>,
+    /// The noise floor of the channel when the installation request was detected
+    pub noise_floor: Option<Rssi>,
+    /// The decoding of the installation request, e.g. the number of corrected bits
+    pub rx: RxMetadata,
+    /// Whether the installation request was confirmed within the response window
+    pub confirmed: bool,
+}
+
+impl InstalledMeter {
+    /// Get the signal to noise ratio of the installation request in dB
+    pub fn snr(&self) -> Option<Rssi> {
+        Some(self.rssi? - self.noise_floor?)
+    }
+}
+
+/// Registry of installed meters with room for `N` meters
+pub struct Registry<const N: usize> {
+    meters: Vec<InstalledMeter, N>,
+}
+
+impl<const N: usize> Registry<N> {
+    pub const fn new() -> Self {
+        Self { meters: Vec::new() }
+    }
+
+    /// Get an installed meter
+    pub fn get(&self, address: &WMBusAddress) -> Option<&InstalledMeter> {
+        self.meters.iter().find(|meter| &meter.address == address)
+    }
+
+    /// Get whether a meter can be inserted, either because it is already installed or because there is room for it
+    pub fn has_room_for(&self, address: &WMBusAddress) -> bool {
+        !self.meters.is_full() || self.get(address).is_some()
+    }
+
+    /// Insert a meter, or update it with its latest installation request if it is already installed.
+    /// The meter is given back if the registry is full.
+    pub fn insert(&mut self, meter: InstalledMeter) -> Result<&InstalledMeter, InstalledMeter> {
+        let index = match self
+            .meters
+            .iter()
+            .position(|installed| installed.address == meter.address)
+        {
+            Some(index) => {
+                self.meters[index] = meter;
+                index
+            }
+            None => {
+                self.meters.push(meter)?;
+                self.meters.len() - 1
+            }
+        };
+
+        Ok(&self.meters[index])
+    }
+
+    /// Remove an installed meter
+    pub fn remove(&mut self, address: &WMBusAddress) -> Option<InstalledMeter> {
+        let index = self
+            .meters
+            .iter()
+            .position(|meter| &meter.address == address)?;
+        Some(self.meters.swap_remove(index))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &InstalledMeter> {
+        self.meters.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.meters.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.meters.is_empty()
+    }
+}
+
+impl<const N: usize> Default for Registry<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Write the encoded frame that confirms the installation request of a meter.
+/// This is the confirmation type of the default configuration,
+/// and any `FnMut` with the same signature can be used, e.g. to capture keys or counters of the application.
+pub type WriteConfirmation = fn(&InstalledMeter, &mut dyn Writer) -> Result<(), WriteError>;
+
+/// Configuration of the installation mode
+pub struct InstallationConfig<C = WriteConfirmation> {
+    /// The confirmation of installation requests, which are not confirmed if `None`.
+    /// The stack has no function code for a confirmation, so the frame is written by the application.
+    pub confirmation: Option<C>,
+    /// The earliest start of the confirmation after the end of the installation request
+    pub min_response_delay: Duration,
+    /// The latest start of the confirmation after the end of the installation request.
+    /// The confirmation is skipped if it can not be transmitted in time.
+    pub max_response_delay: Duration,
+}
+
+impl<C> Default for InstallationConfig<C> {
+    fn default() -> Self {
+        Self {
+            confirmation: None,
+            min_response_delay: Duration::from_millis(5),
+            max_response_delay: Duration::from_millis(50),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InstallationError<E> {
+    Transceiver(E),
+    /// The registry has no room for the meter, which was not confirmed
+    RegistryFull(InstalledMeter),
+    /// The confirmation frame could not be written, e.g. because it is longer than the longest frame
+    Write(WriteError),
+}
+
+/// Gateway mode that installs meters from their installation requests.
+///
+/// Frames other than installation requests are ignored.
+/// Each installation request is recorded in the registry together with its link quality,
+/// and it is confirmed within the response window of the meter if a confirmation is configured.
+pub struct Installer<const N: usize, C = WriteConfirmation> {
+    pub config: InstallationConfig<C>,
+    registry: Registry<N>,
+}
+
+impl<const N: usize, C> Installer<N, C>
+where
+    C: FnMut(&InstalledMeter, &mut dyn Writer) -> Result<(), WriteError>,
+{
+    /// Create a new installer with an empty registry
+    pub const fn new(config: InstallationConfig<C>) -> Self {
+        Self {
+            config,
+            registry: Registry::new(),
+        }
+    }
+
+    pub fn registry(&self) -> &Registry<N> {
+        &self.registry
+    }
+
+    /// Get the registry, e.g. to remove meters or to restore previously installed meters
+    pub fn registry_mut(&mut self) -> &mut Registry<N> {
+        &mut self.registry
+    }
+
+    /// Listen until an installation request is received and install the meter.
+    /// The receiver is idle when the meter is returned, so an installation session is ended by not calling this again.
+    pub async fn install_next<
+        T,
+        F,
+        A,
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+        const FRAME_LENGTH: usize,
+    >(
+        &mut self,
+        controller: &mut Controller<T, F, FRAME_LENGTH>,
+        stack: &Stack<
+            A,
+            MBAL_MAX,
+            BLOCK_LENGTH,
+            BLOCK_BITS,
+            FIRST_TRELLIS_BITS,
+            SECOND_TRELLIS_BITS,
+            TRELLIS_BITS,
+            FRAME_BITS,
+            FRAME_LENGTH,
+        >,
+    ) -> Result<&InstalledMeter, InstallationError<T::Error>>
+    where
+        T: traits::Transceiver,
+        F: AddressFilter,
+        A: Layer<Extension = ()>,
+    {
+        let (mut meter, received_at) = loop {
+            let request = {
+                let stream = controller
+                    .receive()
+                    .await
+                    .map_err(InstallationError::Transceiver)?;
+                pin_mut!(stream);
+
+                let mut request = None;
+                while let Some(frame) = stream.next().await {
+                    let frame = frame.map_err(InstallationError::Transceiver)?;
+                    // The frame is yielded when it is fully received
+                    let received_at = Instant::now();
+
+                    if let Ok(packet) = stack.read(frame.bytes())
+                        && let Some(mbal) = packet.mbal
+                        && mbal.command.function_code == MbalFunctionCode::SendInstallationRequest
+                    {
+                        let meter = InstalledMeter {
+                            address: mbal.address,
+                            channel: frame.channel,
+                            timestamp: frame.timestamp,
+                            rssi: frame.rssi,
+                            noise_floor: frame.noise_floor,
+                            rx: packet.rx.unwrap_or_default(),
+                            confirmed: false,
+                        };
+                        request = Some((meter, received_at));
+                        break;
+                    }
+                }
+                request
+            };
+            controller
+                .idle()
+                .await
+                .map_err(InstallationError::Transceiver)?;
+
+            if let Some(request) = request {
+                break request;
+            }
+        };
+
+        if !self.registry.has_room_for(&meter.address) {
+            return Err(InstallationError::RegistryFull(meter));
+        }
+
+        meter.confirmed = self.confirm(controller, &meter, received_at).await?;
+
+        self.registry
+            .insert(meter)
+            .map_err(InstallationError::RegistryFull)
+    }
+
+    /// Transmit a confirmation to a meter within its response window.
+    /// Returns whether the confirmation was transmitted, which it is not if no confirmation is configured.
+    async fn confirm<T, F, const FRAME_LENGTH: usize>(
+        &mut self,
+        controller: &mut Controller<T, F, FRAME_LENGTH>,
+        meter: &InstalledMeter,
+        received_at: Instant,
+    ) -> Result<bool, InstallationError<T::Error>>
+    where
+        T: traits::Transceiver,
+        F: AddressFilter,
+    {
+        let Some(confirmation) = self.config.confirmation.as_mut() else {
+            return Ok(false);
+        };
+
+        let mut frame = Vec::<u8, FRAME_LENGTH>::new();
+        confirmation(meter, &mut frame).map_err(InstallationError::Write)?;
+
+        Timer::at(received_at + self.config.min_response_delay).await;
+        if Instant::now() > received_at + self.config.max_response_delay {
+            // The meter no longer listens for the confirmation
+            return Ok(false);
+        }
+
+        controller
+            .write(&frame)
+            .await
+            .map_err(InstallationError::Transceiver)?;
+        controller
+            .transmit(meter.channel)
+            .await
+            .map_err(InstallationError::Transceiver)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use crate::{
+        ctrl::traits::{stubs::RxTokenStub, Transceiver},
+        fec::CodeRate,
+        stack::{phl, Packet, PacketBuilder},
+    };
+
+    use super::*;
+
+    /// Meters that send their frames to the gateway, and a record of the frames transmitted by the gateway
+    struct SimulatedMeters {
+        frames: VecDeque<(Channel, std::vec::Vec<u8>)>,
+        channel: Channel,
+        receiving: Option<std::vec::Vec<u8>>,
+        written: std::vec::Vec<u8>,
+        transmitted: std::vec::Vec<(Channel, std::vec::Vec<u8>)>,
+    }
+
+    impl SimulatedMeters {
+        fn new(frames: impl IntoIterator<Item = (Channel, std::vec::Vec<u8>)>) -> Self {
+            Self {
+                frames: frames.into_iter().collect(),
+                channel: Channel::A,
+                receiving: None,
+                written: std::vec::Vec::new(),
+                transmitted: std::vec::Vec::new(),
+            }
+        }
+    }
+
+    impl Transceiver for SimulatedMeters {
+        type RxToken = RxTokenStub;
+        type Error = ();
+
+        async fn init(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn set_channel(&mut self, channel: Channel) -> Result<(), Self::Error> {
+            self.channel = channel;
+            Ok(())
+        }
+
+        async fn write(&mut self, buffer: &[u8]) -> Result<(), Self::Error> {
+            self.written.extend_from_slice(buffer);
+            Ok(())
+        }
+
+        async fn transmit(&mut self) -> Result<(), Self::Error> {
+            let frame = core::mem::take(&mut self.written);
+            self.transmitted.push((self.channel, frame));
+            Ok(())
+        }
+
+        async fn listen(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn get_rssi(&mut self) -> Result<Rssi, Self::Error> {
+            match self.frames.front() {
+                Some((channel, _)) if *channel == self.channel => Ok(-80),
+                _ => Ok(-120),
+            }
+        }
+
+        async fn receive(
+            &mut self,
+            _min_frame_length: usize,
+        ) -> Result<Self::RxToken, Self::Error> {
+            let (_, frame) = self.frames.pop_front().unwrap();
+            self.receiving = Some(frame);
+            Ok(RxTokenStub(Instant::now()))
+        }
+
+        async fn read<'a>(
+            &'a mut self,
+            _token: &mut Self::RxToken,
+            buffer: &mut [u8],
+        ) -> Result<usize, Self::Error> {
+            let frame = self.receiving.take().ok_or(())?;
+            buffer[..frame.len()].copy_from_slice(&frame);
+            Ok(frame.len())
+        }
+
+        async fn accept(
+            &mut self,
+            _token: &mut Self::RxToken,
+            _frame_length: usize,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn idle(&mut self) -> Result<(), Self::Error> {
+            self.receiving = None;
+            Ok(())
+        }
+    }
+
+    fn address(id: u32) -> WMBusAddress {
+        WMBusAddress::new(
+            0x2c2d.try_into().unwrap(),
+            id,
+            0x34,
+            0x04.try_into().unwrap(),
+        )
+    }
+
+    fn frame(id: u32, function_code: MbalFunctionCode) -> std::vec::Vec<u8> {
+        let packet: Packet = PacketBuilder::new()
+            .code_rate(CodeRate::OneHalf)
+            .address(address(id))
+            .function_code(function_code)
+            .mbus_data(&[0x7A, 0x01, 0x00, 0x20])
+            .build()
+            .unwrap();
+        let mut frame = std::vec::Vec::new();
+        Stack::new().write(&mut frame, &packet).unwrap();
+        frame
+    }
+
+    /// A confirmation of the application, which is the address of the meter
+    fn write_confirmation(
+        meter: &InstalledMeter,
+        writer: &mut dyn Writer,
+    ) -> Result<(), WriteError> {
+        writer.write(meter.address.get_bytes().as_slice())
+    }
+
+    fn write_longest_confirmation(
+        _meter: &InstalledMeter,
+        writer: &mut dyn Writer,
+    ) -> Result<(), WriteError> {
+        writer.write(&[0x55; phl::MAX_FRAME_LENGTH])
+    }
+
+    fn write_too_long_confirmation(
+        _meter: &InstalledMeter,
+        writer: &mut dyn Writer,
+    ) -> Result<(), WriteError> {
+        writer.write(&[0x55; phl::MAX_FRAME_LENGTH + 1])
+    }
+
+    fn confirming_with<C>(confirmation: C) -> InstallationConfig<C>
+    where
+        C: FnMut(&InstalledMeter, &mut dyn Writer) -> Result<(), WriteError>,
+    {
+        InstallationConfig {
+            confirmation: Some(confirmation),
+            ..Default::default()
+        }
+    }
+
+    fn confirming() -> InstallationConfig {
+        InstallationConfig {
+            confirmation: Some(write_confirmation),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn can_install_meter() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([
+            (
+                Channel::B,
+                frame(71006389, MbalFunctionCode::SendUnsolicitedApplicationData),
+            ),
+            (
+                Channel::C,
+                frame(12341234, MbalFunctionCode::SendInstallationRequest),
+            ),
+        ]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<4>::new(confirming());
+
+        // When
+        let meter = installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap()
+            .clone();
+
+        // Then
+        assert_eq!(address(12341234), meter.address);
+        assert_eq!(Channel::C, meter.channel);
+        assert_eq!(Some(-80), meter.rssi);
+        assert_eq!(Some(30), meter.snr());
+        assert_eq!(0, meter.rx.decode_distance);
+        assert!(meter.confirmed);
+        assert_eq!(1, installer.registry().len());
+        assert!(installer.registry().get(&address(71006389)).is_none());
+
+        let transmitted = controller.release().transmitted;
+        assert_eq!(1, transmitted.len());
+        assert_eq!(Channel::C, transmitted[0].0);
+        assert_eq!(address(12341234).get_bytes().as_slice(), transmitted[0].1);
+    }
+
+    #[tokio::test]
+    async fn can_confirm_with_longest_frame() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([(
+            Channel::A,
+            frame(12341234, MbalFunctionCode::SendInstallationRequest),
+        )]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<4>::new(InstallationConfig {
+            confirmation: Some(write_longest_confirmation),
+            ..Default::default()
+        });
+
+        // When
+        let meter = installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap();
+
+        // Then
+        assert!(meter.confirmed);
+        let transmitted = controller.release().transmitted;
+        assert_eq!(phl::MAX_FRAME_LENGTH, transmitted[0].1.len());
+    }
+
+    #[tokio::test]
+    async fn can_confirm_with_application_state() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([
+            (
+                Channel::A,
+                frame(12341234, MbalFunctionCode::SendInstallationRequest),
+            ),
+            (
+                Channel::B,
+                frame(71006389, MbalFunctionCode::SendInstallationRequest),
+            ),
+        ]);
+        let mut controller = Controller::new(meters);
+        let mut sequence_number = 0u8;
+        let mut installer = Installer::<4, _>::new(confirming_with(|_meter, writer| {
+            sequence_number += 1;
+            writer.write(&[sequence_number])
+        }));
+
+        // When
+        for _ in 0..2 {
+            installer
+                .install_next(&mut controller, &stack)
+                .await
+                .unwrap();
+        }
+
+        // Then
+        assert_eq!(2, sequence_number);
+        let transmitted = controller.release().transmitted;
+        assert_eq!(vec![1], transmitted[0].1);
+        assert_eq!(vec![2], transmitted[1].1);
+    }
+
+    #[tokio::test]
+    async fn can_not_confirm_with_too_long_frame() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([(
+            Channel::A,
+            frame(12341234, MbalFunctionCode::SendInstallationRequest),
+        )]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<4>::new(InstallationConfig {
+            confirmation: Some(write_too_long_confirmation),
+            ..Default::default()
+        });
+
+        // When
+        let result = installer.install_next(&mut controller, &stack).await;
+
+        // Then
+        assert!(matches!(
+            result,
+            Err(InstallationError::Write(WriteError::Capacity))
+        ));
+        assert!(installer.registry().is_empty());
+        assert!(controller.release().transmitted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_update_installed_meter_without_confirmation() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([
+            (
+                Channel::A,
+                frame(12341234, MbalFunctionCode::SendInstallationRequest),
+            ),
+            (
+                Channel::D,
+                frame(12341234, MbalFunctionCode::SendInstallationRequest),
+            ),
+        ]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<4>::new(InstallationConfig::default());
+
+        // When
+        installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap();
+        installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap();
+
+        // Then
+        assert_eq!(1, installer.registry().len());
+        let meter = installer.registry().get(&address(12341234)).unwrap();
+        assert_eq!(Channel::D, meter.channel);
+        assert!(!meter.confirmed);
+        assert!(controller.release().transmitted.is_empty());
+    }
+
+    #[tokio::test]
+    async fn can_not_install_when_registry_is_full() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([
+            (
+                Channel::A,
+                frame(12341234, MbalFunctionCode::SendInstallationRequest),
+            ),
+            (
+                Channel::B,
+                frame(71006389, MbalFunctionCode::SendInstallationRequest),
+            ),
+        ]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<1>::new(confirming());
+        installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap();
+
+        // When
+        let result = installer.install_next(&mut controller, &stack).await;
+
+        // Then
+        assert!(
+            matches!(result, Err(InstallationError::RegistryFull(meter)) if meter.address == address(71006389))
+        );
+        assert_eq!(1, installer.registry().len());
+        assert_eq!(1, controller.release().transmitted.len());
+    }
+
+    #[tokio::test]
+    async fn can_skip_confirmation_after_response_window() {
+        // Given
+        let stack = Stack::new();
+        let meters = SimulatedMeters::new([(
+            Channel::A,
+            frame(12341234, MbalFunctionCode::SendInstallationRequest),
+        )]);
+        let mut controller = Controller::new(meters);
+        let mut installer = Installer::<4>::new(InstallationConfig {
+            min_response_delay: Duration::from_millis(2),
+            max_response_delay: Duration::from_millis(1),
+            ..confirming()
+        });
+
+        // When
+        let meter = installer
+            .install_next(&mut controller, &stack)
+            .await
+            .unwrap();
+
+        // Then
+        assert!(!meter.confirmed);
+        assert!(controller.release().transmitted.is_empty());
+    }
+
+    #[test]
+    fn can_remove_installed_meter() {
+        // Given
+        let mut registry = Registry::<2>::new();
+        for id in [12341234, 71006389] {
+            registry
+                .insert(InstalledMeter {
+                    address: address(id),
+                    channel: Channel::A,
+                    timestamp: Instant::from_ticks(0),
+                    rssi: None,
+                    noise_floor: None,
+                    rx: RxMetadata::default(),
+                    confirmed: false,
+                })
+                .unwrap();
+        }
+
+        // When
+        let removed = registry.remove(&address(12341234));
+
+        // Then
+        assert_eq!(address(12341234), removed.unwrap().address);
+        assert_eq!(1, registry.len());
+        assert!(registry.has_room_for(&address(1)));
+        assert!(registry.get(&address(71006389)).is_some());
+    }
+}
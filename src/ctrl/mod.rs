@@ -1,39 +1,65 @@
 mod controller;
+pub mod installation;
 mod noicefloor;
 pub mod traits;
 
 pub use controller::Controller;
 use embassy_time::Instant;
 
-use crate::stack::{phl, Rssi};
+use crate::stack::{phl, Channel, Rssi};
 
-pub struct Frame {
+/// A received frame with room for `FRAME_LENGTH` bytes.
+/// The length for frames with at most `MBAL_MAX` MBAL bytes is [`phl::max_frame_length`]`(MBAL_MAX)`.
+pub struct Frame<const FRAME_LENGTH: usize = { phl::MAX_FRAME_LENGTH }> {
     pub timestamp: Instant,
     pub rssi: Option<Rssi>,
-    buffer: [u8; phl::MAX_FRAME_LENGTH],
+    /// The channel the frame was received on
+    pub channel: Channel,
+    /// The noise floor of the channel when the frame was detected
+    pub noise_floor: Option<Rssi>,
+    buffer: [u8; FRAME_LENGTH],
     received: usize,
     len: Option<usize>,
+    parity_absent: bool,
 }
 
-impl Default for Frame {
+impl<const FRAME_LENGTH: usize> Default for Frame<FRAME_LENGTH> {
     fn default() -> Self {
         Self {
             timestamp: Instant::now(),
             rssi: None,
-            buffer: [0; phl::MAX_FRAME_LENGTH],
+            channel: Channel::A,
+            noise_floor: None,
+            buffer: [0; FRAME_LENGTH],
             received: 0,
             len: None,
+            parity_absent: false,
         }
     }
 }
 
 #[allow(clippy::len_without_is_empty)]
-impl Frame {
+impl<const FRAME_LENGTH: usize> Frame<FRAME_LENGTH> {
+    /// Get the frame length given by the header.
+    /// This is longer than the received bytes if the frame is truncated or its parity is absent.
     pub fn len(&self) -> usize {
         self.len.unwrap()
     }
 
+    /// Get the received frame bytes.
+    /// This is shorter than the frame length if the frame is truncated.
     pub fn bytes(&self) -> &[u8] {
-        &self.buffer[0..self.len.unwrap()]
+        &self.buffer[0..self.len.unwrap().min(self.received)]
+    }
+
+    /// Get whether the reception stopped before all parity bytes were received
+    pub fn is_truncated(&self) -> bool {
+        self.received < self.len.unwrap()
+    }
+
+    /// Get whether the reception was ended early because the systematic block passed the CRC.
+    /// The parity is then absent from the frame.
+    pub fn is_parity_absent(&self) -> bool {
+        self.parity_absent
     }
 }
@@ -23,4 +23,14 @@ impl EncoderTermination {
             BcjrSymbol::new(((v & 0x08) != 0).mul(snr), ((v & 0x01) != 0).mul(snr)),
         ]
     }
+
+    /// Get the termination symbols from the six soft bits in the order they are transmitted
+    pub(crate) fn get_soft_symbols(llrs: &[Llr]) -> [BcjrSymbol; 3] {
+        assert_eq!(6, llrs.len());
+        [
+            BcjrSymbol::new(llrs[0], llrs[3]),
+            BcjrSymbol::new(llrs[1], llrs[4]),
+            BcjrSymbol::new(llrs[2], llrs[5]),
+        ]
+    }
 }
@@ -3,6 +3,8 @@ mod turbodecoderinput;
 mod turboencoderoutput;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum CodeRate {
     OneThird,
     OneHalf,
@@ -17,6 +19,8 @@ impl From<CodeRate> for fastfec::CodeRate {
     }
 }
 
+pub use fastfec::Llr;
+
 pub(crate) use encodertermination::EncoderTermination;
 pub(crate) use turbodecoderinput::TurboDecoderInput;
 pub(crate) use turboencoderoutput::TurboEncoderOutput;
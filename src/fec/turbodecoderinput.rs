@@ -22,6 +22,54 @@ impl<const N: usize> TurboDecoderInput<N> {
         second_termination: EncoderTermination,
         snr: Llr,
     ) -> Self {
+        let mut systematic_reader = BitReader::<u8, Msb0>::from_slice(block);
+        let mut parity_reader = BitReader::<u8, Msb0>::from_slice(parity);
+
+        Self {
+            symbols: Self::read_symbols(
+                rate,
+                8 * block.len(),
+                || systematic_reader.read_bit().unwrap().mul(snr),
+                // Parity bits missing from a truncated frame are erasures
+                || parity_reader.read_bit().map_or(0, |bit| bit.mul(snr)),
+            ),
+            first_termination: first_termination.get_symbols(snr),
+            second_termination: second_termination.get_symbols(snr),
+        }
+    }
+
+    /// Create the decoder input from soft bits.
+    /// The systematic and parity bits are given in the order they are transmitted,
+    /// and a positive llr value is in favor of a one bit.
+    /// An erased bit has the llr value zero.
+    pub fn from_llrs(
+        rate: CodeRate,
+        systematic: &[Llr],
+        parity: &[Llr],
+        first_termination: &[Llr],
+        second_termination: &[Llr],
+    ) -> Self {
+        let mut systematic_iter = systematic.iter();
+        let mut parity_iter = parity.iter();
+
+        Self {
+            symbols: Self::read_symbols(
+                rate,
+                systematic.len(),
+                || *systematic_iter.next().unwrap(),
+                || *parity_iter.next().unwrap(),
+            ),
+            first_termination: EncoderTermination::get_soft_symbols(first_termination),
+            second_termination: EncoderTermination::get_soft_symbols(second_termination),
+        }
+    }
+
+    fn read_symbols(
+        rate: CodeRate,
+        block_bits: usize,
+        mut next_systematic: impl FnMut() -> Llr,
+        mut next_parity: impl FnMut() -> Llr,
+    ) -> Vec<TurboSymbol, N> {
         // Get the encoder puncturers for the given code rate
         let (mut first_puncturer, mut second_puncturer) = if rate == CodeRate::OneThird {
             (Puncturer::default(), Puncturer::default())
@@ -30,14 +78,12 @@ impl<const N: usize> TurboDecoderInput<N> {
         };
 
         let mut symbols = Vec::new();
-        let mut systematic_reader = BitReader::<u8, Msb0>::from_slice(block);
-        let mut parity_reader = BitReader::<u8, Msb0>::from_slice(parity);
 
         // Read systematic and first encoder parity
-        for _ in 0..8 * block.len() {
-            let systematic = systematic_reader.read_bit().unwrap().mul(snr);
+        for _ in 0..block_bits {
+            let systematic = next_systematic();
             let first_parity = if first_puncturer.read_output() {
-                parity_reader.read_bit().unwrap().mul(snr)
+                next_parity()
             } else {
                 0
             };
@@ -48,16 +94,12 @@ impl<const N: usize> TurboDecoderInput<N> {
         }
 
         // Read second encoder parity
-        for symbol in symbols.iter_mut().take(8 * block.len()) {
+        for symbol in symbols.iter_mut().take(block_bits) {
             if second_puncturer.read_output() {
-                symbol.second_parity = parity_reader.read_bit().unwrap().mul(snr);
+                symbol.second_parity = next_parity();
             }
         }
 
-        Self {
-            symbols,
-            first_termination: first_termination.get_symbols(snr),
-            second_termination: second_termination.get_symbols(snr),
-        }
+        symbols
     }
 }
@@ -3,38 +3,37 @@ use fastfec::{
 };
 
 use super::{CodeRate, EncoderTermination};
-use bitvec::prelude::*;
+use crate::{bitbuffer::BitBuffer, stack::mbal};
+
+const MAX_BLOCK: usize = mbal::MBAL_MAX + 4;
 
 pub(crate) struct TurboEncoderOutput {
     first_puncturer: Puncturer,
     second_puncturer: Puncturer,
-    systematic: BitVec<u8, Msb0>,
-    first_parity: BitVec<u8, Msb0>,
-    second_parity: BitVec<u8>,
+    systematic: BitBuffer<MAX_BLOCK>,
+    first_parity: BitBuffer<{ 2 * MAX_BLOCK }>,
+    second_parity: BitBuffer<MAX_BLOCK>,
     first_termination: EncoderTermination,
     second_termination: EncoderTermination,
     written: usize,
 }
 
-pub struct EncodeResult {
-    pub systematic: BitVec<u8, Msb0>,
-    pub parity: BitVec<u8, Msb0>,
+pub(crate) struct EncodeResult {
+    pub systematic: BitBuffer<MAX_BLOCK>,
+    pub parity: BitBuffer<{ 2 * MAX_BLOCK }>,
     first_termination: EncoderTermination,
     second_termination: EncoderTermination,
 }
 
 impl TurboEncoderOutput {
     pub fn new(rate: CodeRate, block_size: usize) -> Self {
+        assert!(block_size <= 8 * MAX_BLOCK);
+
         let (first_puncturer, second_puncturer) = match rate {
             CodeRate::OneThird => (Puncturer::default(), Puncturer::default()),
             CodeRate::OneHalf => (Puncturer::new(2, 0b10), Puncturer::new(2, 0b01)),
         };
 
-        let parity_bits = match rate {
-            CodeRate::OneThird => block_size,
-            CodeRate::OneHalf => block_size / 2,
-        };
-
         // The written parity bits for the second encoder is never exposed
         // but appended to the first parity writer - this is the reason why
         // no specific bit order is specified for that writer. Basically
@@ -43,9 +42,9 @@ impl TurboEncoderOutput {
         Self {
             first_puncturer,
             second_puncturer,
-            systematic: BitVec::with_capacity(block_size),
-            first_parity: BitVec::with_capacity(2 * parity_bits),
-            second_parity: BitVec::with_capacity(parity_bits),
+            systematic: BitBuffer::new(),
+            first_parity: BitBuffer::new(),
+            second_parity: BitBuffer::new(),
             first_termination: EncoderTermination::default(),
             second_termination: EncoderTermination::default(),
             written: 0,
@@ -56,7 +55,7 @@ impl TurboEncoderOutput {
         assert_eq!(0, (self.first_parity.len() + self.second_parity.len()) % 8);
 
         if self.second_parity.any() {
-            for parity in self.second_parity {
+            for parity in self.second_parity.iter() {
                 self.first_parity.push(parity);
             }
         }
@@ -0,0 +1,375 @@
+//! C interface to the protocol stack.
+//!
+//! The functions are declared in `include/linkiq.h`, which is generated with
+//! `cbindgen --config cbindgen.toml --output include/linkiq.h`.
+//! Build the library for C with `cargo rustc --lib --release --features ffi --crate-type staticlib`
+//! or `--crate-type cdylib`.
+//!
+//! All functions return [`LINKIQ_OK`] on success or a negative status code on failure.
+
+use core::{ffi::c_char, ptr, slice};
+
+use num_traits::FromPrimitive;
+use wmbus::WMBusAddress;
+
+use crate::{
+    fec::CodeRate,
+    stack::{
+        filter::ADDRESS_SIZE,
+        mbal::{self, MbalFunctionCode},
+        phl, Packet, PacketBuilder, ReadError, Stack, WriteError, Writer,
+    },
+};
+
+pub const LINKIQ_OK: i32 = 0;
+/// A pointer was null or a packet field has an invalid value
+pub const LINKIQ_ERROR_INVALID_ARGUMENT: i32 = -1;
+pub const LINKIQ_ERROR_CAPACITY: i32 = -2;
+pub const LINKIQ_ERROR_NOT_ENOUGH_BYTES: i32 = -3;
+pub const LINKIQ_ERROR_PHL_DECODE: i32 = -4;
+pub const LINKIQ_ERROR_RECEPTION_MISMATCH: i32 = -5;
+pub const LINKIQ_ERROR_MBAL_CRC: i32 = -6;
+pub const LINKIQ_ERROR_MBAL_CONTROL: i32 = -7;
+pub const LINKIQ_ERROR_MBAL_ADDRESS: i32 = -8;
+pub const LINKIQ_ERROR_MBAL_COMMAND: i32 = -9;
+pub const LINKIQ_ERROR_FILTERED: i32 = -10;
+pub const LINKIQ_ERROR_WMBUS_LENGTH: i32 = -11;
+pub const LINKIQ_ERROR_WMBUS_CRC: i32 = -12;
+
+pub const LINKIQ_CODE_RATE_ONE_THIRD: u8 = 0;
+pub const LINKIQ_CODE_RATE_ONE_HALF: u8 = 1;
+
+/// SND-NR
+pub const LINKIQ_FUNCTION_CODE_SND_NR: u8 = 4;
+/// SND-IR
+pub const LINKIQ_FUNCTION_CODE_SND_IR: u8 = 6;
+
+/// The maximum length of an encoded frame.
+/// cbindgen can not evaluate the derived length, so the header defines it in `cbindgen.toml`.
+/// cbindgen:ignore
+pub const LINKIQ_MAX_FRAME_LENGTH: usize = phl::MAX_FRAME_LENGTH;
+// The header, the MBAL block with its CRC, and the parity of a rate 1/3 frame
+const _: () = assert!(LINKIQ_MAX_FRAME_LENGTH == phl::HEADER_SIZE + 3 * (mbal::MBAL_MAX + 4));
+
+/// An opaque protocol stack
+pub struct LinkiqStack(Stack);
+
+/// A packet in a C friendly representation
+#[repr(C)]
+pub struct LinkiqPacket {
+    /// One of the `LINKIQ_CODE_RATE_` constants
+    pub code_rate: u8,
+    pub is_prioritized: bool,
+    /// The address bytes in the order they are transmitted
+    pub address: [u8; ADDRESS_SIZE],
+    /// One of the `LINKIQ_FUNCTION_CODE_` constants
+    pub function_code: u8,
+    /// The M-Bus data
+    pub payload: *const u8,
+    pub payload_len: usize,
+    /// The reception statistics, which are ignored when encoding
+    pub header_distance: u32,
+    pub decode_iterations: u32,
+    pub decode_distance: u32,
+    pub erased_bits: u32,
+    pub bit_flips: u32,
+}
+
+fn read_status(error: ReadError) -> i32 {
+    match error {
+        ReadError::Capacity => LINKIQ_ERROR_CAPACITY,
+        ReadError::NotEnoughBytes => LINKIQ_ERROR_NOT_ENOUGH_BYTES,
+        ReadError::PhlDecodeError => LINKIQ_ERROR_PHL_DECODE,
+        ReadError::ReceptionMismatch => LINKIQ_ERROR_RECEPTION_MISMATCH,
+        ReadError::MBalCrcError => LINKIQ_ERROR_MBAL_CRC,
+        ReadError::MBalControlError => LINKIQ_ERROR_MBAL_CONTROL,
+        ReadError::MBalAddressError => LINKIQ_ERROR_MBAL_ADDRESS,
+        ReadError::MBalCommandError => LINKIQ_ERROR_MBAL_COMMAND,
+        ReadError::Filtered => LINKIQ_ERROR_FILTERED,
+        ReadError::WMBusLengthError => LINKIQ_ERROR_WMBUS_LENGTH,
+        ReadError::WMBusCrcError => LINKIQ_ERROR_WMBUS_CRC,
+    }
+}
+
+fn write_status(error: WriteError) -> i32 {
+    match error {
+        WriteError::Capacity => LINKIQ_ERROR_CAPACITY,
+    }
+}
+
+/// Writer into a buffer given by the caller
+struct SliceWriter<'a> {
+    buffer: &'a mut [u8],
+    length: usize,
+}
+
+impl Writer for SliceWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> Result<(), WriteError> {
+        let end = self.length + buf.len();
+        self.buffer
+            .get_mut(self.length..end)
+            .ok_or(WriteError::Capacity)?
+            .copy_from_slice(buf);
+        self.length = end;
+        Ok(())
+    }
+}
+
+/// Create a stack that must be freed with [`linkiq_stack_free`]
+#[no_mangle]
+pub extern "C" fn linkiq_stack_new() -> *mut LinkiqStack {
+    Box::into_raw(Box::new(LinkiqStack(Stack::new())))
+}
+
+/// Free a stack
+///
+/// # Safety
+/// The stack must be null or created by [`linkiq_stack_new`] and not already freed.
+#[no_mangle]
+pub unsafe extern "C" fn linkiq_stack_free(stack: *mut LinkiqStack) {
+    if !stack.is_null() {
+        drop(unsafe { Box::from_raw(stack) });
+    }
+}
+
+/// Set the maximum number of turbo decoder iterations
+///
+/// # Safety
+/// The stack must be created by [`linkiq_stack_new`].
+#[no_mangle]
+pub unsafe extern "C" fn linkiq_stack_set_max_iterations(
+    stack: *mut LinkiqStack,
+    max_iterations: u32,
+) -> i32 {
+    let Some(stack) = (unsafe { stack.as_mut() }) else {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    };
+
+    stack.0.phl_mut().max_decode_iterations = max_iterations as usize;
+    LINKIQ_OK
+}
+
+/// Get the length of a frame from its first bytes
+///
+/// # Safety
+/// The buffer must hold `length` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn linkiq_frame_length(
+    buffer: *const u8,
+    length: usize,
+    frame_length: *mut usize,
+) -> i32 {
+    if buffer.is_null() || frame_length.is_null() {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    }
+
+    let buffer = unsafe { slice::from_raw_parts(buffer, length) };
+    match phl::get_frame_length(buffer) {
+        Ok(length) => {
+            unsafe { *frame_length = length };
+            LINKIQ_OK
+        }
+        Err(error) => read_status(error),
+    }
+}
+
+/// Decode a frame in place.
+/// The payload of the packet points into the frame buffer, which is corrected if decoding was needed.
+///
+/// # Safety
+/// The stack must be created by [`linkiq_stack_new`], the frame must hold `length` bytes,
+/// and the packet must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn linkiq_stack_decode(
+    stack: *const LinkiqStack,
+    frame: *mut u8,
+    length: usize,
+    packet: *mut LinkiqPacket,
+) -> i32 {
+    let Some(stack) = (unsafe { stack.as_ref() }) else {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    };
+    if frame.is_null() || packet.is_null() {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    }
+
+    let frame = unsafe { slice::from_raw_parts_mut(frame, length) };
+    let read = match stack.0.read_ref(frame) {
+        Ok(read) => read,
+        Err(error) => return read_status(error),
+    };
+
+    let decoded = LinkiqPacket {
+        code_rate: match read.phl.code_rate {
+            CodeRate::OneThird => LINKIQ_CODE_RATE_ONE_THIRD,
+            CodeRate::OneHalf => LINKIQ_CODE_RATE_ONE_HALF,
+        },
+        is_prioritized: read.mbal.control.is_prioritized,
+        address: read.mbal.address.get_bytes().as_slice().try_into().unwrap(),
+        function_code: read.mbal.command.function_code as u8,
+        payload: read.mbus_data.as_ptr(),
+        payload_len: read.mbus_data.len(),
+        header_distance: read.rx.header_distance as u32,
+        decode_iterations: read.rx.decode_iterations as u32,
+        decode_distance: read.rx.decode_distance as u32,
+        erased_bits: read.rx.erased_bits as u32,
+        bit_flips: read.rx.bit_flips as u32,
+    };
+    unsafe { ptr::write(packet, decoded) };
+
+    LINKIQ_OK
+}
+
+/// Encode a packet into a buffer with room for `capacity` bytes
+///
+/// # Safety
+/// The stack must be created by [`linkiq_stack_new`], the payload of the packet must hold `payload_len` bytes,
+/// the buffer must have room for `capacity` bytes, and `length` must point to writable memory.
+#[no_mangle]
+pub unsafe extern "C" fn linkiq_stack_encode(
+    stack: *const LinkiqStack,
+    packet: *const LinkiqPacket,
+    buffer: *mut u8,
+    capacity: usize,
+    length: *mut usize,
+) -> i32 {
+    let (Some(stack), Some(packet)) = (unsafe { stack.as_ref() }, unsafe { packet.as_ref() })
+    else {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    };
+    if buffer.is_null() || length.is_null() || (packet.payload.is_null() && packet.payload_len > 0)
+    {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    }
+
+    let code_rate = match packet.code_rate {
+        LINKIQ_CODE_RATE_ONE_THIRD => CodeRate::OneThird,
+        LINKIQ_CODE_RATE_ONE_HALF => CodeRate::OneHalf,
+        _ => return LINKIQ_ERROR_INVALID_ARGUMENT,
+    };
+    let Some(function_code) = MbalFunctionCode::from_u8(packet.function_code) else {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    };
+    let Ok(address) = WMBusAddress::from_bytes(packet.address.as_slice().try_into().unwrap())
+    else {
+        return LINKIQ_ERROR_INVALID_ARGUMENT;
+    };
+    let payload = match packet.payload_len {
+        0 => &[][..],
+        len => unsafe { slice::from_raw_parts(packet.payload, len) },
+    };
+
+    let built: Result<Packet, WriteError> = PacketBuilder::new()
+        .code_rate(code_rate)
+        .prioritized(packet.is_prioritized)
+        .address(address)
+        .function_code(function_code)
+        .mbus_data(payload)
+        .build();
+    let built = match built {
+        Ok(built) => built,
+        Err(error) => return write_status(error),
+    };
+
+    let mut writer = SliceWriter {
+        buffer: unsafe { slice::from_raw_parts_mut(buffer, capacity) },
+        length: 0,
+    };
+    match stack.0.write(&mut writer, &built) {
+        Ok(()) => {
+            unsafe { *length = writer.length };
+            LINKIQ_OK
+        }
+        Err(error) => write_status(error),
+    }
+}
+
+/// Get a static description of a status code
+#[no_mangle]
+pub extern "C" fn linkiq_status_str(status: i32) -> *const c_char {
+    let description = match status {
+        LINKIQ_OK => c"ok",
+        LINKIQ_ERROR_INVALID_ARGUMENT => c"invalid argument",
+        LINKIQ_ERROR_CAPACITY => c"capacity exceeded",
+        LINKIQ_ERROR_NOT_ENOUGH_BYTES => c"not enough bytes",
+        LINKIQ_ERROR_PHL_DECODE => c"physical layer decode error",
+        LINKIQ_ERROR_RECEPTION_MISMATCH => c"reception mismatch",
+        LINKIQ_ERROR_MBAL_CRC => c"MBAL CRC error",
+        LINKIQ_ERROR_MBAL_CONTROL => c"invalid MBAL control",
+        LINKIQ_ERROR_MBAL_ADDRESS => c"invalid MBAL address",
+        LINKIQ_ERROR_MBAL_COMMAND => c"invalid MBAL command",
+        LINKIQ_ERROR_FILTERED => c"filtered",
+        LINKIQ_ERROR_WMBUS_LENGTH => c"invalid wM-Bus length",
+        LINKIQ_ERROR_WMBUS_CRC => c"wM-Bus CRC error",
+        _ => c"unknown status",
+    };
+    description.as_ptr()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn can_encode_and_decode() {
+        // Given
+        let stack = linkiq_stack_new();
+        let payload = [0x7A, 0x01, 0x00, 0x20];
+        let packet = LinkiqPacket {
+            code_rate: LINKIQ_CODE_RATE_ONE_HALF,
+            is_prioritized: true,
+            address: [0x2D, 0x2C, 0x89, 0x63, 0x00, 0x71, 0x34, 0x04],
+            function_code: LINKIQ_FUNCTION_CODE_SND_IR,
+            payload: payload.as_ptr(),
+            payload_len: payload.len(),
+            header_distance: 0,
+            decode_iterations: 0,
+            decode_distance: 0,
+            erased_bits: 0,
+            bit_flips: 0,
+        };
+        let mut frame = [0; LINKIQ_MAX_FRAME_LENGTH];
+        let mut length = 0;
+
+        // When
+        let encoded = unsafe {
+            linkiq_stack_encode(stack, &packet, frame.as_mut_ptr(), frame.len(), &mut length)
+        };
+        frame[phl::HEADER_SIZE] ^= 0xFF;
+        let mut decoded = core::mem::MaybeUninit::<LinkiqPacket>::uninit();
+        let status =
+            unsafe { linkiq_stack_decode(stack, frame.as_mut_ptr(), length, decoded.as_mut_ptr()) };
+        unsafe { linkiq_stack_free(stack) };
+
+        // Then
+        assert_eq!(LINKIQ_OK, encoded);
+        assert_eq!(LINKIQ_OK, status);
+        let decoded = unsafe { decoded.assume_init() };
+        assert_eq!(LINKIQ_CODE_RATE_ONE_HALF, decoded.code_rate);
+        assert!(decoded.is_prioritized);
+        assert_eq!(packet.address, decoded.address);
+        assert_eq!(LINKIQ_FUNCTION_CODE_SND_IR, decoded.function_code);
+        assert!(decoded.decode_iterations > 0);
+        let decoded_payload =
+            unsafe { slice::from_raw_parts(decoded.payload, decoded.payload_len) };
+        assert_eq!(payload, decoded_payload);
+    }
+
+    #[test]
+    fn can_report_errors() {
+        // Given
+        let stack = linkiq_stack_new();
+        let mut frame = [0xFF; 8];
+        let mut length = 0;
+
+        // When
+        let decoded =
+            unsafe { linkiq_stack_decode(stack, frame.as_mut_ptr(), frame.len(), ptr::null_mut()) };
+        let truncated = unsafe { linkiq_frame_length(frame.as_ptr(), 4, &mut length) };
+        unsafe { linkiq_stack_free(stack) };
+
+        // Then
+        assert_eq!(LINKIQ_ERROR_INVALID_ARGUMENT, decoded);
+        assert_eq!(LINKIQ_ERROR_NOT_ENOUGH_BYTES, truncated);
+    }
+}
@@ -0,0 +1,501 @@
+//! Compact append-only log of received frames.
+//!
+//! The log is meant for gateways that record receptions to flash or an SD card.
+//! Each record is written in one pass and is self-contained, so a log that is cut short by a
+//! power loss is still readable up to the last complete record. Records are little endian with the layout
+//!
+//! | Offset | Size | Field                                                        |
+//! |--------|------|--------------------------------------------------------------|
+//! | 0      | 2    | Sync bytes `LQ`                                              |
+//! | 2      | 2    | Length of the fields and the frame                           |
+//! | 4      | 8    | Start-of-frame timestamp in µs                               |
+//! | 12     | 2    | RSSI in dBm                                                  |
+//! | 14     | 2    | Noise floor in dBm                                           |
+//! | 16     | 1    | Channel, where 0 is channel A                                |
+//! | 17     | 1    | Flags telling whether the RSSI and the noise floor are present |
+//! | 18     | n    | The received frame bytes                                     |
+//! | 18 + n | 4    | CRC-32 of the length, the fields and the frame               |
+//!
+//! The reader skips anything that is not a valid record and continues at the next sync bytes.
+
+use crc::{Crc, CRC_32_ISO_HDLC};
+
+use crate::stack::{Channel, Rssi, WriteError, Writer};
+
+/// The sync bytes at the start of every record
+pub const SYNC: [u8; 2] = *b"LQ";
+
+/// The size of a record without the frame bytes
+pub const RECORD_OVERHEAD: usize = 4 + FIELDS_SIZE + 4;
+
+const FIELDS_SIZE: usize = 14;
+const FLAG_RSSI: u8 = 0x01;
+const FLAG_NOISE_FLOOR: u8 = 0x02;
+const CRC: Crc<u32> = Crc::<u32>::new(&CRC_32_ISO_HDLC);
+
+/// The reception metadata of a logged frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordInfo {
+    /// The start-of-frame timestamp in µs
+    pub timestamp: u64,
+    pub channel: Channel,
+    pub rssi: Option<Rssi>,
+    /// The noise floor of the channel when the frame was detected
+    pub noise_floor: Option<Rssi>,
+}
+
+/// Writer of records to a frame log
+pub struct LogWriter<W: Writer> {
+    writer: W,
+}
+
+impl<W: Writer> LogWriter<W> {
+    /// Create a log writer that appends records to the writer
+    pub const fn new(writer: W) -> Self {
+        Self { writer }
+    }
+
+    /// Append a record
+    pub fn write(&mut self, info: &RecordInfo, frame: &[u8]) -> Result<(), WriteError> {
+        let length = u16::try_from(FIELDS_SIZE + frame.len()).map_err(|_| WriteError::Capacity)?;
+
+        let mut fields = [0; 2 + FIELDS_SIZE];
+        fields[..2].copy_from_slice(&length.to_le_bytes());
+        fields[2..10].copy_from_slice(&info.timestamp.to_le_bytes());
+        fields[10..12].copy_from_slice(&info.rssi.unwrap_or(0).to_le_bytes());
+        fields[12..14].copy_from_slice(&info.noise_floor.unwrap_or(0).to_le_bytes());
+        fields[14] = info.channel as u8;
+        if info.rssi.is_some() {
+            fields[15] |= FLAG_RSSI;
+        }
+        if info.noise_floor.is_some() {
+            fields[15] |= FLAG_NOISE_FLOOR;
+        }
+
+        let mut digest = CRC.digest();
+        digest.update(&fields);
+        digest.update(frame);
+        let crc = digest.finalize();
+
+        self.writer.write(&SYNC)?;
+        self.writer.write(&fields)?;
+        self.writer.write(frame)?;
+        self.writer.write(&crc.to_le_bytes())
+    }
+
+    /// Append a frame received by the controller
+    #[cfg(feature = "ctrl")]
+    pub fn write_frame<const FRAME_LENGTH: usize>(
+        &mut self,
+        frame: &crate::ctrl::Frame<FRAME_LENGTH>,
+    ) -> Result<(), WriteError> {
+        let info = RecordInfo {
+            timestamp: frame.timestamp.as_micros(),
+            channel: frame.channel,
+            rssi: frame.rssi,
+            noise_floor: frame.noise_floor,
+        };
+        self.write(&info, frame.bytes())
+    }
+
+    /// Get the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Parse the record at the start of the buffer and get the record length
+#[cfg(feature = "std")]
+fn parse_record(buffer: &[u8]) -> Option<(RecordInfo, &[u8], usize)> {
+    use num_traits::FromPrimitive;
+
+    if buffer.len() < 4 || buffer[..2] != SYNC {
+        return None;
+    }
+
+    let length = u16::from_le_bytes([buffer[2], buffer[3]]) as usize;
+    if length < FIELDS_SIZE || buffer.len() < 4 + length + 4 {
+        return None;
+    }
+
+    let fields = &buffer[2..4 + length];
+    let crc = u32::from_le_bytes(buffer[4 + length..8 + length].try_into().unwrap());
+    if CRC.checksum(fields) != crc {
+        return None;
+    }
+
+    let flags = fields[15];
+    let info = RecordInfo {
+        timestamp: u64::from_le_bytes(fields[2..10].try_into().unwrap()),
+        channel: Channel::from_u8(fields[14])?,
+        rssi: (flags & FLAG_RSSI != 0).then(|| Rssi::from_le_bytes([fields[10], fields[11]])),
+        noise_floor: (flags & FLAG_NOISE_FLOOR != 0)
+            .then(|| Rssi::from_le_bytes([fields[12], fields[13]])),
+    };
+
+    Some((info, &fields[2 + FIELDS_SIZE..], 8 + length))
+}
+
+/// A frame read from a log
+#[cfg(feature = "std")]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub info: RecordInfo,
+    pub frame: std::vec::Vec<u8>,
+}
+
+/// Reader of the records in a frame log.
+///
+/// Bytes that are not part of a valid record, such as a record cut short by a power loss,
+/// are skipped and counted.
+#[cfg(feature = "std")]
+pub struct LogReader {
+    log: std::vec::Vec<u8>,
+    offset: usize,
+    skipped: usize,
+}
+
+#[cfg(feature = "std")]
+impl LogReader {
+    /// Read the whole log
+    pub fn new(mut reader: impl std::io::Read) -> std::io::Result<Self> {
+        let mut log = std::vec::Vec::new();
+        reader.read_to_end(&mut log)?;
+        Ok(Self {
+            log,
+            offset: 0,
+            skipped: 0,
+        })
+    }
+
+    /// Get the number of bytes skipped so far because they were not part of a valid record
+    pub fn skipped_bytes(&self) -> usize {
+        self.skipped
+    }
+}
+
+#[cfg(feature = "std")]
+impl Iterator for LogReader {
+    type Item = Record;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset < self.log.len() {
+            if let Some((info, frame, length)) = parse_record(&self.log[self.offset..]) {
+                let record = Record {
+                    info,
+                    frame: frame.to_vec(),
+                };
+                self.offset += length;
+                return Some(record);
+            }
+
+            self.offset += 1;
+            self.skipped += 1;
+        }
+
+        None
+    }
+}
+
+#[cfg(all(feature = "std", feature = "ctrl"))]
+pub use replay::{LogReplay, LogRxToken, ReplayError};
+
+/// Replay of a log into the controller
+#[cfg(all(feature = "std", feature = "ctrl"))]
+mod replay {
+    use std::collections::VecDeque;
+
+    use embassy_time::Instant;
+
+    use crate::{
+        ctrl::traits::{RxToken, Transceiver},
+        stack::{Channel, Rssi},
+    };
+
+    use super::Record;
+
+    /// The rssi reported on channels without a logged frame when the noise floor is not logged
+    const NOISE_LEVEL: Rssi = -120;
+
+    /// A transceiver that replays logged frames, e.g. for regression tests of the controller.
+    ///
+    /// The frames are replayed in order. A frame is only detected when the controller listens on
+    /// the channel it was logged on, and a frame is dropped if the controller does not start
+    /// to receive it when its rssi is reported. After the last logged frame the replay fails with
+    /// [`ReplayError::EndOfLog`], which ends the stream of the controller.
+    ///
+    /// The logged noise floor is reported as the rssi of the channels without a frame,
+    /// so it only feeds the noise floor estimate of the controller.
+    /// The noise floor of a replayed frame is the estimate of the controller, not the logged value.
+    pub struct LogReplay {
+        records: VecDeque<Record>,
+        channel: Channel,
+        detected: bool,
+        receiving: Option<Record>,
+    }
+
+    pub struct LogRxToken(Instant);
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ReplayError {
+        /// All logged frames were replayed
+        EndOfLog,
+        /// All logged bytes of the frame were read
+        EndOfFrame,
+    }
+
+    impl LogReplay {
+        pub fn new(records: impl IntoIterator<Item = Record>) -> Self {
+            Self {
+                records: records.into_iter().collect(),
+                channel: Channel::A,
+                detected: false,
+                receiving: None,
+            }
+        }
+
+        /// Get the number of frames not yet replayed
+        pub fn remaining(&self) -> usize {
+            self.records.len()
+        }
+    }
+
+    impl Transceiver for LogReplay {
+        type RxToken = LogRxToken;
+        type Error = ReplayError;
+
+        async fn init(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn set_channel(&mut self, channel: Channel) -> Result<(), Self::Error> {
+            if self.detected {
+                // The controller did not receive the detected frame
+                self.records.pop_front();
+                self.detected = false;
+            }
+
+            self.channel = channel;
+            Ok(())
+        }
+
+        async fn write(&mut self, _buffer: &[u8]) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn transmit(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn listen(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn get_rssi(&mut self) -> Result<Rssi, Self::Error> {
+            let Some(record) = self.records.front() else {
+                return Err(ReplayError::EndOfLog);
+            };
+
+            if record.info.channel == self.channel {
+                self.detected = true;
+                Ok(record.info.rssi.unwrap_or(0))
+            } else {
+                Ok(record.info.noise_floor.unwrap_or(NOISE_LEVEL))
+            }
+        }
+
+        async fn receive(
+            &mut self,
+            _min_frame_length: usize,
+        ) -> Result<Self::RxToken, Self::Error> {
+            self.detected = false;
+            let Some(record) = self.records.pop_front() else {
+                return Err(ReplayError::EndOfLog);
+            };
+
+            let token = LogRxToken(Instant::from_micros(record.info.timestamp));
+            self.receiving = Some(record);
+            Ok(token)
+        }
+
+        async fn read<'a>(
+            &'a mut self,
+            _token: &mut Self::RxToken,
+            buffer: &mut [u8],
+        ) -> Result<usize, Self::Error> {
+            // All logged bytes are read at once, and a truncated frame ends with an error
+            let record = self.receiving.take().ok_or(ReplayError::EndOfFrame)?;
+            let length = record.frame.len().min(buffer.len());
+            buffer[..length].copy_from_slice(&record.frame[..length]);
+            Ok(length)
+        }
+
+        async fn accept(
+            &mut self,
+            _token: &mut Self::RxToken,
+            _frame_length: usize,
+        ) -> Result<(), Self::Error> {
+            Ok(())
+        }
+
+        async fn idle(&mut self) -> Result<(), Self::Error> {
+            self.receiving = None;
+            Ok(())
+        }
+    }
+
+    impl RxToken for LogRxToken {
+        fn timestamp(&self) -> Instant {
+            self.0
+        }
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    fn info(timestamp: u64) -> RecordInfo {
+        RecordInfo {
+            timestamp,
+            channel: Channel::B,
+            rssi: Some(-85),
+            noise_floor: Some(-112),
+        }
+    }
+
+    #[test]
+    fn can_read_written_records() {
+        // Given
+        let mut writer = LogWriter::new(std::vec::Vec::new());
+        writer.write(&info(1), &[1, 2, 3]).unwrap();
+        let no_metadata = RecordInfo {
+            rssi: None,
+            noise_floor: None,
+            ..info(2)
+        };
+        writer.write(&no_metadata, &[]).unwrap();
+        let log = writer.into_inner();
+
+        // When
+        let mut reader = LogReader::new(log.as_slice()).unwrap();
+        let records: std::vec::Vec<Record> = reader.by_ref().collect();
+
+        // Then
+        assert_eq!(RECORD_OVERHEAD + 3 + RECORD_OVERHEAD, log.len());
+        assert_eq!(2, records.len());
+        assert_eq!(info(1), records[0].info);
+        assert_eq!([1, 2, 3], records[0].frame.as_slice());
+        assert_eq!(no_metadata, records[1].info);
+        assert!(records[1].frame.is_empty());
+        assert_eq!(0, reader.skipped_bytes());
+    }
+
+    #[test]
+    fn can_recover_after_power_loss() {
+        // Given
+        let mut writer = LogWriter::new(std::vec::Vec::new());
+        writer.write(&info(1), &[0x11; 40]).unwrap();
+        writer.write(&info(2), &[0x22; 40]).unwrap();
+        let mut log = writer.into_inner();
+
+        // The second record is cut short and the gateway appends after it when restarted
+        log.truncate(RECORD_OVERHEAD + 40 + 20);
+        let mut writer = LogWriter::new(log);
+        writer.write(&info(3), &[0x33; 40]).unwrap();
+        let mut log = writer.into_inner();
+
+        // A bit error in the first record
+        log[10] ^= 0x01;
+
+        // When
+        let mut reader = LogReader::new(log.as_slice()).unwrap();
+        let records: std::vec::Vec<Record> = reader.by_ref().collect();
+
+        // Then
+        assert_eq!(1, records.len());
+        assert_eq!(info(3), records[0].info);
+        assert_eq!(RECORD_OVERHEAD + 40 + 20, reader.skipped_bytes());
+    }
+
+    #[test]
+    fn can_not_write_too_long_frame() {
+        // Given
+        let mut writer = LogWriter::new(heapless::Vec::<u8, 64>::new());
+
+        // When
+        let result = writer.write(&info(1), &[0; 100]);
+
+        // Then
+        assert!(matches!(result, Err(WriteError::Capacity)));
+    }
+
+    #[cfg(feature = "ctrl")]
+    #[tokio::test]
+    async fn can_replay_into_controller() {
+        use futures::{pin_mut, StreamExt};
+
+        use crate::{
+            ctrl::Controller,
+            fec::CodeRate,
+            stack::{mbal::MbalFunctionCode, Packet, PacketBuilder, Stack},
+        };
+
+        // Given
+        let stack = Stack::new();
+        let packet: Packet = PacketBuilder::new()
+            .code_rate(CodeRate::OneHalf)
+            .address(wmbus::WMBusAddress::new(
+                0x2c2d.try_into().unwrap(),
+                71006389,
+                0x34,
+                0x04.try_into().unwrap(),
+            ))
+            .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+            .mbus_data(&[0x7A, 0x01, 0x00, 0x20])
+            .build()
+            .unwrap();
+        let mut frame = std::vec::Vec::new();
+        stack.write(&mut frame, &packet).unwrap();
+
+        let mut writer = LogWriter::new(std::vec::Vec::new());
+        writer.write(&info(1_000), &frame).unwrap();
+        let on_d = RecordInfo {
+            channel: Channel::D,
+            ..info(2_000)
+        };
+        writer.write(&on_d, &frame).unwrap();
+        let log = writer.into_inner();
+
+        let replay = LogReplay::new(LogReader::new(log.as_slice()).unwrap());
+        let mut controller = Controller::new(replay);
+        controller.init().await.unwrap();
+
+        // When
+        let mut frames = std::vec::Vec::new();
+        {
+        let mut error = None;
+        {
+            let stream = controller.receive().await.unwrap();
+            pin_mut!(stream);
+            while let Some(received) = stream.next().await {
+                match received {
+                    Ok(frame) => frames.push(frame),
+                    Err(e) => error = Some(e),
+                }
+            }
+        }
+
+        // Then
+        assert_eq!(2, frames.len());
+        assert_eq!(Some(ReplayError::EndOfLog), error);
+        assert_eq!(0, controller.release().remaining());
+        assert_eq!(Channel::B, frames[0].channel);
+        assert_eq!(Channel::D, frames[1].channel);
+        assert_eq!(1_000, frames[0].timestamp.as_micros());
+        assert_eq!(Some(-85), frames[0].rssi);
+        // The logged noise floor of -112 is not restored, the frame has the initial estimate
+        assert_eq!(Some(-110), frames[0].noise_floor);
+        assert_eq!(frame.as_slice(), frames[1].bytes());
+    }
+}
@@ -1,19 +1,32 @@
-#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(any(test, feature = "std")), no_std)]
 #![allow(async_fn_in_trait)]
 #![feature(const_trait_impl)]
 #![feature(coroutines)]
 #![feature(let_chains)]
+#![cfg_attr(test, feature(test))]
 
+#[cfg(feature = "alloc")]
 extern crate alloc;
 
 #[macro_use]
 extern crate num_derive;
 
+mod bitbuffer;
 mod bitreader;
 pub mod fec;
+pub mod framelog;
 pub mod interleaver;
 mod phycodedheader;
 pub mod stack;
 
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+#[cfg(feature = "pcap")]
+pub mod pcap;
+
+#[cfg(feature = "sim")]
+pub mod sim;
+
 #[cfg(feature = "ctrl")]
 pub mod ctrl;
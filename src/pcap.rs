@@ -0,0 +1,655 @@
+//! Export and import of frames in the pcapng capture format.
+//!
+//! Frames are stored in enhanced packet blocks on an interface with the user link type
+//! [`LINKTYPE_LINKIQ`]. Every packet starts with a small pseudo-header, similar to radiotap,
+//! with the reception metadata followed by the raw frame bytes.
+//!
+//! The pseudo-header is little endian and has the layout
+//!
+//! | Offset | Size | Field                                             |
+//! |--------|------|---------------------------------------------------|
+//! | 0      | 1    | Version, currently 0                              |
+//! | 1      | 1    | Flags telling which of the optional fields are present |
+//! | 2      | 2    | Length of the pseudo-header in bytes              |
+//! | 4      | 4    | Frequency in Hz                                   |
+//! | 8      | 2    | RSSI in dBm as a signed integer                   |
+//! | 10     | 1    | Channel, where 0 is channel A                     |
+//! | 11     | 1    | Reserved                                          |
+//!
+//! Readers must use the length field to find the frame, so that fields can be appended later.
+
+use std::io::{self, Read, Write};
+
+use num_traits::FromPrimitive;
+
+use crate::stack::{apl, Channel, Layer, Packet, ReadError, Rssi, Stack};
+
+/// The link type used for LinkIQ frames, which is the first of the user link types
+pub const LINKTYPE_LINKIQ: u16 = 147;
+
+/// The length of the pseudo-header written before every frame
+pub const PSEUDO_HEADER_SIZE: usize = 12;
+
+const SECTION_HEADER_BLOCK: u32 = 0x0A0D0D0A;
+const INTERFACE_DESCRIPTION_BLOCK: u32 = 0x00000001;
+const ENHANCED_PACKET_BLOCK: u32 = 0x00000006;
+const BYTE_ORDER_MAGIC: u32 = 0x1A2B3C4D;
+const OPTION_END: u16 = 0;
+const OPTION_TSRESOL: u16 = 9;
+
+const FLAG_FREQUENCY: u8 = 0x01;
+const FLAG_RSSI: u8 = 0x02;
+const FLAG_CHANNEL: u8 = 0x04;
+
+/// The metadata stored together with a frame
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameInfo {
+    /// The start-of-frame timestamp in microseconds.
+    /// This is either since the Unix epoch or, for frames from the controller, since boot.
+    pub timestamp: u64,
+    pub channel: Option<Channel>,
+    pub rssi: Option<Rssi>,
+    /// The frequency in Hz
+    pub frequency: Option<u32>,
+}
+
+impl FrameInfo {
+    /// Create the metadata for a frame received on a channel
+    pub const fn on_channel(timestamp: u64, channel: Channel, rssi: Option<Rssi>) -> Self {
+        Self {
+            timestamp,
+            channel: Some(channel),
+            rssi,
+            frequency: Some(channel.frequency()),
+        }
+    }
+}
+
+/// A frame read from a capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct Record {
+    pub info: FrameInfo,
+    pub frame: Vec<u8>,
+}
+
+/// Writer of pcapng captures with a single LinkIQ interface
+pub struct PcapWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> PcapWriter<W> {
+    /// Start a new capture by writing the section header and the interface description
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        // Section header block with an unspecified section length
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&(-1i64).to_le_bytes());
+        write_block(&mut writer, SECTION_HEADER_BLOCK, &body)?;
+
+        // Interface description block with microsecond timestamps
+        let mut body = Vec::new();
+        body.extend_from_slice(&LINKTYPE_LINKIQ.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&OPTION_TSRESOL.to_le_bytes());
+        body.extend_from_slice(&1u16.to_le_bytes());
+        body.extend_from_slice(&[6, 0, 0, 0]);
+        body.extend_from_slice(&OPTION_END.to_le_bytes());
+        body.extend_from_slice(&0u16.to_le_bytes());
+        write_block(&mut writer, INTERFACE_DESCRIPTION_BLOCK, &body)?;
+
+        Ok(Self { writer })
+    }
+
+    /// Write a frame with its metadata
+    pub fn write(&mut self, info: &FrameInfo, frame: &[u8]) -> io::Result<()> {
+        let captured = PSEUDO_HEADER_SIZE + frame.len();
+
+        let mut body = Vec::with_capacity(20 + captured + 3);
+        body.extend_from_slice(&0u32.to_le_bytes());
+        body.extend_from_slice(&((info.timestamp >> 32) as u32).to_le_bytes());
+        body.extend_from_slice(&(info.timestamp as u32).to_le_bytes());
+        body.extend_from_slice(&(captured as u32).to_le_bytes());
+        body.extend_from_slice(&(captured as u32).to_le_bytes());
+        write_pseudo_header(&mut body, info);
+        body.extend_from_slice(frame);
+        body.resize(body.len().next_multiple_of(4), 0);
+
+        write_block(&mut self.writer, ENHANCED_PACKET_BLOCK, &body)
+    }
+
+    /// Write a frame received by the controller
+    #[cfg(feature = "ctrl")]
+    pub fn write_frame<const FRAME_LENGTH: usize>(
+        &mut self,
+        frame: &crate::ctrl::Frame<FRAME_LENGTH>,
+    ) -> io::Result<()> {
+        let info = FrameInfo::on_channel(frame.timestamp.as_micros(), frame.channel, frame.rssi);
+        self.write(&info, frame.bytes())
+    }
+
+    /// Flush the underlying writer
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Get the underlying writer
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+fn write_block(writer: &mut impl Write, block_type: u32, body: &[u8]) -> io::Result<()> {
+    let total_length = (12 + body.len()) as u32;
+    writer.write_all(&block_type.to_le_bytes())?;
+    writer.write_all(&total_length.to_le_bytes())?;
+    writer.write_all(body)?;
+    writer.write_all(&total_length.to_le_bytes())
+}
+
+fn write_pseudo_header(buffer: &mut Vec<u8>, info: &FrameInfo) {
+    let mut flags = 0;
+    if info.frequency.is_some() {
+        flags |= FLAG_FREQUENCY;
+    }
+    if info.rssi.is_some() {
+        flags |= FLAG_RSSI;
+    }
+    if info.channel.is_some() {
+        flags |= FLAG_CHANNEL;
+    }
+
+    buffer.push(0);
+    buffer.push(flags);
+    buffer.extend_from_slice(&(PSEUDO_HEADER_SIZE as u16).to_le_bytes());
+    buffer.extend_from_slice(&info.frequency.unwrap_or(0).to_le_bytes());
+    buffer.extend_from_slice(&info.rssi.unwrap_or(0).to_le_bytes());
+    buffer.push(info.channel.map_or(0, |channel| channel as u8));
+    buffer.push(0);
+}
+
+fn read_pseudo_header(timestamp: u64, data: &[u8]) -> io::Result<(FrameInfo, &[u8])> {
+    if data.len() < PSEUDO_HEADER_SIZE {
+        return Err(invalid_data("truncated pseudo-header"));
+    }
+
+    let flags = data[1];
+    let length = u16::from_le_bytes([data[2], data[3]]) as usize;
+    if length < PSEUDO_HEADER_SIZE || length > data.len() {
+        return Err(invalid_data("invalid pseudo-header length"));
+    }
+
+    let info = FrameInfo {
+        timestamp,
+        channel: match flags & FLAG_CHANNEL {
+            0 => None,
+            _ => Some(Channel::from_u8(data[10]).ok_or_else(|| invalid_data("invalid channel"))?),
+        },
+        rssi: (flags & FLAG_RSSI != 0).then(|| Rssi::from_le_bytes([data[8], data[9]])),
+        frequency: (flags & FLAG_FREQUENCY != 0)
+            .then(|| u32::from_le_bytes(data[4..8].try_into().unwrap())),
+    };
+
+    Ok((info, &data[length..]))
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// An interface described in the current section
+struct Interface {
+    link_type: u16,
+    /// Timestamp units per second
+    resolution: u64,
+}
+
+/// Reader of LinkIQ frames from pcapng captures.
+///
+/// Captures in either byte order are supported.
+/// Blocks of other types and packets on interfaces with other link types are skipped.
+pub struct PcapReader<R: Read> {
+    reader: R,
+    big_endian: bool,
+    interfaces: Vec<Interface>,
+}
+
+impl<R: Read> PcapReader<R> {
+    /// Start reading a capture, which must begin with a section header
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut block_type = [0; 4];
+        reader.read_exact(&mut block_type)?;
+        if u32::from_le_bytes(block_type) != SECTION_HEADER_BLOCK {
+            return Err(invalid_data("not a pcapng capture"));
+        }
+
+        let mut reader = Self {
+            reader,
+            big_endian: false,
+            interfaces: Vec::new(),
+        };
+        reader.read_section_header()?;
+        Ok(reader)
+    }
+
+    /// Read the next frame, or `None` at the end of the capture
+    pub fn read(&mut self) -> io::Result<Option<Record>> {
+        loop {
+            let mut block_type = [0; 4];
+            match self.reader.read_exact(&mut block_type) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(error) => return Err(error),
+            }
+
+            // The section header block type is a palindrome and can be matched before the byte order is known
+            if u32::from_le_bytes(block_type) == SECTION_HEADER_BLOCK {
+                self.read_section_header()?;
+                continue;
+            }
+
+            let block_type = self.u32(block_type);
+            let body = self.read_body()?;
+            match block_type {
+                INTERFACE_DESCRIPTION_BLOCK => self.read_interface(&body)?,
+                ENHANCED_PACKET_BLOCK => {
+                    if let Some(record) = self.read_packet(&body)? {
+                        return Ok(Some(record));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn read_section_header(&mut self) -> io::Result<()> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+        let mut magic = [0; 4];
+        self.reader.read_exact(&mut magic)?;
+
+        self.big_endian = match u32::from_le_bytes(magic) {
+            BYTE_ORDER_MAGIC => false,
+            magic if magic.swap_bytes() == BYTE_ORDER_MAGIC => true,
+            _ => return Err(invalid_data("invalid byte order magic")),
+        };
+        self.interfaces.clear();
+
+        // Skip the rest of the block including the trailing length
+        let length = self.u32(length) as usize;
+        if length < 28 {
+            return Err(invalid_data("invalid section header length"));
+        }
+        self.skip(length - 12)
+    }
+
+    /// Read the rest of a block after its type, and get the body without the lengths
+    fn read_body(&mut self) -> io::Result<Vec<u8>> {
+        let mut length = [0; 4];
+        self.reader.read_exact(&mut length)?;
+        let length = self.u32(length) as usize;
+        if length < 12 || length % 4 != 0 {
+            return Err(invalid_data("invalid block length"));
+        }
+
+        // The body is read incrementally, so a corrupt length can not allocate more than the file holds
+        let mut body = Vec::new();
+        (&mut self.reader)
+            .take(length as u64 - 12)
+            .read_to_end(&mut body)?;
+        if body.len() != length - 12 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.skip(4)?;
+        Ok(body)
+    }
+
+    fn read_interface(&mut self, body: &[u8]) -> io::Result<()> {
+        if body.len() < 8 {
+            return Err(invalid_data("truncated interface description"));
+        }
+
+        let mut interface = Interface {
+            link_type: self.u16([body[0], body[1]]),
+            resolution: 1_000_000,
+        };
+
+        let mut options = &body[8..];
+        while options.len() >= 4 {
+            let code = self.u16([options[0], options[1]]);
+            let length = self.u16([options[2], options[3]]) as usize;
+            let value = options
+                .get(4..4 + length)
+                .ok_or_else(|| invalid_data("truncated option"))?;
+            match code {
+                OPTION_END => break,
+                OPTION_TSRESOL if length == 1 => {
+                    interface.resolution = match value[0] {
+                        exponent if exponent & 0x80 == 0 => 10u64.pow(exponent as u32),
+                        exponent => 1 << (exponent & 0x7F),
+                    };
+                }
+                _ => {}
+            }
+            options = &options[(4 + length).next_multiple_of(4).min(options.len())..];
+        }
+
+        self.interfaces.push(interface);
+        Ok(())
+    }
+
+    fn read_packet(&self, body: &[u8]) -> io::Result<Option<Record>> {
+        if body.len() < 20 {
+            return Err(invalid_data("truncated packet"));
+        }
+
+        let interface = self.u32(body[0..4].try_into().unwrap()) as usize;
+        let interface = self
+            .interfaces
+            .get(interface)
+            .ok_or_else(|| invalid_data("unknown interface"))?;
+        if interface.link_type != LINKTYPE_LINKIQ {
+            return Ok(None);
+        }
+
+        let high = self.u32(body[4..8].try_into().unwrap()) as u64;
+        let low = self.u32(body[8..12].try_into().unwrap()) as u64;
+        let ticks = (high << 32) | low;
+        let timestamp = (ticks as u128 * 1_000_000 / interface.resolution as u128) as u64;
+
+        let captured = self.u32(body[12..16].try_into().unwrap()) as usize;
+        let data = body
+            .get(20..20 + captured)
+            .ok_or_else(|| invalid_data("truncated packet data"))?;
+        let (info, frame) = read_pseudo_header(timestamp, data)?;
+
+        Ok(Some(Record {
+            info,
+            frame: frame.to_vec(),
+        }))
+    }
+
+    fn skip(&mut self, length: usize) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.reader).take(length as u64), &mut io::sink())?;
+        if skipped as usize != length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        Ok(())
+    }
+
+    fn u16(&self, bytes: [u8; 2]) -> u16 {
+        match self.big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        }
+    }
+
+    fn u32(&self, bytes: [u8; 4]) -> u32 {
+        match self.big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        }
+    }
+}
+
+impl<R: Read> Iterator for PcapReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+impl<
+        U: Layer,
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+        const FRAME_LENGTH: usize,
+    >
+    Stack<
+        U,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+        FRAME_LENGTH,
+    >
+{
+    /// Read all frames in a capture.
+    /// The rssi of each read packet is taken from the capture.
+    pub fn replay<'a, R: Read>(
+        &'a self,
+        reader: PcapReader<R>,
+    ) -> impl Iterator<
+        Item = io::Result<(
+            FrameInfo,
+            Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError>,
+        )>,
+    > + 'a
+    where
+        R: 'a,
+    {
+        reader.map(move |record| {
+            let record = record?;
+            let result = self.read(&record.frame).map(|mut packet| {
+                if let Some(rx) = &mut packet.rx {
+                    rx.rssi = record.info.rssi;
+                }
+                packet
+            });
+            Ok((record.info, result))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wmbus::WMBusAddress;
+
+    use super::*;
+    use crate::{
+        fec::CodeRate,
+        stack::{mbal::MbalFunctionCode, phl, PacketBuilder},
+    };
+
+    fn info(timestamp: u64) -> FrameInfo {
+        FrameInfo::on_channel(timestamp, Channel::C, Some(-87))
+    }
+
+    #[test]
+    fn can_round_trip_frames() {
+        // Given
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer
+            .write(&info(1_700_000_000_123_456), &[1, 2, 3])
+            .unwrap();
+        writer
+            .write(&FrameInfo::default(), &[4, 5, 6, 7, 8])
+            .unwrap();
+        let capture = writer.into_inner();
+
+        // When
+        let records: Vec<Record> = PcapReader::new(capture.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        // Then
+        assert_eq!(2, records.len());
+        assert_eq!(info(1_700_000_000_123_456), records[0].info);
+        assert_eq!(Some(868_530_000), records[0].info.frequency);
+        assert_eq!([1, 2, 3], records[0].frame.as_slice());
+        assert_eq!(FrameInfo::default(), records[1].info);
+        assert_eq!([4, 5, 6, 7, 8], records[1].frame.as_slice());
+    }
+
+    #[test]
+    fn can_write_blocks_aligned_to_four_bytes() {
+        // Given
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        let start = writer.writer.len();
+
+        // When
+        writer.write(&info(0), &[0xAA; 5]).unwrap();
+
+        // Then
+        let block = &writer.writer[start..];
+        assert_eq!(0, block.len() % 4);
+        assert_eq!(
+            block.len() as u32,
+            u32::from_le_bytes(block[4..8].try_into().unwrap())
+        );
+        assert_eq!(block[4..8], block[block.len() - 4..]);
+    }
+
+    #[test]
+    fn can_skip_other_link_types_and_blocks() {
+        // Given
+        let mut capture = PcapWriter::new(Vec::new()).unwrap().into_inner();
+
+        // An ethernet interface with a packet, and a name resolution block
+        write_block(
+            &mut capture,
+            INTERFACE_DESCRIPTION_BLOCK,
+            &[1, 0, 0, 0, 0, 0, 0, 0],
+        )
+        .unwrap();
+        let mut body = vec![1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 4, 0, 0, 0, 4, 0, 0, 0];
+        body.extend_from_slice(&[0xFF; 4]);
+        write_block(&mut capture, ENHANCED_PACKET_BLOCK, &body).unwrap();
+        write_block(&mut capture, 0x00000004, &[0; 4]).unwrap();
+
+        let mut writer = PcapWriter { writer: capture };
+        writer.write(&info(42), &[9, 9]).unwrap();
+        let capture = writer.into_inner();
+
+        // When
+        let records: Vec<Record> = PcapReader::new(capture.as_slice())
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        // Then
+        assert_eq!(1, records.len());
+        assert_eq!(info(42), records[0].info);
+    }
+
+    #[test]
+    fn can_read_big_endian_capture() {
+        // Given
+        let mut capture = Vec::new();
+        let block = |capture: &mut Vec<u8>, block_type: u32, body: &[u8]| {
+            let length = (12 + body.len()) as u32;
+            capture.extend_from_slice(&block_type.to_be_bytes());
+            capture.extend_from_slice(&length.to_be_bytes());
+            capture.extend_from_slice(body);
+            capture.extend_from_slice(&length.to_be_bytes());
+        };
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&BYTE_ORDER_MAGIC.to_be_bytes());
+        body.extend_from_slice(&[0, 1, 0, 0]);
+        body.extend_from_slice(&(-1i64).to_be_bytes());
+        block(&mut capture, SECTION_HEADER_BLOCK, &body);
+        block(
+            &mut capture,
+            INTERFACE_DESCRIPTION_BLOCK,
+            &[0, 147, 0, 0, 0, 0, 0, 0],
+        );
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&1_000u32.to_be_bytes());
+        body.extend_from_slice(&14u32.to_be_bytes());
+        body.extend_from_slice(&14u32.to_be_bytes());
+        write_pseudo_header(&mut body, &info(1_000));
+        body.extend_from_slice(&[1, 2, 0, 0]);
+        block(&mut capture, ENHANCED_PACKET_BLOCK, &body);
+
+        // When
+        let record = PcapReader::new(capture.as_slice())
+            .unwrap()
+            .read()
+            .unwrap()
+            .unwrap();
+
+        // Then
+        assert_eq!(info(1_000), record.info);
+        assert_eq!([1, 2], record.frame.as_slice());
+    }
+
+    #[test]
+    fn can_replay_capture() {
+        // Given
+        let stack = Stack::new();
+        let packet: Packet = PacketBuilder::new()
+            .code_rate(CodeRate::OneThird)
+            .address(WMBusAddress::new(
+                0x2c2d.try_into().unwrap(),
+                71006389,
+                0x34,
+                0x04.try_into().unwrap(),
+            ))
+            .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+            .mbus_data(&[0x7A, 0x01, 0x00, 0x20, 0x05, 0x19, 0x32, 0x29])
+            .build()
+            .unwrap();
+        let mut frame = Vec::new();
+        stack.write(&mut frame, &packet).unwrap();
+
+        let mut writer = PcapWriter::new(Vec::new()).unwrap();
+        writer.write(&info(1), &frame).unwrap();
+        writer.write(&info(2), &frame[..phl::HEADER_SIZE]).unwrap();
+        let capture = writer.into_inner();
+
+        // When
+        let results: Vec<_> = stack
+            .replay(PcapReader::new(capture.as_slice()).unwrap())
+            .collect::<io::Result<_>>()
+            .unwrap();
+
+        // Then
+        assert_eq!(2, results.len());
+        let (info, read) = &results[0];
+        let read = read.as_ref().unwrap();
+        assert_eq!(1, info.timestamp);
+        assert_eq!(Some(-87), read.rx.as_ref().unwrap().rssi);
+        assert_eq!(packet.mbus_data, read.mbus_data);
+        assert!(results[1].1.is_err());
+    }
+
+    #[test]
+    fn can_not_read_block_beyond_end_of_capture() {
+        // Given
+        let mut capture = PcapWriter::new(Vec::new()).unwrap().into_inner();
+        capture.extend_from_slice(&INTERFACE_DESCRIPTION_BLOCK.to_le_bytes());
+        capture.extend_from_slice(&0xFFFF_FFF0u32.to_le_bytes());
+        capture.extend_from_slice(&[0; 8]);
+
+        // When
+        let result = PcapReader::new(capture.as_slice()).unwrap().next().unwrap();
+
+        // Then
+        assert_eq!(io::ErrorKind::UnexpectedEof, result.err().unwrap().kind());
+    }
+
+    #[test]
+    fn can_not_read_other_formats() {
+        // When
+        let result = PcapReader::new([0xD4, 0xC3, 0xB2, 0xA1, 0, 0, 0, 0].as_slice());
+
+        // Then
+        assert_eq!(io::ErrorKind::InvalidData, result.err().unwrap().kind());
+    }
+}
@@ -1,10 +1,13 @@
 use bitvec::prelude::*;
+use heapless::Vec;
 
 use crate::{
+    bitbuffer::BitBuffer,
     bitreader::{BitField, BitReader},
-    fec::CodeRate,
+    fec::{CodeRate, Llr},
 };
 
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub(crate) struct PhyCodedHeader {
     pub rate: CodeRate,
     pub data_length: usize,
@@ -16,60 +19,163 @@ impl PhyCodedHeader {
     }
 
     pub fn read<T: BitStore>(reader: &mut BitReader<T, Msb0>) -> Option<(Self, usize)> {
+        let (first, second, third) = PhyCodedHeader::read_codeword(reader)?;
+
+        Some(
+            PhyCodedHeader::find_close_codeword(first, second, third)
+                .unwrap_or_else(|| PhyCodedHeader::scan(first, second, third)),
+        )
+    }
+
+    /// Read the header and rank the `N` closest (header, distance) candidates by distance.
+    /// The first candidate is the one returned by [`PhyCodedHeader::read`].
+    pub fn read_candidates<T: BitStore, const N: usize>(
+        reader: &mut BitReader<T, Msb0>,
+    ) -> Option<Vec<(Self, usize), N>> {
+        let (first, second, third) = PhyCodedHeader::read_codeword(reader)?;
+
+        // Rate 1/3 is ranked first on equal distance, matching `read`
+        let mut candidates = Vec::new();
+        for rate in [CodeRate::OneThird, CodeRate::OneHalf] {
+            for data_length in MIN_DATA_LENGTH..=MAX_DATA_LENGTH {
+                let header = PhyCodedHeader::new(rate, data_length);
+                let distance = header.distance(first, second, third);
+
+                let position = candidates.partition_point(|(_, other)| *other <= distance);
+                if position < N {
+                    if candidates.is_full() {
+                        candidates.pop();
+                    }
+                    candidates.insert(position, (header, distance)).ok()?;
+                }
+            }
+        }
+
+        Some(candidates)
+    }
+
+    /// Read the header from soft bits by correlation against all codewords.
+    /// There is one llr per code bit, excluding the two padding bits preceding the header,
+    /// and a positive value is in favor of a one bit.
+    /// Missing trailing llrs are treated as erasures, so the header can be decoded from partial input.
+    /// The reliability is the correlation margin to the second best codeword.
+    pub fn read_soft(llrs: &[Llr]) -> Option<(Self, u32)> {
+        let candidates = PhyCodedHeader::read_soft_candidates::<2>(llrs)?;
+        let (header, best) = candidates[0];
+        let (_, second) = candidates[1];
+        Some((header, best.abs_diff(second)))
+    }
+
+    /// Rank the `N` codewords with the largest correlation against the soft bits.
+    /// The candidates are returned with their correlation.
+    pub fn read_soft_candidates<const N: usize>(llrs: &[Llr]) -> Option<Vec<(Self, i32), N>> {
+        if llrs.is_empty() {
+            return None;
+        }
+
+        // Rate 1/3 is ranked first on equal correlation, matching `read`
+        let mut candidates = Vec::new();
+        for rate in [CodeRate::OneThird, CodeRate::OneHalf] {
+            for data_length in MIN_DATA_LENGTH..=MAX_DATA_LENGTH {
+                let header = PhyCodedHeader::new(rate, data_length);
+                let correlation = header.correlation(llrs);
+
+                let position = candidates.partition_point(|(_, other)| *other >= correlation);
+                if position < N {
+                    if candidates.is_full() {
+                        candidates.pop();
+                    }
+                    candidates.insert(position, (header, correlation)).ok()?;
+                }
+            }
+        }
+
+        Some(candidates)
+    }
+
+    /// Get the number of hard decisions of the soft bits that differ from the codeword
+    pub fn hard_distance(&self, llrs: &[Llr]) -> usize {
+        let codeword = self.codeword();
+        let bits = &codeword.view_bits::<Msb0>()[2..2 + CODE_BITS];
+        bits.iter()
+            .zip(llrs)
+            .filter(|(bit, llr)| **bit != (**llr > 0))
+            .count()
+    }
+
+    fn correlation(&self, llrs: &[Llr]) -> i32 {
+        let codeword = self.codeword();
+        let bits = &codeword.view_bits::<Msb0>()[2..2 + CODE_BITS];
+        bits.iter()
+            .zip(llrs)
+            .map(|(bit, llr)| if *bit { *llr as i32 } else { -(*llr as i32) })
+            .sum()
+    }
+
+    fn read_codeword<T: BitStore>(reader: &mut BitReader<T, Msb0>) -> Option<(u32, u32, u32)> {
         let first = 0xC0000000 | reader.read_bits::<u32>(30)?;
         let second = reader.read_bits::<u32>(32)?;
         let third = reader.read_bits::<u32>(20)? << 12;
+        Some((first, second, third))
+    }
+
+    /// Find the codeword within the unique decoding distance by flipping up to two of the
+    /// received information bits, i.e. the eight length bits and the rate bit.
+    /// The found codeword is the closest, so the result is the same as for a full table scan.
+    fn find_close_codeword(first: u32, second: u32, third: u32) -> Option<(Self, usize)> {
+        let received = (((third >> 12) & 1) << 8) | ((first >> 22) & 0xFF);
+
+        let single_flips = (0..INFO_BITS).map(|bit| 1 << bit);
+        let double_flips =
+            (0..INFO_BITS).flat_map(|high| (0..high).map(move |low| (1 << high) | (1 << low)));
+
+        for flips in [0].into_iter().chain(single_flips).chain(double_flips) {
+            let info = received ^ flips;
+            let rate = match info >> 8 {
+                0 => CodeRate::OneHalf,
+                _ => CodeRate::OneThird,
+            };
+            let data_length = (info & 0xFF) as usize;
+            if !(MIN_DATA_LENGTH..=MAX_DATA_LENGTH).contains(&data_length) {
+                continue;
+            }
+
+            let header = PhyCodedHeader::new(rate, data_length);
+            let distance = header.distance(first, second, third);
+            if distance <= UNIQUE_DISTANCE {
+                return Some((header, distance));
+            }
+        }
+
+        None
+    }
 
+    fn scan(first: u32, second: u32, third: u32) -> (Self, usize) {
         let (length12, distance12) =
-            PhyCodedHeader::find_best_length(&RATE_ONE_HALF, first, second, third);
+            PhyCodedHeader::find_best_length(CodeRate::OneHalf, first, second, third);
         if distance12 == 0 {
-            return Some((
-                PhyCodedHeader {
-                    rate: CodeRate::OneHalf,
-                    data_length: length12,
-                },
-                distance12,
-            ));
+            return (PhyCodedHeader::new(CodeRate::OneHalf, length12), distance12);
         }
 
         let (length13, distance13) =
-            PhyCodedHeader::find_best_length(&RATE_ONE_THIRD, first, second, third);
+            PhyCodedHeader::find_best_length(CodeRate::OneThird, first, second, third);
         if distance12 < distance13 {
-            Some((
-                PhyCodedHeader {
-                    rate: CodeRate::OneHalf,
-                    data_length: length12,
-                },
-                distance12,
-            ))
+            (PhyCodedHeader::new(CodeRate::OneHalf, length12), distance12)
         } else {
-            Some((
-                PhyCodedHeader {
-                    rate: CodeRate::OneThird,
-                    data_length: length13,
-                },
-                distance13,
-            ))
+            (PhyCodedHeader::new(CodeRate::OneThird, length13), distance13)
         }
     }
 
-    fn find_best_length(
-        table: &[[u32; 3]; 240],
-        first: u32,
-        second: u32,
-        third: u32,
-    ) -> (usize, usize) {
+    fn find_best_length(rate: CodeRate, first: u32, second: u32, third: u32) -> (usize, usize) {
         let mut found_length = 0;
         let mut min_distance = usize::MAX;
 
-        for (index, row) in table.iter().enumerate() {
-            let distance = ((row[0] ^ first).count_ones()
-                + (row[1] ^ second).count_ones()
-                + (row[2] ^ third).count_ones()) as usize;
+        for data_length in MIN_DATA_LENGTH..=MAX_DATA_LENGTH {
+            let distance = PhyCodedHeader::new(rate, data_length).distance(first, second, third);
 
             if distance < min_distance {
                 min_distance = distance;
-                found_length = index + 12;
+                found_length = data_length;
 
                 if min_distance == 0 {
                     return (found_length, 0);
@@ -80,18 +186,107 @@ impl PhyCodedHeader {
         (found_length, min_distance)
     }
 
-    pub fn write<T: BitStore>(&self, writer: &mut BitVec<T, Msb0>) {
+    fn distance(&self, first: u32, second: u32, third: u32) -> usize {
+        let codeword = self.codeword();
+        ((codeword[0] ^ first).count_ones()
+            + (codeword[1] ^ second).count_ones()
+            + (codeword[2] ^ third).count_ones()) as usize
+    }
+
+    pub fn write<const N: usize>(&self, writer: &mut BitBuffer<N>) {
+        let codeword = self.codeword();
+        let entry = codeword.view_bits::<Msb0>();
+
+        writer.extend_from_bitslice(&entry[2..2 + CODE_BITS]);
+    }
+
+    #[cfg(feature = "header-tables")]
+    fn codeword(&self) -> [u32; 3] {
         let table = match self.rate {
             CodeRate::OneThird => &RATE_ONE_THIRD,
             CodeRate::OneHalf => &RATE_ONE_HALF,
         };
-        let index = self.data_length - 12;
-        let entry = table[index].view_bits::<Msb0>();
+        table[self.data_length - MIN_DATA_LENGTH]
+    }
+
+    #[cfg(not(feature = "header-tables"))]
+    fn codeword(&self) -> [u32; 3] {
+        self.encode()
+    }
 
-        writer.extend_from_bitslice(&entry[2..32 + 32 + 20]);
+    /// Encode the header from the generator definition of the code
+    #[cfg_attr(all(feature = "header-tables", not(test)), allow(dead_code))]
+    pub const fn encode(&self) -> [u32; 3] {
+        let mut codeword = BASE;
+
+        let mut bit = 0;
+        while bit < 8 {
+            if (self.data_length >> bit) & 1 == 1 {
+                codeword = xor(codeword, LENGTH_GENERATORS[bit]);
+            }
+            bit += 1;
+        }
+
+        if matches!(self.rate, CodeRate::OneThird) {
+            codeword = xor(codeword, RATE_GENERATOR);
+        }
+
+        codeword
     }
 }
 
+const fn xor(first: [u32; 3], second: [u32; 3]) -> [u32; 3] {
+    [
+        first[0] ^ second[0],
+        first[1] ^ second[1],
+        first[2] ^ second[2],
+    ]
+}
+
+/// The number of code bits following the two padding bits
+pub const CODE_BITS: usize = 82;
+
+const MIN_DATA_LENGTH: usize = 12;
+const MAX_DATA_LENGTH: usize = 251;
+
+/// The number of information bits in the coded header, i.e. eight length bits and the rate bit
+const INFO_BITS: u32 = 9;
+
+/// The minimum distance of the header code is 22, so a received header
+/// within this distance of a codeword is closer to it than to any other codeword
+const UNIQUE_DISTANCE: usize = 10;
+
+// The coded header is an affine code with the data length and the code rate as information bits.
+// A codeword is stored as three words holding the two one bits that precede the header,
+// the 82 code bits and twelve unused zero bits. The first eight code bits are the data length
+// (msb first), the last code bit is set for rate 1/3, and the remaining bits are parity.
+// The codeword is the base word xor'ed with the generator of each information bit that is set.
+
+#[rustfmt::skip]
+#[allow(clippy::unusual_byte_groupings)]
+const BASE: [u32; 3] =
+    [0b11_000000000000000001111001000001, 0b11100101110111100101110010010010, 0b01001011110111010000_000000000000];
+
+/// The generators for the data length bits, lsb first
+#[rustfmt::skip]
+#[allow(clippy::unusual_byte_groupings)]
+const LENGTH_GENERATORS: [[u32; 3]; 8] = [
+    [0b00_000000010000000111100101000111, 0b10010111000000000000000001000011, 0b00100101100111010000_000000000000],
+    [0b00_000000100000001111001011000000, 0b11110010110000001111001011000011, 0b10011110011110010000_000000000000],
+    [0b00_000001000000011110010111011110, 0b01011100100000000000000111000010, 0b11110111011110010000_000000000000],
+    [0b00_000010000000111100101110000011, 0b11001011100000111100101110000000, 0b10110010110010110000_000000000000],
+    [0b00_000100000001111001011100000000, 0b00000000010000000000011110000001, 0b01100111010010110000_000000000000],
+    [0b00_001000000011110010111001001111, 0b00101110010011110010111001000010, 0b01001001001001000000_000000000000],
+    [0b00_010000000111100101110010000000, 0b00000001110000000001111001000001, 0b11011110011001000000_000000000000],
+    [0b00_100000001111001011100101111100, 0b10111001011111001011100101000011, 0b00101100101100100000_000000000000],
+];
+
+#[rustfmt::skip]
+#[allow(clippy::unusual_byte_groupings)]
+const RATE_GENERATOR: [u32; 3] =
+    [0b00_000000000000000000000001000000, 0b00000111100000000111100101000001, 0b11010010111100100001_000000000000];
+
+#[cfg(feature = "header-tables")]
 #[rustfmt::skip]
 #[allow(clippy::unusual_byte_groupings)]
 const RATE_ONE_HALF: [[u32; 3]; 240] = [
@@ -337,6 +532,7 @@ const RATE_ONE_HALF: [[u32; 3]; 240] = [
     [ 0b11_11111011_1010010000001011110110, 0b11011101001011101110101101010011, 0b10011110010010110000_000000000000 ],
 ];
 
+#[cfg(feature = "header-tables")]
 #[rustfmt::skip]
 #[allow(clippy::unusual_byte_groupings)]
 const RATE_ONE_THIRD: [[u32; 3]; 240] = [
@@ -581,3 +777,261 @@ const RATE_ONE_THIRD: [[u32; 3]; 240] = [
     [ 0b11_11111010_1010010111101111110001, 0b01001101101011101001001001010001, 0b01101001001001000001_000000000000 ],
     [ 0b11_11111011_1010010000001010110110, 0b11011010101011101001001000010010, 0b01001100101110010001_000000000000 ],
 ];
+
+#[cfg(test)]
+mod tests {
+    extern crate test;
+
+    use rand::{Rng, SeedableRng};
+    use test::Bencher;
+
+    use super::*;
+
+    /// Flip a bit in a codeword given as three words where codeword bit 0 is bit 29 of the first word
+    fn flip(codeword: &mut [u32; 3], position: usize) {
+        match position {
+            0..30 => codeword[0] ^= 1 << (29 - position),
+            30..62 => codeword[1] ^= 1 << (31 - (position - 30)),
+            _ => codeword[2] ^= 1 << (31 - (position - 62)),
+        }
+    }
+
+    fn decode(codeword: &[u32; 3]) -> (PhyCodedHeader, usize) {
+        let [first, second, third] = *codeword;
+        PhyCodedHeader::find_close_codeword(first, second, third)
+            .unwrap_or_else(|| PhyCodedHeader::scan(first, second, third))
+    }
+
+    fn codewords() -> impl Iterator<Item = (PhyCodedHeader, [u32; 3])> {
+        [CodeRate::OneHalf, CodeRate::OneThird]
+            .into_iter()
+            .flat_map(|rate| {
+                (MIN_DATA_LENGTH..=MAX_DATA_LENGTH).map(move |data_length| {
+                    let header = PhyCodedHeader::new(rate, data_length);
+                    (header, header.encode())
+                })
+            })
+    }
+
+    #[cfg(feature = "header-tables")]
+    #[test]
+    fn can_encode_as_tables() {
+        for data_length in MIN_DATA_LENGTH..=MAX_DATA_LENGTH {
+            let index = data_length - MIN_DATA_LENGTH;
+            assert_eq!(
+                RATE_ONE_HALF[index],
+                PhyCodedHeader::new(CodeRate::OneHalf, data_length).encode()
+            );
+            assert_eq!(
+                RATE_ONE_THIRD[index],
+                PhyCodedHeader::new(CodeRate::OneThird, data_length).encode()
+            );
+        }
+    }
+
+    #[test]
+    fn has_minimum_distance_beyond_unique_distance() {
+        // Given
+        let headers: Vec<PhyCodedHeader, 512> = [CodeRate::OneHalf, CodeRate::OneThird]
+            .into_iter()
+            .flat_map(|rate| {
+                (0..256).map(move |data_length| PhyCodedHeader::new(rate, data_length))
+            })
+            .collect();
+
+        // When
+        let min_distance = headers
+            .iter()
+            .enumerate()
+            .flat_map(|(index, header)| {
+                let [first, second, third] = header.encode();
+                headers[..index]
+                    .iter()
+                    .map(move |other| other.distance(first, second, third))
+            })
+            .min()
+            .unwrap();
+
+        // Then
+        assert_eq!(22, min_distance);
+        assert!(2 * UNIQUE_DISTANCE < min_distance);
+    }
+
+    #[test]
+    fn can_decode_all_codewords_with_up_to_two_errors() {
+        for (header, codeword) in codewords() {
+            // No errors
+            assert_eq!((header, 0), decode(&codeword));
+
+            for first in 0..82 {
+                let mut received = codeword;
+                flip(&mut received, first);
+                assert_eq!((header, 1), decode(&received));
+
+                for second in 0..first {
+                    let mut received = received;
+                    flip(&mut received, second);
+                    assert_eq!((header, 2), decode(&received));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn can_decode_same_as_scan_with_many_errors() {
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+
+        for errors in 3..=20 {
+            for (_, codeword) in codewords() {
+                // Given
+                let mut received = codeword;
+                for position in rand::seq::index::sample(&mut rng, 82, errors) {
+                    flip(&mut received, position);
+                }
+                let [first, second, third] = received;
+
+                // When
+                let found = decode(&received);
+
+                // Then
+                assert_eq!(PhyCodedHeader::scan(first, second, third), found);
+            }
+        }
+
+        // Random words far from any codeword
+        for _ in 0..1000 {
+            let received = [
+                0xC0000000 | (rng.random::<u32>() >> 2),
+                rng.random(),
+                rng.random::<u32>() & 0xFFFFF000,
+            ];
+            let [first, second, third] = received;
+            assert_eq!(PhyCodedHeader::scan(first, second, third), decode(&received));
+        }
+    }
+
+    fn soft(header: &PhyCodedHeader) -> [Llr; CODE_BITS] {
+        let codeword = header.encode();
+        let bits = &codeword.view_bits::<Msb0>()[2..2 + CODE_BITS];
+        core::array::from_fn(|index| if bits[index] { 4 } else { -4 })
+    }
+
+    #[test]
+    fn can_read_soft() {
+        // Given
+        let header = PhyCodedHeader::new(CodeRate::OneHalf, 42);
+        let mut llrs = soft(&header);
+
+        // Invert hard decisions with weak llrs beyond the hard decoding radius
+        for index in (0..CODE_BITS).step_by(5) {
+            llrs[index] = -llrs[index] / 4;
+        }
+
+        // When
+        let (found, reliability) = PhyCodedHeader::read_soft(&llrs).unwrap();
+
+        // Then
+        assert_eq!(header, found);
+        assert!(reliability > 0);
+        assert_eq!(17, header.hard_distance(&llrs));
+    }
+
+    #[test]
+    fn can_read_soft_from_partial_input() {
+        // Given
+        let header = PhyCodedHeader::new(CodeRate::OneThird, 200);
+        let llrs = soft(&header);
+
+        // When
+        let (found, reliability) = PhyCodedHeader::read_soft(&llrs[..60]).unwrap();
+
+        // Then
+        assert_eq!(header, found);
+        assert!(reliability > 0);
+    }
+
+    #[test]
+    fn can_not_read_soft_without_input() {
+        assert_eq!(None, PhyCodedHeader::read_soft(&[]));
+    }
+
+    #[bench]
+    fn bench_read_scan(b: &mut Bencher) {
+        let codeword = with_errors(PhyCodedHeader::new(CodeRate::OneThird, 212).encode());
+        b.iter(|| {
+            let [first, second, third] = test::black_box(codeword);
+            PhyCodedHeader::scan(first, second, third)
+        });
+    }
+
+    #[bench]
+    fn bench_read_fast(b: &mut Bencher) {
+        let codeword = with_errors(PhyCodedHeader::new(CodeRate::OneThird, 212).encode());
+        b.iter(|| decode(&test::black_box(codeword)));
+    }
+
+    fn with_errors(mut codeword: [u32; 3]) -> [u32; 3] {
+        for position in [3, 17, 40, 66, 80] {
+            flip(&mut codeword, position);
+        }
+        codeword
+    }
+
+    fn codeword(header: &PhyCodedHeader) -> BitBuffer<12> {
+        let mut bits = BitBuffer::new();
+        bits.push(true);
+        bits.push(true);
+        header.write(&mut bits);
+        bits.resize(96, false);
+        bits
+    }
+
+    fn reader(bits: &BitBuffer<12>) -> BitReader<'_, u8, Msb0> {
+        let mut reader = BitReader::from_slice(bits.as_raw_slice());
+        reader.read_bits::<usize>(2).unwrap(); // Discard the two padding bits
+        reader
+    }
+
+    #[test]
+    fn can_read_candidates_in_order() {
+        // Given
+        let header = PhyCodedHeader::new(CodeRate::OneThird, 100);
+        let mut bits = codeword(&header);
+        for index in [10, 30, 50] {
+            let bit = bits.as_bitslice()[index];
+            bits.as_mut_bitslice().set(index, !bit);
+        }
+
+        // When
+        let candidates = PhyCodedHeader::read_candidates::<_, 3>(&mut reader(&bits)).unwrap();
+
+        // Then
+        assert_eq!(3, candidates.len());
+        assert_eq!(header, candidates[0].0);
+        assert_eq!(3, candidates[0].1);
+        assert!(candidates[1].1 >= 22 - 3);
+        assert!(candidates[1].1 <= candidates[2].1);
+    }
+
+    #[test]
+    fn can_rank_first_candidate_as_read() {
+        for length in 12..=251 {
+            for rate in [CodeRate::OneHalf, CodeRate::OneThird] {
+                // Given
+                let mut bits = codeword(&PhyCodedHeader::new(rate, length));
+                for index in (2..84).step_by(7) {
+                    let bit = bits.as_bitslice()[index];
+                    bits.as_mut_bitslice().set(index, !bit);
+                }
+
+                // When
+                let read = PhyCodedHeader::read(&mut reader(&bits)).unwrap();
+                let candidates =
+                    PhyCodedHeader::read_candidates::<_, 1>(&mut reader(&bits)).unwrap();
+
+                // Then
+                assert_eq!(read, candidates[0]);
+            }
+        }
+    }
+}
@@ -0,0 +1,106 @@
+use core::f64::consts::PI;
+
+use bitvec::prelude::*;
+use rand::{Rng, RngCore};
+
+use crate::fec::{CodeRate, Llr};
+
+use super::{ChannelModel, Received};
+
+/// Additive white gaussian noise channel with antipodal signaling
+pub struct AwgnChannel {
+    /// The energy per information bit to noise power spectral density ratio in dB
+    pub ebn0_db: f64,
+    /// The scale applied to the channel llrs before they are quantized
+    pub llr_scale: f64,
+    /// Whether the receiver outputs soft bits or hard decisions
+    pub soft: bool,
+}
+
+impl AwgnChannel {
+    /// Create a new channel with soft bit output
+    pub const fn new(ebn0_db: f64) -> Self {
+        Self {
+            ebn0_db,
+            llr_scale: 1.0,
+            soft: true,
+        }
+    }
+
+    /// Create a new channel with hard decision output
+    pub const fn hard(ebn0_db: f64) -> Self {
+        Self {
+            ebn0_db,
+            llr_scale: 1.0,
+            soft: false,
+        }
+    }
+
+    fn sigma(&self, rate: CodeRate) -> f64 {
+        let rate = match rate {
+            CodeRate::OneThird => 1.0 / 3.0,
+            CodeRate::OneHalf => 1.0 / 2.0,
+        };
+        let esn0 = 10f64.powf(self.ebn0_db / 10.0) * rate;
+        (1.0 / (2.0 * esn0)).sqrt()
+    }
+}
+
+impl ChannelModel for AwgnChannel {
+    fn transmit(&mut self, rng: &mut dyn RngCore, frame: &[u8], rate: CodeRate) -> Received {
+        let sigma = self.sigma(rate);
+        let samples = frame.view_bits::<Msb0>().iter().map(|bit| {
+            let symbol = if *bit { 1.0 } else { -1.0 };
+            symbol + sigma * gaussian(rng)
+        });
+
+        if self.soft {
+            let scale = self.llr_scale * 2.0 / (sigma * sigma);
+            Received::Soft(samples.map(|sample| quantize(scale * sample)).collect())
+        } else {
+            let mut received = vec![0; frame.len()];
+            let bits = received.view_bits_mut::<Msb0>();
+            for (mut bit, sample) in bits.iter_mut().zip(samples) {
+                *bit = sample > 0.0;
+            }
+            Received::Hard(received)
+        }
+    }
+}
+
+/// Draw a sample from the standard normal distribution using the Box-Muller transform
+fn gaussian(rng: &mut dyn RngCore) -> f64 {
+    let u1 = 1.0 - rng.random::<f64>(); // Avoid zero
+    let u2 = rng.random::<f64>();
+    (-2.0 * u1.ln()).sqrt() * (2.0 * PI * u2).cos()
+}
+
+fn quantize(llr: f64) -> Llr {
+    llr.round().clamp(Llr::MIN as f64, Llr::MAX as f64) as Llr
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn can_transmit_at_high_snr() {
+        // Given
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+        let mut channel = AwgnChannel::new(20.0);
+        let frame = [0x12, 0x34, 0x56, 0x78];
+
+        // When
+        let received = channel.transmit(&mut rng, &frame, CodeRate::OneThird);
+
+        // Then
+        let Received::Soft(llrs) = &received else {
+            panic!("Expected soft bits");
+        };
+        assert_eq!(8 * frame.len(), llrs.len());
+        assert!(llrs.iter().all(|llr| *llr != 0));
+        assert_eq!(frame.as_slice(), received.to_bytes());
+    }
+}
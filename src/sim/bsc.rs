@@ -0,0 +1,70 @@
+use bitvec::prelude::*;
+use rand::{Rng, RngCore};
+
+use crate::fec::CodeRate;
+
+use super::{ChannelModel, Received};
+
+/// Binary symmetric channel where each bit is flipped independently with probability `ber`
+pub struct BinarySymmetricChannel {
+    pub ber: f64,
+}
+
+impl BinarySymmetricChannel {
+    pub const fn new(ber: f64) -> Self {
+        Self { ber }
+    }
+}
+
+impl ChannelModel for BinarySymmetricChannel {
+    fn transmit(&mut self, rng: &mut dyn RngCore, frame: &[u8], _rate: CodeRate) -> Received {
+        let mut received = frame.to_vec();
+        flip_bits(rng, &mut received, self.ber);
+        Received::Hard(received)
+    }
+}
+
+pub(crate) fn flip_bits(rng: &mut dyn RngCore, bytes: &mut [u8], ber: f64) {
+    if ber > 0.0 {
+        for mut bit in bytes.view_bits_mut::<Msb0>().iter_mut() {
+            if rng.random::<f64>() < ber {
+                *bit = !*bit;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn can_transmit_without_errors() {
+        // Given
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+        let mut channel = BinarySymmetricChannel::new(0.0);
+        let frame = [0x12, 0x34, 0x56, 0x78];
+
+        // When
+        let received = channel.transmit(&mut rng, &frame, CodeRate::OneHalf);
+
+        // Then
+        assert_eq!(frame.as_slice(), received.to_bytes());
+    }
+
+    #[test]
+    fn can_flip_all_bits() {
+        // Given
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+        let mut channel = BinarySymmetricChannel::new(1.0);
+        let frame = [0x12, 0x34, 0x56, 0x78];
+
+        // When
+        let received = channel.transmit(&mut rng, &frame, CodeRate::OneHalf);
+
+        // Then
+        assert_eq!([0xED, 0xCB, 0xA9, 0x87].as_slice(), received.to_bytes());
+    }
+}
@@ -0,0 +1,77 @@
+use bitvec::prelude::*;
+use rand::{Rng, RngCore};
+
+use crate::fec::CodeRate;
+
+use super::{ChannelModel, Received};
+
+/// Burst error channel based on the two-state Gilbert-Elliott model.
+///
+/// The channel is either in the good or the bad state, and each state has its own bit error rate.
+/// Every frame starts in the good state.
+pub struct BurstChannel {
+    /// The probability per bit of moving from the good to the bad state
+    pub good_to_bad: f64,
+    /// The probability per bit of moving from the bad to the good state
+    pub bad_to_good: f64,
+    /// The bit error rate in the good state
+    pub good_ber: f64,
+    /// The bit error rate in the bad state
+    pub bad_ber: f64,
+}
+
+impl BurstChannel {
+    /// Create a channel without errors outside bursts where bits are random within a burst.
+    /// `burst_probability` is the probability that a burst starts at any given bit.
+    pub fn new(burst_probability: f64, mean_burst_length: f64) -> Self {
+        Self {
+            good_to_bad: burst_probability,
+            bad_to_good: 1.0 / mean_burst_length,
+            good_ber: 0.0,
+            bad_ber: 0.5,
+        }
+    }
+}
+
+impl ChannelModel for BurstChannel {
+    fn transmit(&mut self, rng: &mut dyn RngCore, frame: &[u8], _rate: CodeRate) -> Received {
+        let mut received = frame.to_vec();
+        let mut bad = false;
+
+        for mut bit in received.view_bits_mut::<Msb0>().iter_mut() {
+            bad = if bad {
+                rng.random::<f64>() >= self.bad_to_good
+            } else {
+                rng.random::<f64>() < self.good_to_bad
+            };
+
+            let ber = if bad { self.bad_ber } else { self.good_ber };
+            if rng.random::<f64>() < ber {
+                *bit = !*bit;
+            }
+        }
+
+        Received::Hard(received)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::SeedableRng;
+
+    use super::*;
+
+    #[test]
+    fn can_transmit_without_bursts() {
+        // Given
+        let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+        let mut channel = BurstChannel::new(0.0, 16.0);
+        let frame = [0x12, 0x34, 0x56, 0x78];
+
+        // When
+        let received = channel.transmit(&mut rng, &frame, CodeRate::OneHalf);
+
+        // Then
+        assert_eq!(frame.as_slice(), received.to_bytes());
+    }
+}
@@ -0,0 +1,81 @@
+//! Channel models and frame error rate simulation.
+//!
+//! The channel models corrupt encoded frames the way a radio link would,
+//! and [`Sweep`] runs the frames through the [`Stack`](crate::stack::Stack) decoder
+//! to produce frame error rate curves.
+//!
+//! With the `ctrl` feature, [`Network`] simulates many meters transmitting to a single gateway
+//! where a [`Controller`](crate::ctrl::Controller) receives the frames from a virtual radio.
+
+mod awgn;
+mod bsc;
+mod burst;
+#[cfg(feature = "ctrl")]
+mod network;
+mod runner;
+
+use rand::{Rng, RngCore};
+use wmbus::WMBusAddress;
+
+use crate::{
+    fec::{CodeRate, Llr},
+    stack::{mbal::MbalFunctionCode, Packet, PacketBuilder},
+};
+
+pub use awgn::AwgnChannel;
+pub use bsc::BinarySymmetricChannel;
+pub use burst::BurstChannel;
+#[cfg(feature = "ctrl")]
+pub use network::{Meter, MeterReport, Network, NetworkReport, RadioConfig};
+pub use runner::{Sweep, SweepPoint};
+
+/// A frame as seen by the receiver after it has passed through a channel
+pub enum Received {
+    /// Hard decision frame bytes
+    Hard(Vec<u8>),
+    /// One llr per frame bit in the order they are transmitted.
+    /// A positive value is in favor of a one bit.
+    Soft(Vec<Llr>),
+}
+
+/// Channel model
+pub trait ChannelModel {
+    /// Pass a transmitted frame through the channel
+    fn transmit(&mut self, rng: &mut dyn RngCore, frame: &[u8], rate: CodeRate) -> Received;
+}
+
+impl Received {
+    /// Get the hard decision frame bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            Received::Hard(bytes) => bytes.clone(),
+            Received::Soft(llrs) => llrs
+                .chunks(8)
+                .map(|byte| {
+                    byte.iter()
+                        .fold(0, |value, llr| (value << 1) | (*llr > 0) as u8)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Create an SND-NR packet with a random payload
+pub(crate) fn random_packet(
+    rng: &mut impl RngCore,
+    address: WMBusAddress,
+    code_rate: CodeRate,
+    payload_length: usize,
+) -> Packet {
+    let mut payload = vec![0; payload_length];
+    rng.fill_bytes(&mut payload);
+
+    PacketBuilder::new()
+        .code_rate(code_rate)
+        .prioritized(rng.random())
+        .address(address)
+        .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+        .mbus_data(&payload)
+        .build()
+        .unwrap()
+}
@@ -0,0 +1,591 @@
+use core::{
+    cell::RefCell,
+    future::poll_fn,
+    task::{Poll, Waker},
+};
+use std::rc::Rc;
+
+use embassy_time::{Duration, Instant};
+use futures::{
+    future::{select, Either},
+    pin_mut, StreamExt,
+};
+use num_traits::FromPrimitive;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use wmbus::WMBusAddress;
+
+use crate::{
+    ctrl::{
+        traits::{RxToken, Transceiver},
+        Controller,
+    },
+    fec::CodeRate,
+    stack::{phl, Channel, Rssi, Stack},
+};
+
+use super::random_packet;
+
+/// A meter that periodically transmits SND-NR frames on a random channel
+#[derive(Clone)]
+pub struct Meter {
+    pub address: WMBusAddress,
+    /// The nominal interval between transmissions
+    pub interval: Duration,
+    /// The signal strength of the meter at the gateway
+    pub rssi: Rssi,
+    pub code_rate: CodeRate,
+    /// The number of application bytes in each frame
+    pub payload_length: usize,
+}
+
+/// Virtual radio configuration
+#[derive(Clone)]
+pub struct RadioConfig {
+    /// The over the air bit rate
+    pub bitrate: u32,
+    /// The number of preamble and sync word bits transmitted before each frame
+    pub preamble_bits: u32,
+    /// The time it takes to measure the rssi
+    pub rssi_time: Duration,
+    /// The time it takes to switch channel
+    pub channel_switch_time: Duration,
+    /// The rssi reported when no frame can be detected.
+    /// This should be below the initial noise floor of the controller.
+    pub noise_level: Rssi,
+    /// The minimum signal to interference ratio in dB for a frame to survive a collision
+    pub capture_threshold: Rssi,
+}
+
+/// Simulation of many meters transmitting to a single gateway
+pub struct Network {
+    pub meters: Vec<Meter>,
+    pub radio: RadioConfig,
+    /// The maximum deviation from the nominal transmit interval as a fraction of the interval
+    pub jitter: f64,
+    pub seed: u64,
+}
+
+/// The result of a network simulation
+pub struct NetworkReport {
+    pub meters: Vec<MeterReport>,
+}
+
+/// The result for a single meter in a network simulation
+#[derive(Clone)]
+pub struct MeterReport {
+    pub address: WMBusAddress,
+    pub rssi: Rssi,
+    /// The number of frames transmitted by the meter
+    pub sent: usize,
+    /// The number of frames successfully decoded by the gateway
+    pub received: usize,
+}
+
+struct Transmission {
+    channel: Channel,
+    /// The start of the preamble in µs
+    start: u64,
+    /// The start of the frame in µs
+    sync: u64,
+    /// The end of the frame in µs
+    end: u64,
+    rssi: Rssi,
+    frame: Vec<u8>,
+}
+
+/// The transmissions on air and the simulation time
+struct Air {
+    /// All transmissions ordered by their start time
+    transmissions: Vec<Transmission>,
+    /// The current simulation time in µs
+    now: u64,
+    /// The simulation end time in µs
+    end: u64,
+    /// The waker of the run, which is woken when the end time is reached
+    end_waker: Option<Waker>,
+}
+
+/// A gateway radio that receives the transmissions on air.
+///
+/// Reading the rssi and switching channel advances the simulation time,
+/// and receiving a frame advances the time to the end of the frame.
+/// A frame can only be detected while its preamble is on air on the current channel.
+struct VirtualRadio {
+    air: Rc<RefCell<Air>>,
+    config: RadioConfig,
+    rng: StdRng,
+    channel: Channel,
+    detected: Option<usize>,
+    receiving: Option<usize>,
+}
+
+struct VirtualRxToken(Instant);
+
+impl Default for RadioConfig {
+    fn default() -> Self {
+        Self {
+            bitrate: 19_200,
+            preamble_bits: 64,
+            rssi_time: Duration::from_micros(250),
+            channel_switch_time: Duration::from_micros(150),
+            noise_level: -115,
+            capture_threshold: 6,
+        }
+    }
+}
+
+impl RadioConfig {
+    /// Get the time in µs from the start of the preamble to the end of a frame
+    pub fn airtime(&self, frame_length: usize) -> u64 {
+        self.bits_time(self.preamble_bits as u64 + 8 * frame_length as u64)
+    }
+
+    fn bits_time(&self, bits: u64) -> u64 {
+        bits * 1_000_000 / self.bitrate as u64
+    }
+}
+
+impl Network {
+    /// Create a new network simulation
+    pub fn new(meters: Vec<Meter>) -> Self {
+        Self {
+            meters,
+            radio: RadioConfig::default(),
+            jitter: 0.1,
+            seed: 0,
+        }
+    }
+
+    /// Create a network of meters with random signal strength, code rate and payload length
+    pub fn random(meter_count: usize, interval: Duration, seed: u64) -> Self {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let meters = (0..meter_count)
+            .map(|index| Meter {
+                address: WMBusAddress::new(
+                    0x2c2d.try_into().unwrap(),
+                    index as u32 + 1,
+                    0x01,
+                    0x07.try_into().unwrap(),
+                ),
+                interval,
+                rssi: rng.random_range(-115..=-70),
+                code_rate: if rng.random() {
+                    CodeRate::OneHalf
+                } else {
+                    CodeRate::OneThird
+                },
+                payload_length: rng.random_range(16..=64),
+            })
+            .collect();
+
+        Self {
+            seed,
+            ..Self::new(meters)
+        }
+    }
+
+    /// Run the simulation.
+    /// The controller uses timeouts in real time, so an embassy time driver must be available.
+    pub async fn run(&self, duration: Duration) -> NetworkReport {
+        let stack = Stack::new();
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let (transmissions, sent) = self.schedule(&stack, &mut rng, duration.as_micros());
+
+        let air = Rc::new(RefCell::new(Air {
+            transmissions,
+            now: 0,
+            end: duration.as_micros(),
+            end_waker: None,
+        }));
+        let radio = VirtualRadio {
+            air: air.clone(),
+            config: self.radio.clone(),
+            rng: StdRng::seed_from_u64(rng.random()),
+            channel: Channel::A,
+            detected: None,
+            receiving: None,
+        };
+
+        let mut controller = Controller::new(radio);
+        controller.init().await.unwrap();
+
+        let mut received = vec![0; self.meters.len()];
+        {
+            let stream = controller.receive().await.unwrap();
+            pin_mut!(stream);
+
+            loop {
+                let ended = poll_fn(|cx| {
+                    let mut air = air.borrow_mut();
+                    if air.is_ended() {
+                        Poll::Ready(())
+                    } else {
+                        air.end_waker = Some(cx.waker().clone());
+                        Poll::Pending
+                    }
+                });
+                pin_mut!(ended);
+
+                match select(stream.next(), ended).await {
+                    Either::Left((Some(Ok(frame)), _)) => {
+                        if let Ok(packet) = stack.read(frame.bytes())
+                            && let Some(mbal) = packet.mbal
+                            && let Some(index) = self
+                                .meters
+                                .iter()
+                                .position(|meter| meter.address == mbal.address)
+                        {
+                            received[index] += 1;
+                        }
+                    }
+                    _ => break,
+                }
+            }
+        }
+
+        controller.idle().await.unwrap();
+
+        NetworkReport {
+            meters: self
+                .meters
+                .iter()
+                .enumerate()
+                .map(|(index, meter)| MeterReport {
+                    address: meter.address.clone(),
+                    rssi: meter.rssi,
+                    sent: sent[index],
+                    received: received[index],
+                })
+                .collect(),
+        }
+    }
+
+    fn schedule(
+        &self,
+        stack: &Stack,
+        rng: &mut StdRng,
+        end: u64,
+    ) -> (Vec<Transmission>, Vec<usize>) {
+        let mut transmissions = Vec::new();
+        let mut sent = vec![0; self.meters.len()];
+
+        for (index, meter) in self.meters.iter().enumerate() {
+            let interval = meter.interval.as_micros() as f64;
+            let mut start = rng.random::<f64>() * interval;
+
+            loop {
+                let packet = random_packet(
+                    rng,
+                    meter.address.clone(),
+                    meter.code_rate,
+                    meter.payload_length,
+                );
+                let mut frame = Vec::<u8>::new();
+                stack.write(&mut frame, &packet).unwrap();
+
+                let start_us = start as u64;
+                let end_us = start_us + self.radio.airtime(frame.len());
+                if end_us > end {
+                    break;
+                }
+
+                transmissions.push(Transmission {
+                    channel: Channel::from_u8(rng.random_range(0..4)).unwrap(),
+                    start: start_us,
+                    sync: start_us + self.radio.bits_time(self.radio.preamble_bits as u64),
+                    end: end_us,
+                    rssi: meter.rssi,
+                    frame,
+                });
+                sent[index] += 1;
+
+                start += interval * (1.0 + self.jitter * (2.0 * rng.random::<f64>() - 1.0));
+            }
+        }
+
+        transmissions.sort_by_key(|tx| tx.start);
+        (transmissions, sent)
+    }
+}
+
+impl NetworkReport {
+    /// Get the total number of transmitted frames
+    pub fn sent(&self) -> usize {
+        self.meters.iter().map(|meter| meter.sent).sum()
+    }
+
+    /// Get the total number of received frames
+    pub fn received(&self) -> usize {
+        self.meters.iter().map(|meter| meter.received).sum()
+    }
+
+    /// Get the overall reception probability
+    pub fn reception_probability(&self) -> f64 {
+        self.received() as f64 / self.sent() as f64
+    }
+}
+
+impl MeterReport {
+    /// Get the reception probability for the meter
+    pub fn reception_probability(&self) -> f64 {
+        self.received as f64 / self.sent as f64
+    }
+}
+
+impl Air {
+    fn is_ended(&self) -> bool {
+        self.now >= self.end
+    }
+
+    /// Advance the simulation time and wake the run when the end time is reached
+    fn advance_to(&mut self, now: u64) {
+        self.now = now;
+        if self.is_ended()
+            && let Some(waker) = self.end_waker.take()
+        {
+            waker.wake();
+        }
+    }
+
+    /// Get the index of the first transmission that starts at or after `time`
+    fn starting_from(&self, time: u64) -> usize {
+        self.transmissions.partition_point(|tx| tx.start < time)
+    }
+
+    /// Get the index of the first transmission that starts after `time`
+    fn starting_after(&self, time: u64) -> usize {
+        self.transmissions.partition_point(|tx| tx.start <= time)
+    }
+}
+
+impl VirtualRadio {
+    fn detect(&self, air: &Air) -> Option<usize> {
+        let preamble_time = self.config.bits_time(self.config.preamble_bits as u64);
+        let first = air.starting_from(air.now.saturating_sub(preamble_time));
+        let last = air.starting_after(air.now);
+
+        (first..last)
+            .filter(|&index| {
+                let tx = &air.transmissions[index];
+                tx.channel == self.channel && air.now < tx.sync
+            })
+            .max_by_key(|&index| air.transmissions[index].rssi)
+    }
+
+    /// Get the frame as received where bytes that collide with stronger transmissions are lost
+    fn receive_frame(&mut self, air: &Air, index: usize) -> Vec<u8> {
+        let tx = &air.transmissions[index];
+        let mut frame = tx.frame.clone();
+
+        let max_airtime = self.config.airtime(phl::MAX_FRAME_LENGTH);
+        let first = air.starting_from(tx.start.saturating_sub(max_airtime));
+        let last = air.starting_after(tx.end);
+
+        for other in (first..last).filter(|&other| other != index) {
+            let other = &air.transmissions[other];
+            if other.channel != tx.channel
+                || other.end <= tx.sync
+                || tx.rssi - other.rssi >= self.config.capture_threshold
+            {
+                continue;
+            }
+
+            let from = other.start.max(tx.sync) - tx.sync;
+            let to = other.end.min(tx.end) - tx.sync;
+            let bits_per_second = self.config.bitrate as u64;
+            let first_byte = (from * bits_per_second / 8_000_000) as usize;
+            let last_byte = (to * bits_per_second).div_ceil(8_000_000) as usize;
+
+            for byte in frame[first_byte..last_byte.min(tx.frame.len())].iter_mut() {
+                *byte = self.rng.random();
+            }
+        }
+
+        frame
+    }
+}
+
+impl Transceiver for VirtualRadio {
+    type RxToken = VirtualRxToken;
+    type Error = ();
+
+    async fn init(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn set_channel(&mut self, channel: Channel) -> Result<(), Self::Error> {
+        self.channel = channel;
+        let mut air = self.air.borrow_mut();
+        let now = air.now + self.config.channel_switch_time.as_micros();
+        air.advance_to(now);
+        Ok(())
+    }
+
+    async fn write(&mut self, _buffer: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn transmit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn listen(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn get_rssi(&mut self) -> Result<Rssi, Self::Error> {
+        let mut air = self.air.borrow_mut();
+        let now = air.now + self.config.rssi_time.as_micros();
+        air.advance_to(now);
+
+        if air.is_ended() {
+            // Report a strong signal so that the controller waits for a frame
+            // that never arrives until the simulation is stopped
+            self.detected = None;
+            return Ok(0);
+        }
+
+        self.detected = self.detect(&air);
+        Ok(match self.detected {
+            Some(index) => air.transmissions[index].rssi,
+            None => self.config.noise_level,
+        })
+    }
+
+    async fn receive(&mut self, _min_frame_length: usize) -> Result<Self::RxToken, Self::Error> {
+        let Some(index) = self.detected.take() else {
+            return core::future::pending().await;
+        };
+
+        let mut air = self.air.borrow_mut();
+        let sync = air.transmissions[index].sync;
+        air.advance_to(sync);
+        self.receiving = Some(index);
+
+        Ok(VirtualRxToken(Instant::from_micros(sync)))
+    }
+
+    async fn read<'a>(
+        &'a mut self,
+        _token: &mut Self::RxToken,
+        buffer: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        let index = self.receiving.take().ok_or(())?;
+
+        let air = self.air.clone();
+        let mut air = air.borrow_mut();
+        let frame = self.receive_frame(&air, index);
+        let end = air.transmissions[index].end;
+        air.advance_to(end);
+
+        let length = frame.len().min(buffer.len());
+        buffer[..length].copy_from_slice(&frame[..length]);
+        Ok(length)
+    }
+
+    async fn accept(
+        &mut self,
+        _token: &mut Self::RxToken,
+        _frame_length: usize,
+    ) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    async fn idle(&mut self) -> Result<(), Self::Error> {
+        self.receiving = None;
+        Ok(())
+    }
+}
+
+impl RxToken for VirtualRxToken {
+    fn timestamp(&self) -> Instant {
+        self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn meter(id: u32, rssi: Rssi) -> Meter {
+        Meter {
+            address: WMBusAddress::new(
+                0x2c2d.try_into().unwrap(),
+                id,
+                0x01,
+                0x07.try_into().unwrap(),
+            ),
+            interval: Duration::from_secs(2),
+            rssi,
+            code_rate: CodeRate::OneHalf,
+            payload_length: 20,
+        }
+    }
+
+    #[tokio::test]
+    async fn can_receive_sparse_meters() {
+        // Given
+        let network = Network::new(vec![meter(1, -80), meter(2, -85), meter(3, -90)]);
+
+        // When
+        let report = network.run(Duration::from_secs(20)).await;
+
+        // Then
+        assert!(report.sent() >= 27);
+        assert!(report.reception_probability() > 0.8);
+    }
+
+    #[tokio::test]
+    async fn can_not_receive_meters_below_noise_floor() {
+        // Given
+        let network = Network::new(vec![meter(1, -120)]);
+
+        // When
+        let report = network.run(Duration::from_secs(10)).await;
+
+        // Then
+        assert!(report.meters[0].sent > 0);
+        assert_eq!(0, report.meters[0].received);
+    }
+
+    #[test]
+    fn can_lose_frame_in_collision() {
+        // Given
+        let radio = RadioConfig::default();
+        let tx = |start: u64, rssi: Rssi| Transmission {
+            channel: Channel::A,
+            start,
+            sync: start + radio.bits_time(radio.preamble_bits as u64),
+            end: start + radio.airtime(40),
+            rssi,
+            frame: vec![0; 40],
+        };
+        let air = Air {
+            transmissions: vec![tx(0, -80), tx(10_000, -82), tx(20_000, -60)],
+            now: 0,
+            end: 100_000,
+        };
+        let mut virtual_radio = VirtualRadio {
+            air: Rc::new(RefCell::new(Air {
+                transmissions: Vec::new(),
+                now: 0,
+                end: 0,
+            })),
+            config: radio.clone(),
+            rng: StdRng::seed_from_u64(0),
+            channel: Channel::A,
+            detected: None,
+            receiving: None,
+        };
+
+        // When
+        let first = virtual_radio.receive_frame(&air, 0);
+        let second = virtual_radio.receive_frame(&air, 1);
+        let third = virtual_radio.receive_frame(&air, 2);
+
+        // Then
+        assert_ne!(vec![0; 40], first);
+        assert_ne!(vec![0; 40], second);
+        assert_eq!(vec![0; 40], third); // Captured
+    }
+}
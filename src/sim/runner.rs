@@ -0,0 +1,265 @@
+use core::fmt;
+use std::io;
+
+use rand::{rngs::StdRng, SeedableRng};
+use wmbus::WMBusAddress;
+
+use crate::{
+    fec::CodeRate,
+    stack::{apl, phl, Stack},
+};
+
+use super::{random_packet, ChannelModel, Received};
+
+/// Frame error rate sweep over noise levels, payload lengths and code rates.
+///
+/// The channel used for each noise level is created by the channel factory given to [`Sweep::new`],
+/// e.g. `|ebn0_db| AwgnChannel::new(ebn0_db)`.
+pub struct Sweep<F> {
+    /// The noise levels passed to the channel factory
+    pub levels: Vec<f64>,
+    /// The number of application bytes in each simulated frame
+    pub payload_lengths: Vec<usize>,
+    pub code_rates: Vec<CodeRate>,
+    /// The number of frames simulated for each point
+    pub frames: usize,
+    pub seed: u64,
+    channel: F,
+}
+
+/// The result for a single point in a sweep
+#[derive(Debug, Clone)]
+pub struct SweepPoint {
+    pub level: f64,
+    pub code_rate: CodeRate,
+    pub payload_length: usize,
+    pub frames: usize,
+    pub frame_errors: usize,
+    pub header_failures: usize,
+    /// The total number of decode iterations for all successfully decoded frames
+    pub decode_iterations: usize,
+}
+
+impl<F, C> Sweep<F>
+where
+    F: Fn(f64) -> C,
+    C: ChannelModel,
+{
+    /// Create a new sweep using a channel factory that creates a channel for a given noise level
+    pub fn new(channel: F) -> Self {
+        Self {
+            levels: Vec::new(),
+            payload_lengths: vec![12, 48, 192],
+            code_rates: vec![CodeRate::OneHalf, CodeRate::OneThird],
+            frames: 1000,
+            seed: 0,
+            channel,
+        }
+    }
+
+    /// Run the sweep
+    pub fn run(&self, stack: &Stack) -> Vec<SweepPoint> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        let mut points = Vec::new();
+
+        for &code_rate in &self.code_rates {
+            for &payload_length in &self.payload_lengths {
+                for &level in &self.levels {
+                    let mut channel = (self.channel)(level);
+                    points.push(self.run_point(
+                        stack,
+                        &mut rng,
+                        &mut channel,
+                        level,
+                        code_rate,
+                        payload_length,
+                    ));
+                }
+            }
+        }
+
+        points
+    }
+
+    /// Run the sweep and write the result as csv including a header line
+    pub fn write_csv(&self, stack: &Stack, writer: &mut impl io::Write) -> io::Result<()> {
+        writeln!(writer, "{}", SweepPoint::CSV_HEADER)?;
+        for point in self.run(stack) {
+            writeln!(writer, "{}", point)?;
+        }
+        Ok(())
+    }
+
+    fn run_point(
+        &self,
+        stack: &Stack,
+        rng: &mut StdRng,
+        channel: &mut C,
+        level: f64,
+        code_rate: CodeRate,
+        payload_length: usize,
+    ) -> SweepPoint {
+        assert!(payload_length <= apl::MBUS_DATA_MAX);
+
+        let mut point = SweepPoint {
+            level,
+            code_rate,
+            payload_length,
+            frames: self.frames,
+            frame_errors: 0,
+            header_failures: 0,
+            decode_iterations: 0,
+        };
+
+        for _ in 0..self.frames {
+            let packet = random_packet(rng, address(), code_rate, payload_length);
+            let mut frame = Vec::<u8>::new();
+            stack.write(&mut frame, &packet).unwrap();
+
+            let received = channel.transmit(rng, &frame, code_rate);
+            if phl::get_frame_length(&received.to_bytes()).ok() != Some(frame.len()) {
+                point.header_failures += 1;
+            }
+
+            let result = match &received {
+                Received::Hard(bytes) => stack.read(bytes),
+                Received::Soft(llrs) => stack.read_soft(llrs),
+            };
+
+            match result {
+                Ok(decoded) if decoded.mbus_data == packet.mbus_data => {
+                    point.decode_iterations += decoded.rx.unwrap().decode_iterations;
+                }
+                _ => point.frame_errors += 1,
+            }
+        }
+
+        point
+    }
+}
+
+impl SweepPoint {
+    pub const CSV_HEADER: &'static str = "level,code_rate,payload_length,frames,frame_errors,fer,average_iterations,header_failures,header_failure_rate";
+
+    /// Get the frame error rate
+    pub fn fer(&self) -> f64 {
+        self.frame_errors as f64 / self.frames as f64
+    }
+
+    /// Get the average number of decode iterations for the successfully decoded frames.
+    /// This is zero if no frames were decoded.
+    pub fn average_iterations(&self) -> f64 {
+        let decoded = self.frames - self.frame_errors;
+        if decoded == 0 {
+            return 0.0;
+        }
+
+        self.decode_iterations as f64 / decoded as f64
+    }
+
+    /// Get the rate of frames where the coded header was not decoded correctly
+    pub fn header_failure_rate(&self) -> f64 {
+        self.header_failures as f64 / self.frames as f64
+    }
+}
+
+/// Format the point as a csv line matching [`SweepPoint::CSV_HEADER`]
+impl fmt::Display for SweepPoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code_rate = match self.code_rate {
+            CodeRate::OneThird => "1/3",
+            CodeRate::OneHalf => "1/2",
+        };
+        write!(
+            f,
+            "{},{},{},{},{},{},{},{},{}",
+            self.level,
+            code_rate,
+            self.payload_length,
+            self.frames,
+            self.frame_errors,
+            self.fer(),
+            self.average_iterations(),
+            self.header_failures,
+            self.header_failure_rate()
+        )
+    }
+}
+
+fn address() -> WMBusAddress {
+    WMBusAddress::new(
+        0x2c2d.try_into().unwrap(),
+        71006389,
+        0x34,
+        0x04.try_into().unwrap(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sim::BinarySymmetricChannel;
+
+    use super::*;
+
+    #[test]
+    fn can_run_sweep_without_errors() {
+        // Given
+        let stack = Stack::new();
+        let mut sweep = Sweep::new(BinarySymmetricChannel::new);
+        sweep.levels = vec![0.0];
+        sweep.payload_lengths = vec![20];
+        sweep.frames = 4;
+
+        // When
+        let points = sweep.run(&stack);
+
+        // Then
+        assert_eq!(2, points.len());
+        for point in points {
+            assert_eq!(0, point.frame_errors);
+            assert_eq!(0, point.header_failures);
+            assert_eq!(0.0, point.average_iterations());
+        }
+    }
+
+    #[test]
+    fn can_run_sweep_where_all_frames_fail() {
+        // Given
+        let stack = Stack::new();
+        let mut sweep = Sweep::new(BinarySymmetricChannel::new);
+        sweep.levels = vec![0.5];
+        sweep.payload_lengths = vec![20];
+        sweep.code_rates = vec![CodeRate::OneHalf];
+        sweep.frames = 2;
+
+        // When
+        let points = sweep.run(&stack);
+
+        // Then
+        assert_eq!(2, points[0].frame_errors);
+        assert_eq!(0.0, points[0].average_iterations());
+        assert!(!points[0].to_string().contains("NaN"));
+    }
+
+    #[test]
+    fn can_write_csv() {
+        // Given
+        let stack = Stack::new();
+        let mut sweep = Sweep::new(BinarySymmetricChannel::new);
+        sweep.levels = vec![0.0, 0.01];
+        sweep.payload_lengths = vec![20];
+        sweep.code_rates = vec![CodeRate::OneThird];
+        sweep.frames = 2;
+        let mut csv = Vec::new();
+
+        // When
+        sweep.write_csv(&stack, &mut csv).unwrap();
+
+        // Then
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<&str> = csv.lines().collect();
+        assert_eq!(3, lines.len());
+        assert_eq!(SweepPoint::CSV_HEADER, lines[0]);
+        assert!(lines[1].starts_with("0,1/3,20,2,"));
+    }
+}
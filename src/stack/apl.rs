@@ -13,6 +13,8 @@ impl Apl {
 }
 
 impl Layer for Apl {
+    type Extension = ();
+
     fn read<const N: usize>(&self, packet: &mut Packet<N>, buffer: &[u8]) -> Result<(), ReadError> {
         packet.mbus_data = Vec::from_slice(buffer).map_err(|_| ReadError::Capacity)?;
         Ok(())
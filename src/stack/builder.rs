@@ -0,0 +1,187 @@
+use heapless::Vec;
+use wmbus::WMBusAddress;
+
+use crate::fec::CodeRate;
+
+use super::{
+    apl,
+    mbal::{MbalCommand, MbalControl, MbalFields, MbalFunctionCode},
+    phl::PhlFields,
+    Packet, WriteError,
+};
+
+/// Builder for packets to be written.
+///
+/// The code rate, address, function code and M-Bus data must all be given before the packet can be built.
+/// A field that is not yet given has the unit type `()`.
+pub struct PacketBuilder<R = (), A = (), F = (), D = ()> {
+    code_rate: R,
+    is_prioritized: bool,
+    address: A,
+    function_code: F,
+    mbus_data: D,
+}
+
+impl PacketBuilder {
+    /// Create a new builder for a packet that is not prioritized
+    pub const fn new() -> Self {
+        Self {
+            code_rate: (),
+            is_prioritized: false,
+            address: (),
+            function_code: (),
+            mbus_data: (),
+        }
+    }
+}
+
+impl Default for PacketBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R, A, F, D> PacketBuilder<R, A, F, D> {
+    /// Set whether the packet is prioritized
+    pub fn prioritized(mut self, is_prioritized: bool) -> Self {
+        self.is_prioritized = is_prioritized;
+        self
+    }
+}
+
+impl<A, F, D> PacketBuilder<(), A, F, D> {
+    /// Set the code rate of the frame
+    pub fn code_rate(self, code_rate: CodeRate) -> PacketBuilder<CodeRate, A, F, D> {
+        PacketBuilder {
+            code_rate,
+            is_prioritized: self.is_prioritized,
+            address: self.address,
+            function_code: self.function_code,
+            mbus_data: self.mbus_data,
+        }
+    }
+}
+
+impl<R, F, D> PacketBuilder<R, (), F, D> {
+    /// Set the address of the meter
+    pub fn address(self, address: WMBusAddress) -> PacketBuilder<R, WMBusAddress, F, D> {
+        PacketBuilder {
+            code_rate: self.code_rate,
+            is_prioritized: self.is_prioritized,
+            address,
+            function_code: self.function_code,
+            mbus_data: self.mbus_data,
+        }
+    }
+}
+
+impl<R, A, D> PacketBuilder<R, A, (), D> {
+    /// Set the MBAL function code
+    pub fn function_code(
+        self,
+        function_code: MbalFunctionCode,
+    ) -> PacketBuilder<R, A, MbalFunctionCode, D> {
+        PacketBuilder {
+            code_rate: self.code_rate,
+            is_prioritized: self.is_prioritized,
+            address: self.address,
+            function_code,
+            mbus_data: self.mbus_data,
+        }
+    }
+}
+
+impl<R, A, F> PacketBuilder<R, A, F, ()> {
+    /// Set the M-Bus data
+    pub fn mbus_data(self, mbus_data: &[u8]) -> PacketBuilder<R, A, F, &[u8]> {
+        PacketBuilder {
+            code_rate: self.code_rate,
+            is_prioritized: self.is_prioritized,
+            address: self.address,
+            function_code: self.function_code,
+            mbus_data,
+        }
+    }
+}
+
+impl PacketBuilder<CodeRate, WMBusAddress, MbalFunctionCode, &[u8]> {
+    /// Build the packet.
+    /// The M-Bus data must fit both the packet capacity and [`apl::MBUS_DATA_MAX`].
+    pub fn build<const N: usize>(self) -> Result<Packet<N>, WriteError> {
+        if self.mbus_data.len() > apl::MBUS_DATA_MAX {
+            return Err(WriteError::Capacity);
+        }
+
+        Ok(Packet {
+            phl: Some(PhlFields {
+                code_rate: self.code_rate,
+            }),
+            mbal: Some(MbalFields {
+                control: MbalControl {
+                    is_prioritized: self.is_prioritized,
+                },
+                address: self.address,
+                command: MbalCommand {
+                    function_code: self.function_code,
+                },
+            }),
+            mbus_data: Vec::from_slice(self.mbus_data).map_err(|_| WriteError::Capacity)?,
+            extension: (),
+            rx: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address() -> WMBusAddress {
+        WMBusAddress::new(
+            0x2c37.try_into().unwrap(),
+            12341234,
+            27,
+            0x16.try_into().unwrap(),
+        )
+    }
+
+    #[test]
+    fn can_build_packet() {
+        // Given
+        let builder = PacketBuilder::new()
+            .code_rate(CodeRate::OneThird)
+            .prioritized(true)
+            .address(address())
+            .function_code(MbalFunctionCode::SendInstallationRequest)
+            .mbus_data(&[0x7A, 0x01, 0x00]);
+
+        // When
+        let packet: Packet = builder.build().unwrap();
+
+        // Then
+        assert_eq!(CodeRate::OneThird, packet.phl.unwrap().code_rate);
+        let mbal = packet.mbal.unwrap();
+        assert!(mbal.control.is_prioritized);
+        assert_eq!(address(), mbal.address);
+        assert!(mbal.command.function_code == MbalFunctionCode::SendInstallationRequest);
+        assert_eq!([0x7A, 0x01, 0x00], packet.mbus_data);
+        assert!(packet.rx.is_none());
+    }
+
+    #[test]
+    fn can_not_build_packet_with_too_long_mbus_data() {
+        // Given
+        let mbus_data = [0; apl::MBUS_DATA_MAX + 1];
+        let builder = PacketBuilder::new()
+            .mbus_data(&mbus_data)
+            .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+            .address(address())
+            .code_rate(CodeRate::OneHalf);
+
+        // When
+        let result = builder.build::<{ apl::MBUS_DATA_MAX + 1 }>();
+
+        // Then
+        assert!(matches!(result, Err(WriteError::Capacity)));
+    }
+}
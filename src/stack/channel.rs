@@ -1,4 +1,5 @@
 #[derive(FromPrimitive, ToPrimitive, PartialEq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Channel {
     A,
     B,
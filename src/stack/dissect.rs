@@ -0,0 +1,360 @@
+//! Dissection of frames into a tree of named fields.
+//!
+//! Every field covers a range of frame bits and carries both the received raw value and its
+//! interpretation after error correction. The frame is re-encoded from the corrected block,
+//! so the bits corrected by the turbo decoder can be highlighted anywhere in the frame,
+//! including the coded header, the termination bits and the parity.
+
+use core::fmt::{Arguments, Write};
+
+use bitvec::prelude::*;
+use heapless::{String, Vec};
+use num_traits::FromPrimitive;
+
+use crate::fec::CodeRate;
+
+use super::{
+    mbal::{self, MbalFunctionCode},
+    phl::{self, Phl},
+    Layer, ReadError, RxMetadata,
+};
+
+/// The maximum number of fields in a dissection
+pub const MAX_FIELDS: usize = 24;
+
+/// The maximum length of the interpretation of a field
+pub const MAX_INTERPRETATION: usize = 48;
+
+/// A named range of frame bits
+#[derive(Debug, Clone)]
+pub struct Field {
+    pub name: &'static str,
+    /// The depth in the field tree, where top level fields have depth zero
+    pub depth: usize,
+    /// The offset of the first bit from the start of the frame
+    pub bit_offset: usize,
+    pub bit_length: usize,
+    /// The received raw value, present if the field has at most 64 bits which were all received
+    pub value: Option<u64>,
+    /// The meaning of the field after error correction
+    pub interpretation: String<MAX_INTERPRETATION>,
+    /// The number of received bits that were corrected by the decoder
+    pub corrected_bits: usize,
+}
+
+/// A frame dissected into fields
+///
+/// The fields are in the order they appear in the frame, with each field directly followed by its children.
+/// The frame is at most `FRAME_LENGTH` bytes long.
+pub struct Dissection<const FRAME_LENGTH: usize = { phl::MAX_FRAME_LENGTH }> {
+    pub fields: Vec<Field, MAX_FIELDS>,
+    pub rx: RxMetadata,
+    received: Vec<u8, FRAME_LENGTH>,
+    corrected: Vec<u8, FRAME_LENGTH>,
+}
+
+impl<const FRAME_LENGTH: usize> Dissection<FRAME_LENGTH> {
+    /// Get the received frame, without any bytes beyond the frame length
+    pub fn received_frame(&self) -> &[u8] {
+        &self.received
+    }
+
+    /// Get the frame as it was transmitted, reconstructed by encoding the corrected block
+    pub fn corrected_frame(&self) -> &[u8] {
+        &self.corrected
+    }
+
+    /// Get whether a received bit was corrected by the decoder
+    pub fn is_corrected(&self, bit_offset: usize) -> bool {
+        let received = self.received.view_bits::<Msb0>();
+        let corrected = self.corrected.view_bits::<Msb0>();
+        bit_offset < received.len() && received[bit_offset] != corrected[bit_offset]
+    }
+}
+
+/// Dissect a frame whose layers above the physical layer are the MBAL and the application data
+pub(crate) fn dissect<
+    A: Layer,
+    const MBAL_MAX: usize,
+    const BLOCK_LENGTH: usize,
+    const BLOCK_BITS: usize,
+    const FIRST_TRELLIS_BITS: usize,
+    const SECOND_TRELLIS_BITS: usize,
+    const TRELLIS_BITS: usize,
+    const FRAME_BITS: usize,
+    const FRAME_LENGTH: usize,
+>(
+    phl: &Phl<
+        mbal::Mbal<A>,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    >,
+    buffer: &[u8],
+) -> Result<Dissection<FRAME_LENGTH>, ReadError> {
+    // Bytes beyond the largest frame are not part of a frame that fits the decoder
+    if phl::get_frame_length(buffer)? > FRAME_LENGTH {
+        return Err(ReadError::Capacity);
+    }
+    let length = buffer.len().min(FRAME_LENGTH);
+    let mut decoded = Vec::<u8, FRAME_LENGTH>::from_slice(&buffer[..length]).unwrap();
+    let (fields, rx, block) = phl.read_in_place(&mut decoded)?;
+    let data_length = block.len();
+
+    // Re-encode the corrected block to get the transmitted frame
+    let mut corrected = Vec::<u8, FRAME_LENGTH>::new();
+    phl.write_block(
+        &mut corrected,
+        fields.code_rate,
+        &decoded[phl::HEADER_SIZE..phl::HEADER_SIZE + data_length + 4],
+    )
+    .map_err(|_| ReadError::Capacity)?;
+
+    let received = Vec::from_slice(&buffer[..length.min(corrected.len())]).unwrap();
+    let mut dissector = Dissector {
+        received: received.view_bits(),
+        corrected: corrected.view_bits(),
+        fields: Vec::new(),
+    };
+    dissector.add_phl(fields.code_rate, data_length, &rx);
+
+    let fields = dissector.fields;
+    Ok(Dissection {
+        fields,
+        rx,
+        received,
+        corrected,
+    })
+}
+
+struct Dissector<'a> {
+    received: &'a BitSlice<u8, Msb0>,
+    corrected: &'a BitSlice<u8, Msb0>,
+    fields: Vec<Field, MAX_FIELDS>,
+}
+
+impl Dissector<'_> {
+    fn add_phl(&mut self, code_rate: CodeRate, data_length: usize, rx: &RxMetadata) {
+        let header_bits = 8 * phl::HEADER_SIZE;
+        let block_bits = 8 * (data_length + 4);
+        let frame_bits = self.corrected.len();
+
+        self.add(0, 0, header_bits, "PHY header", format_args!(""));
+        self.add(1, 0, 2, "Padding", format_args!("always ones"));
+        self.add(
+            1,
+            2,
+            header_bits - 14,
+            "Coded header",
+            format_args!("distance {}", rx.header_distance),
+        );
+        self.add(
+            2,
+            2,
+            8,
+            "Data length",
+            format_args!("{} bytes", data_length),
+        );
+        self.add(
+            2,
+            header_bits - 13,
+            1,
+            "Code rate",
+            format_args!("{}", code_rate_name(code_rate)),
+        );
+        self.add(1, header_bits - 12, 12, "Termination", format_args!(""));
+        self.add(
+            2,
+            header_bits - 12,
+            6,
+            "First termination",
+            format_args!("state {:#04x}", self.corrected_value(header_bits - 12, 6)),
+        );
+        self.add(
+            2,
+            header_bits - 6,
+            6,
+            "Second termination",
+            format_args!("state {:#04x}", self.corrected_value(header_bits - 6, 6)),
+        );
+
+        self.add(
+            0,
+            header_bits,
+            block_bits,
+            "Systematic block",
+            format_args!("{} decode iterations", rx.decode_iterations),
+        );
+        self.add_mbal(header_bits, data_length);
+        self.add(
+            1,
+            header_bits + block_bits - 32,
+            32,
+            "CRC-32",
+            format_args!("valid"),
+        );
+
+        self.add(
+            0,
+            header_bits + block_bits,
+            frame_bits - header_bits - block_bits,
+            "Parity",
+            format_args!("{} bits erased", rx.erased_bits),
+        );
+    }
+
+    fn add_mbal(&mut self, offset: usize, data_length: usize) {
+        self.add(1, offset, 8 * data_length, "MBAL", format_args!(""));
+        if data_length < mbal::HEADER_SIZE {
+            return;
+        }
+
+        let corrected = self.corrected;
+        let block = &corrected[offset..offset + 8 * data_length];
+        let control = block[..8].load_be::<u8>();
+        let manufacturer = block[8..24].load_le::<u16>();
+        let id = block[24..56].load_le::<u32>();
+        let version = block[56..64].load_be::<u8>();
+        let device_type = block[64..72].load_be::<u8>();
+        let command = block[72..80].load_be::<u8>();
+        let mut header = [0; mbal::HEADER_SIZE];
+        header
+            .view_bits_mut::<Msb0>()
+            .copy_from_bitslice(&block[..8 * mbal::HEADER_SIZE]);
+        let letters = mbal::manufacturer_letters(manufacturer);
+        let letters = letters
+            .as_ref()
+            .and_then(|letters| core::str::from_utf8(letters).ok())
+            .unwrap_or("???");
+
+        self.add(
+            2,
+            offset,
+            8,
+            "Control",
+            format_args!(
+                "{}",
+                match control {
+                    0 => "not prioritized",
+                    1 => "prioritized",
+                    _ => "invalid",
+                }
+            ),
+        );
+        self.add(
+            2,
+            offset + 8,
+            64,
+            "Address",
+            format_args!(
+                "{} {:08x} version {} type {:#04x}",
+                letters, id, version, device_type
+            ),
+        );
+        self.add(
+            3,
+            offset + 8,
+            16,
+            "Manufacturer",
+            format_args!("{}", letters),
+        );
+        self.add(
+            3,
+            offset + 24,
+            32,
+            "Identification",
+            format_args!("{:08x}", id),
+        );
+        self.add(3, offset + 56, 8, "Version", format_args!("{}", version));
+        self.add(
+            3,
+            offset + 64,
+            8,
+            "Device type",
+            format_args!("{:#04x}", device_type),
+        );
+        self.add(
+            2,
+            offset + 72,
+            8,
+            "Command",
+            format_args!(
+                "{}",
+                MbalFunctionCode::from_u8(command >> 4).map_or("invalid", |code| code.name())
+            ),
+        );
+        self.add(
+            2,
+            offset + 80,
+            16,
+            "CRC-16",
+            format_args!(
+                "{}",
+                if mbal::is_valid_crc(&header) {
+                    "valid"
+                } else {
+                    "invalid"
+                }
+            ),
+        );
+        self.add(
+            2,
+            offset + 8 * mbal::HEADER_SIZE,
+            8 * (data_length - mbal::HEADER_SIZE),
+            "Application data",
+            format_args!("{} bytes", data_length - mbal::HEADER_SIZE),
+        );
+    }
+
+    fn add(
+        &mut self,
+        depth: usize,
+        bit_offset: usize,
+        bit_length: usize,
+        name: &'static str,
+        interpretation: Arguments,
+    ) {
+        let end = bit_offset + bit_length;
+        let received_start = bit_offset.min(self.received.len());
+        let received_end = end.min(self.received.len());
+
+        let value = (bit_length > 0 && bit_length <= 64 && end <= self.received.len())
+            .then(|| self.received[bit_offset..end].load_be::<u64>());
+        let corrected_bits = self.received[received_start..received_end]
+            .iter()
+            .zip(self.corrected[received_start..received_end].iter())
+            .filter(|(received, corrected)| received != corrected)
+            .count();
+
+        // An interpretation that does not fit is truncated
+        let mut text = String::new();
+        _ = text.write_fmt(interpretation);
+
+        self.fields
+            .push(Field {
+                name,
+                depth,
+                bit_offset,
+                bit_length,
+                value,
+                interpretation: text,
+                corrected_bits,
+            })
+            .unwrap();
+    }
+
+    fn corrected_value(&self, bit_offset: usize, bit_length: usize) -> u64 {
+        self.corrected[bit_offset..bit_offset + bit_length].load_be()
+    }
+}
+
+fn code_rate_name(code_rate: CodeRate) -> &'static str {
+    match code_rate {
+        CodeRate::OneThird => "1/3",
+        CodeRate::OneHalf => "1/2",
+    }
+}
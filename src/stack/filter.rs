@@ -0,0 +1,102 @@
+use wmbus::WMBusAddress;
+
+/// Address filter used to skip frames from unwanted meters before they are decoded.
+///
+/// The address is given as the raw address bytes of the MBAL header in the systematic block,
+/// and they may contain bit errors as the frame is not yet decoded.
+pub trait AddressFilter {
+    fn accepts(&self, address: &[u8; ADDRESS_SIZE]) -> bool;
+}
+
+pub const ADDRESS_SIZE: usize = 8;
+
+/// Filter that accepts all addresses
+#[derive(Default, Clone, Copy)]
+pub struct AcceptAll;
+
+impl AddressFilter for AcceptAll {
+    fn accepts(&self, _address: &[u8; ADDRESS_SIZE]) -> bool {
+        true
+    }
+}
+
+/// Filter that accepts the addresses in a whitelist.
+/// An address is accepted if it differs from a whitelisted address in at most `max_bit_errors` bits.
+pub struct Whitelist<'a> {
+    pub addresses: &'a [WMBusAddress],
+    pub max_bit_errors: u32,
+}
+
+impl<'a> Whitelist<'a> {
+    pub const fn new(addresses: &'a [WMBusAddress]) -> Self {
+        Self {
+            addresses,
+            max_bit_errors: 3,
+        }
+    }
+}
+
+impl AddressFilter for Whitelist<'_> {
+    fn accepts(&self, address: &[u8; ADDRESS_SIZE]) -> bool {
+        self.addresses.iter().any(|whitelisted| {
+            let distance: u32 = whitelisted
+                .get_bytes()
+                .iter()
+                .zip(address)
+                .map(|(expected, actual)| (expected ^ actual).count_ones())
+                .sum();
+            distance <= self.max_bit_errors
+        })
+    }
+}
+
+impl<F: AddressFilter> AddressFilter for &F {
+    fn accepts(&self, address: &[u8; ADDRESS_SIZE]) -> bool {
+        (*self).accepts(address)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn address() -> WMBusAddress {
+        WMBusAddress::new(
+            0x2c37.try_into().unwrap(),
+            12341234,
+            27,
+            0x16.try_into().unwrap(),
+        )
+    }
+
+    #[test]
+    fn can_accept_whitelisted_address_with_bit_errors() {
+        // Given
+        let addresses = [address()];
+        let filter = Whitelist::new(&addresses);
+        let mut bytes: [u8; ADDRESS_SIZE] = address().get_bytes().as_slice().try_into().unwrap();
+        bytes[2] ^= 0x81;
+        bytes[5] ^= 0x10;
+
+        // When
+        let accepted = filter.accepts(&bytes);
+
+        // Then
+        assert!(accepted);
+    }
+
+    #[test]
+    fn can_reject_other_address() {
+        // Given
+        let addresses = [address()];
+        let filter = Whitelist::new(&addresses);
+        let mut bytes: [u8; ADDRESS_SIZE] = address().get_bytes().as_slice().try_into().unwrap();
+        bytes[3] ^= 0x0F;
+
+        // When
+        let accepted = filter.accepts(&bytes);
+
+        // Then
+        assert!(!accepted);
+    }
+}
@@ -3,7 +3,7 @@ use heapless::Vec;
 use num_traits::FromPrimitive;
 use wmbus::WMBusAddress;
 
-use super::{Layer, Packet, ReadError, WriteError, Writer};
+use super::{filter::ADDRESS_SIZE, Layer, Packet, ReadError, WriteError, Writer};
 
 pub const HEADER_SIZE: usize = 12;
 pub const MBAL_MAX: usize = 251;
@@ -12,24 +12,31 @@ const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_EN_13757);
 /// M-Bus Adaption Layer
 pub struct Mbal<A: Layer> {
     above: A,
+    bypassed: bool,
 }
 
 /// M-Bus Adaption Layer Fields
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MbalFields {
     pub control: MbalControl,
+    #[cfg_attr(feature = "serde", serde(with = "address_serde"))]
     pub address: WMBusAddress,
     pub command: MbalCommand,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MbalControl {
     pub is_prioritized: bool,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct MbalCommand {
     pub function_code: MbalFunctionCode,
 }
 
 #[derive(FromPrimitive, ToPrimitive, PartialEq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum MbalFunctionCode {
     /// SND-NR
     SendUnsolicitedApplicationData = 4,
@@ -38,44 +45,95 @@ pub enum MbalFunctionCode {
     SendInstallationRequest = 6,
 }
 
+impl MbalFunctionCode {
+    /// Get the abbreviated name used by EN 13757-4
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::SendUnsolicitedApplicationData => "SND-NR",
+            Self::SendInstallationRequest => "SND-IR",
+        }
+    }
+}
+
 impl<A: Layer> Mbal<A> {
     pub fn new(above: A) -> Self {
-        Self { above }
+        Self {
+            above,
+            bypassed: false,
+        }
     }
-}
 
-impl<A: Layer> Layer for Mbal<A> {
-    fn read<const N: usize>(&self, packet: &mut Packet<N>, buffer: &[u8]) -> Result<(), ReadError> {
-        if buffer.len() < HEADER_SIZE {
-            return Err(ReadError::NotEnoughBytes);
-        } else if !is_valid_crc(&buffer[..HEADER_SIZE]) {
-            return Err(ReadError::MBalCrcError);
+    /// Create an MBAL that passes the whole block to and from the layer above
+    /// without reading or writing the MBAL header, e.g. for a PHL-only stack.
+    pub fn bypassed(above: A) -> Self {
+        Self {
+            above,
+            bypassed: true,
         }
+    }
+}
 
-        let control = MbalControl {
-            is_prioritized: match buffer[0] {
-                0 => false,
-                1 => true,
-                _ => return Err(ReadError::MBalControlError),
-            },
-        };
+/// Get the raw address bytes from the start of a possibly partial MBAL block without checking the CRC
+pub fn peek_address_bytes(block: &[u8]) -> Result<[u8; ADDRESS_SIZE], ReadError> {
+    let address = block
+        .get(1..1 + ADDRESS_SIZE)
+        .ok_or(ReadError::NotEnoughBytes)?;
+    Ok(address.try_into().unwrap())
+}
 
-        let address = WMBusAddress::from_bytes(buffer[1..9].try_into().unwrap())
-            .map_err(|_| ReadError::MBalAddressError)?;
+/// Get the address from the start of a possibly partial MBAL block without checking the CRC
+pub fn peek_address(block: &[u8]) -> Result<WMBusAddress, ReadError> {
+    let bytes = peek_address_bytes(block)?;
+    WMBusAddress::from_bytes(bytes.as_slice().try_into().unwrap())
+        .map_err(|_| ReadError::MBalAddressError)
+}
 
-        let command = MbalCommand {
-            // TODO: Why the shifts
-            function_code: match MbalFunctionCode::from_u8(buffer[9] >> 4) {
-                Some(code) => code,
-                None => return Err(ReadError::MBalCommandError),
-            },
-        };
+/// Read the fields from the header of an MBAL block
+pub fn read_fields(buffer: &[u8]) -> Result<MbalFields, ReadError> {
+    if buffer.len() < HEADER_SIZE {
+        return Err(ReadError::NotEnoughBytes);
+    } else if !is_valid_crc(&buffer[..HEADER_SIZE]) {
+        return Err(ReadError::MBalCrcError);
+    }
+
+    let control = MbalControl {
+        is_prioritized: match buffer[0] {
+            0 => false,
+            1 => true,
+            _ => return Err(ReadError::MBalControlError),
+        },
+    };
+
+    let address = peek_address(buffer)?;
+
+    let command = MbalCommand {
+        // TODO: Why the shifts
+        function_code: match MbalFunctionCode::from_u8(buffer[9] >> 4) {
+            Some(code) => code,
+            None => return Err(ReadError::MBalCommandError),
+        },
+    };
 
-        packet.mbal = Some(MbalFields {
-            control,
-            address,
-            command,
-        });
+    Ok(MbalFields {
+        control,
+        address,
+        command,
+    })
+}
+
+impl<A: Layer> Layer for Mbal<A> {
+    type Extension = A::Extension;
+
+    fn read<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        buffer: &[u8],
+    ) -> Result<(), ReadError> {
+        if self.bypassed {
+            return self.above.read(packet, buffer);
+        }
+
+        packet.mbal = Some(read_fields(buffer)?);
 
         self.above.read(packet, &buffer[HEADER_SIZE..])
     }
@@ -83,8 +141,12 @@ impl<A: Layer> Layer for Mbal<A> {
     fn write<const N: usize>(
         &self,
         writer: &mut impl Writer,
-        packet: &Packet<N>,
+        packet: &Packet<N, A::Extension>,
     ) -> Result<(), WriteError> {
+        if self.bypassed {
+            return self.above.write(writer, packet);
+        }
+
         let fields = packet.mbal.as_ref().unwrap();
 
         let mut header = Vec::<u8, HEADER_SIZE>::new();
@@ -110,7 +172,7 @@ impl<A: Layer> Layer for Mbal<A> {
     }
 }
 
-fn is_valid_crc(block: &[u8]) -> bool {
+pub(crate) fn is_valid_crc(block: &[u8]) -> bool {
     let index = block.len() - 2;
 
     let mut digest = CRC.digest();
@@ -121,3 +183,98 @@ fn is_valid_crc(block: &[u8]) -> bool {
 
     actual == expected
 }
+
+/// Get the three letters of a manufacturer code.
+/// Each letter is one of the 32 characters from `@` to `_`, and a code with the top bit set has no letters.
+pub fn manufacturer_letters(manufacturer: u16) -> Option<[u8; 3]> {
+    if manufacturer & 0x8000 != 0 {
+        return None;
+    }
+
+    Some([10, 5, 0].map(|shift| b'@' + ((manufacturer >> shift) & 0x1F) as u8))
+}
+
+/// Get the manufacturer code from its three letters, see [`manufacturer_letters`]
+pub fn manufacturer_from_letters(letters: &[u8]) -> Option<u16> {
+    if letters.len() != 3 {
+        return None;
+    }
+
+    letters.iter().try_fold(0u16, |code, &letter| {
+        (b'@'..=b'_')
+            .contains(&letter)
+            .then(|| (code << 5) | (letter - b'@') as u16)
+    })
+}
+
+/// Serialize an address as its named parts, like the address printed by `linkiq-decode`
+#[cfg(feature = "serde")]
+mod address_serde {
+    use core::fmt::Write;
+
+    use heapless::String;
+    use serde::{
+        de::Error as _, ser::Error as _, Deserialize, Deserializer, Serialize, Serializer,
+    };
+    use wmbus::WMBusAddress;
+
+    use crate::stack::filter::ADDRESS_SIZE;
+
+    use super::{manufacturer_from_letters, manufacturer_letters};
+
+    #[derive(Serialize, Deserialize)]
+    struct AddressParts {
+        /// The three letter manufacturer code
+        manufacturer: String<3>,
+        /// The identification number as the eight digits of its BCD encoding
+        id: String<8>,
+        version: u8,
+        device_type: u8,
+    }
+
+    pub fn serialize<S: Serializer>(
+        address: &WMBusAddress,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let bytes: [u8; ADDRESS_SIZE] = address.get_bytes().as_slice().try_into().unwrap();
+        let manufacturer = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let id = u32::from_le_bytes([bytes[2], bytes[3], bytes[4], bytes[5]]);
+
+        let mut parts = AddressParts {
+            manufacturer: String::new(),
+            id: String::new(),
+            version: bytes[6],
+            device_type: bytes[7],
+        };
+        let letters = manufacturer_letters(manufacturer)
+            .ok_or_else(|| S::Error::custom("manufacturer has no letters"))?;
+        for letter in letters {
+            parts.manufacturer.push(letter as char).unwrap();
+        }
+        write!(parts.id, "{id:08x}").unwrap();
+
+        parts.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<WMBusAddress, D::Error> {
+        let parts = AddressParts::deserialize(deserializer)?;
+
+        let manufacturer = manufacturer_from_letters(parts.manufacturer.as_bytes())
+            .ok_or_else(|| D::Error::custom("invalid manufacturer"))?;
+        if parts.id.len() != 8 {
+            return Err(D::Error::custom("invalid address"));
+        }
+        let id = u32::from_str_radix(&parts.id, 16)
+            .map_err(|_| D::Error::custom("invalid identification number"))?;
+
+        let mut bytes = [0; ADDRESS_SIZE];
+        bytes[..2].copy_from_slice(&manufacturer.to_le_bytes());
+        bytes[2..6].copy_from_slice(&id.to_le_bytes());
+        bytes[6] = parts.version;
+        bytes[7] = parts.device_type;
+        WMBusAddress::from_bytes(bytes.as_slice().try_into().unwrap())
+            .map_err(|_| D::Error::custom("invalid address"))
+    }
+}
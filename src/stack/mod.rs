@@ -1,32 +1,124 @@
 pub mod apl;
+mod builder;
 pub(crate) mod channel;
+pub mod dissect;
+pub mod filter;
 pub mod mbal;
 pub mod phl;
+pub mod wmbus_frame;
 
 use heapless::Vec;
+use wmbus::WMBusAddress;
+
+use crate::fec::Llr;
+
+use self::filter::AddressFilter;
 
 /// The LinkIQ protocol stack
-pub struct Stack {
-    phl: phl::Phl<mbal::Mbal<apl::Apl>>,
+///
+/// The layer above the MBAL is given by `U`, which by default is the APL.
+/// A custom application layer is placed above the MBAL with [`Stack::with_layer`],
+/// and a PHL-only stack that passes the undecoded MBAL block to `U` is created with [`Stack::with_phl_layer`].
+/// The stack is sized at compile time for frames with at most `MBAL_MAX` MBAL bytes.
+/// A smaller maximum reduces the memory used by the turbo decoder.
+/// The remaining parameters are the buffer sizes derived from `MBAL_MAX`, see [`phl::Phl`],
+/// and a stack type for another maximum is most easily named with [`sized_stack!`](crate::sized_stack).
+pub struct Stack<
+    U: Layer = apl::Apl,
+    const MBAL_MAX: usize = { mbal::MBAL_MAX },
+    const BLOCK_LENGTH: usize = { phl::max_block_length(mbal::MBAL_MAX) },
+    const BLOCK_BITS: usize = { phl::block_bits(mbal::MBAL_MAX) },
+    const FIRST_TRELLIS_BITS: usize = { phl::first_trellis_bits(mbal::MBAL_MAX) },
+    const SECOND_TRELLIS_BITS: usize = { phl::second_trellis_bits(mbal::MBAL_MAX) },
+    const TRELLIS_BITS: usize = { phl::all_trellis_bits(mbal::MBAL_MAX) },
+    const FRAME_BITS: usize = { phl::frame_bits(mbal::MBAL_MAX) },
+    const FRAME_LENGTH: usize = { phl::max_frame_length(mbal::MBAL_MAX) },
+> {
+    phl: phl::Phl<
+        mbal::Mbal<U>,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    >,
+}
+
+/// The type of a [`Stack`] with the layer `$layer` above the MBAL,
+/// sized for frames with at most `$mbal_max` MBAL bytes, e.g. `sized_stack!(Apl, 64)`.
+#[macro_export]
+macro_rules! sized_stack {
+    ($layer:ty, $mbal_max:expr) => {
+        $crate::stack::Stack<
+            $layer,
+            { $mbal_max },
+            { $crate::stack::phl::max_block_length($mbal_max) },
+            { $crate::stack::phl::block_bits($mbal_max) },
+            { $crate::stack::phl::first_trellis_bits($mbal_max) },
+            { $crate::stack::phl::second_trellis_bits($mbal_max) },
+            { $crate::stack::phl::all_trellis_bits($mbal_max) },
+            { $crate::stack::phl::frame_bits($mbal_max) },
+            { $crate::stack::phl::max_frame_length($mbal_max) },
+        >
+    };
 }
 
 /// Layer trait
 pub trait Layer {
-    fn read<const N: usize>(&self, packet: &mut Packet<N>, buffer: &[u8]) -> Result<(), ReadError>;
+    /// The fields of a custom layer stored in the packet extension
+    type Extension: Default;
+
+    fn read<const N: usize>(
+        &self,
+        packet: &mut Packet<N, Self::Extension>,
+        buffer: &[u8],
+    ) -> Result<(), ReadError>;
     fn write<const N: usize>(
         &self,
         writer: &mut impl Writer,
-        packet: &Packet<N>,
+        packet: &Packet<N, Self::Extension>,
     ) -> Result<(), WriteError>;
 }
 
 /// A LinkIQ packet
 #[derive(Default)]
-pub struct Packet<const N: usize = { apl::MBUS_DATA_MAX }> {
-    pub rssi: Option<Rssi>,
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Packet<const N: usize = { apl::MBUS_DATA_MAX }, X = ()> {
     pub phl: Option<phl::PhlFields>,
     pub mbal: Option<mbal::MbalFields>,
     pub mbus_data: Vec<u8, N>,
+    /// The fields of custom layers
+    pub extension: X,
+    /// Metadata about the reception, only present for packets that were read
+    pub rx: Option<RxMetadata>,
+}
+
+/// Metadata about the reception and decoding of a packet
+#[derive(Debug, Default, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RxMetadata {
+    pub rssi: Option<Rssi>,
+    pub header_distance: usize,
+    pub decode_iterations: usize,
+    pub decode_distance: usize,
+    /// The number of receptions that were combined before decoding
+    pub combined_receptions: usize,
+    /// The number of parity bits missing from a truncated frame
+    pub erased_bits: usize,
+    /// The number of bits flipped after turbo decoding to satisfy the CRC
+    pub bit_flips: usize,
+    /// The rank of the coded header candidate that was decoded, zero being the closest
+    pub header_candidate: usize,
+}
+
+/// A LinkIQ packet that borrows its M-Bus data from the decoded frame buffer
+pub struct PacketRef<'a> {
+    pub rx: RxMetadata,
+    pub phl: phl::PhlFields,
+    pub mbal: mbal::MbalFields,
+    pub mbus_data: &'a [u8],
 }
 
 pub type Rssi = i16;
@@ -42,6 +134,7 @@ impl<const N: usize> Writer for Vec<u8, N> {
     }
 }
 
+#[cfg(feature = "alloc")]
 impl Writer for alloc::vec::Vec<u8> {
     fn write(&mut self, buf: &[u8]) -> Result<(), WriteError> {
         self.extend_from_slice(buf);
@@ -49,6 +142,7 @@ impl Writer for alloc::vec::Vec<u8> {
     }
 }
 
+pub use builder::PacketBuilder;
 pub use channel::Channel;
 
 #[derive(Debug)]
@@ -56,10 +150,17 @@ pub enum ReadError {
     Capacity,
     NotEnoughBytes,
     PhlDecodeError,
+    ReceptionMismatch,
     MBalCrcError,
     MBalControlError,
     MBalAddressError,
     MBalCommandError,
+    /// The frame was skipped by an address filter
+    Filtered,
+    /// The L-field of a wM-Bus frame is invalid
+    WMBusLengthError,
+    /// A block of a wM-Bus frame has an invalid CRC
+    WMBusCrcError,
 }
 
 #[derive(Debug, PartialEq)]
@@ -68,32 +169,254 @@ pub enum WriteError {
 }
 
 impl Stack {
-    /// Create a new LinkIQ stack
+    /// Create a new LinkIQ stack for frames of any length
     pub fn new() -> Self {
+        Self::new_sized()
+    }
+}
+
+impl<
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+        const FRAME_LENGTH: usize,
+    >
+    Stack<
+        apl::Apl,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+        FRAME_LENGTH,
+    >
+{
+    /// Create a new LinkIQ stack for frames with at most `MBAL_MAX` MBAL bytes
+    pub fn new_sized() -> Self {
+        Self::with_layer(apl::Apl::new())
+    }
+
+    /// Create a new LinkIQ stack that forwards the undecoded MBAL block in the M-Bus data
+    pub fn phl_only() -> Self {
+        Self::with_phl_layer(apl::Apl::new())
+    }
+}
+
+impl<
+        U: Layer,
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+        const FRAME_LENGTH: usize,
+    >
+    Stack<
+        U,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+        FRAME_LENGTH,
+    >
+{
+    /// Create a new LinkIQ stack with a custom layer above the MBAL
+    pub fn with_layer(above: U) -> Self {
+        Self::with_mbal(mbal::Mbal::new(above))
+    }
+
+    /// Create a new LinkIQ stack without the MBAL, where the layer above the physical layer
+    /// gets the undecoded MBAL block, e.g. to forward it to another system.
+    /// The packets must have room for [`mbal::MBAL_MAX`] bytes of M-Bus data, see [`Stack::read_into`].
+    pub fn with_phl_layer(above: U) -> Self {
+        Self::with_mbal(mbal::Mbal::bypassed(above))
+    }
+
+    fn with_mbal(mbal: mbal::Mbal<U>) -> Self {
+        const { assert!(FRAME_LENGTH == phl::max_frame_length(MBAL_MAX)) }
+
         Self {
-            phl: phl::Phl::new(mbal::Mbal::new(apl::Apl::new())),
+            phl: phl::Phl::new(mbal),
         }
     }
 
-    /// Read a packet from a byte buffer
-    pub fn read(&self, buffer: &[u8]) -> Result<Packet, ReadError> {
+    /// Read a packet from a byte buffer.
+    /// The packet has room for [`apl::MBUS_DATA_MAX`] bytes of M-Bus data,
+    /// so a PHL-only stack should read into a larger packet with [`Stack::read_into`].
+    pub fn read(
+        &self,
+        buffer: &[u8],
+    ) -> Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError> {
+        let mut packet = Packet::default();
+        self.read_into(&mut packet, buffer)?;
+        Ok(packet)
+    }
+
+    /// Read a packet from a byte buffer into an existing packet.
+    /// The packet can be reused between reads, and its capacity decides the maximum M-Bus data length.
+    /// The extension is left for the custom layers to update.
+    pub fn read_into<const N: usize>(
+        &self,
+        packet: &mut Packet<N, U::Extension>,
+        buffer: &[u8],
+    ) -> Result<(), ReadError> {
+        packet.phl = None;
+        packet.mbal = None;
+        packet.mbus_data.clear();
+        packet.rx = None;
+
+        self.phl.read(packet, buffer)
+    }
+
+    /// Read a packet from soft bits, one llr per frame bit where a positive value is in favor of a one bit
+    pub fn read_soft(
+        &self,
+        llrs: &[Llr],
+    ) -> Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError> {
+        let mut packet = Packet::default();
+        self.phl.read_soft(&mut packet, llrs)?;
+        Ok(packet)
+    }
+
+    /// Read a packet by combining several receptions of the same frame before decoding
+    pub fn read_combined(
+        &self,
+        receptions: &[&[u8]],
+    ) -> Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError> {
+        let mut packet = Packet::default();
+        self.phl.read_combined(&mut packet, receptions)?;
+        Ok(packet)
+    }
+
+    /// Read a packet by combining several soft bit receptions of the same frame before decoding
+    pub fn read_soft_combined(
+        &self,
+        receptions: &[&[Llr]],
+    ) -> Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError> {
         let mut packet = Packet::default();
-        self.phl.read(&mut packet, buffer)?;
+        self.phl.read_soft_combined(&mut packet, receptions)?;
         Ok(packet)
     }
 
+    /// Get the physical layer
+    pub fn phl(
+        &self,
+    ) -> &phl::Phl<
+        mbal::Mbal<U>,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    > {
+        &self.phl
+    }
+
+    /// Get the physical layer, e.g. to configure the decoder
+    pub fn phl_mut(
+        &mut self,
+    ) -> &mut phl::Phl<
+        mbal::Mbal<U>,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    > {
+        &mut self.phl
+    }
+
     /// Write a packet
     pub fn write<const N: usize>(
         &self,
         writer: &mut impl Writer,
-        packet: &Packet<N>,
+        packet: &Packet<N, U::Extension>,
     ) -> Result<(), WriteError> {
         self.phl.write(writer, packet)
     }
+
+    /// Read a packet by decoding the frame in place, without copying the M-Bus data.
+    /// The systematic block in the buffer is replaced by the corrected block if decoding was needed.
+    pub fn read_ref<'a>(&self, buffer: &'a mut [u8]) -> Result<PacketRef<'a>, ReadError> {
+        let (phl, rx, block) = self.phl.read_in_place(buffer)?;
+        let mbal = mbal::read_fields(block)?;
+
+        Ok(PacketRef {
+            rx,
+            phl,
+            mbal,
+            mbus_data: &block[mbal::HEADER_SIZE..],
+        })
+    }
+
+    /// Read a packet if its address is accepted by the filter.
+    /// The address is checked before the frame is decoded, so unwanted frames are skipped cheaply.
+    pub fn read_filtered(
+        &self,
+        buffer: &[u8],
+        filter: &impl AddressFilter,
+    ) -> Result<Packet<{ apl::MBUS_DATA_MAX }, U::Extension>, ReadError> {
+        let address = mbal::peek_address_bytes(phl::peek_block(buffer)?)?;
+        if !filter.accepts(&address) {
+            return Err(ReadError::Filtered);
+        }
+
+        self.read(buffer)
+    }
+
+    /// Dissect a frame into a tree of named fields.
+    /// The fields show the received bits together with their meaning after error correction.
+    /// Frames with more than `MBAL_MAX` MBAL bytes can not be dissected.
+    pub fn dissect(&self, buffer: &[u8]) -> Result<dissect::Dissection<FRAME_LENGTH>, ReadError> {
+        dissect::dissect(&self.phl, buffer)
+    }
+
+    /// Get the likely address of a frame without decoding it.
+    /// The address is taken from the systematic bits, so it may contain bit errors.
+    /// Only the start of the frame up to and including the address is needed.
+    pub fn peek_address(&self, buffer: &[u8]) -> Result<WMBusAddress, ReadError> {
+        mbal::peek_address(phl::peek_block(buffer)?)
+    }
 }
 
-impl Default for Stack {
+impl<
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+        const FRAME_LENGTH: usize,
+    > Default
+    for Stack<
+        apl::Apl,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+        FRAME_LENGTH,
+    >
+{
     fn default() -> Self {
-        Self::new()
+        Self::new_sized()
     }
 }
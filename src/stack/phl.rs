@@ -3,32 +3,66 @@ use crc::{Algorithm, Crc};
 use fastfec::{
     catalog,
     turbo::{trellis_bits, umts::UmtsTurboDecoder, TurboCode, TurboEncoder},
-    Llr,
+    Llr, LlrMul,
 };
 use funty::Integral;
 use heapless::Vec;
 
 use crate::{
+    bitbuffer::BitBuffer,
     bitreader::{BitField, BitReader},
     fec::{CodeRate, EncoderTermination, TurboDecoderInput, TurboEncoderOutput},
     interleaver,
-    phycodedheader::PhyCodedHeader,
+    phycodedheader::{self, PhyCodedHeader},
     stack::mbal,
 };
 
-use super::{Layer, Packet, ReadError, WriteError, Writer};
+use super::{Layer, Packet, ReadError, RxMetadata, WriteError, Writer};
 
 pub const HEADER_SIZE: usize = 12;
-const MAX_BLOCK: usize = mbal::MBAL_MAX + 4;
-const MAX_BLOCK_BITS: usize = MAX_BLOCK * 8;
-const MAX_FIRST_TRELLIS_BITS: usize =
-    trellis_bits::<catalog::UMTS>(MAX_BLOCK_BITS, catalog::UMTS::TERMINATE_FIRST);
-const MAX_SECOND_TRELLIS_BITS: usize =
-    trellis_bits::<catalog::UMTS>(MAX_BLOCK_BITS, catalog::UMTS::TERMINATE_SECOND);
-const MAX_TRELLIS_BITS: usize = trellis_bits::<catalog::UMTS>(
-    MAX_BLOCK_BITS,
-    catalog::UMTS::TERMINATE_FIRST || catalog::UMTS::TERMINATE_SECOND,
-);
+/// The number of header bytes needed to decode the coded header, i.e. excluding the termination
+pub const CODED_HEADER_SIZE: usize = (2 + phycodedheader::CODE_BITS).div_ceil(8);
+const TERMINATION_OFFSET: usize = 8 * HEADER_SIZE - 2 * 6;
+const MAX_BLOCK: usize = max_block_length(mbal::MBAL_MAX);
+
+/// The number of bits in the largest encoded block for a maximum MBAL length
+pub const fn block_bits(mbal_max: usize) -> usize {
+    8 * max_block_length(mbal_max)
+}
+
+/// The number of trellis bits of the first constituent decoder for a maximum MBAL length
+pub const fn first_trellis_bits(mbal_max: usize) -> usize {
+    trellis_bits::<catalog::UMTS>(block_bits(mbal_max), catalog::UMTS::TERMINATE_FIRST)
+}
+
+/// The number of trellis bits of the second constituent decoder for a maximum MBAL length
+pub const fn second_trellis_bits(mbal_max: usize) -> usize {
+    trellis_bits::<catalog::UMTS>(block_bits(mbal_max), catalog::UMTS::TERMINATE_SECOND)
+}
+
+/// The number of trellis bits of either constituent decoder for a maximum MBAL length
+pub const fn all_trellis_bits(mbal_max: usize) -> usize {
+    trellis_bits::<catalog::UMTS>(
+        block_bits(mbal_max),
+        catalog::UMTS::TERMINATE_FIRST || catalog::UMTS::TERMINATE_SECOND,
+    )
+}
+
+/// The length of the largest encoded block, including its CRC, for a maximum MBAL length
+pub const fn max_block_length(mbal_max: usize) -> usize {
+    mbal_max + 4
+}
+
+/// The length of the largest frame for a maximum MBAL length, which is a rate 1/3 frame
+pub const fn max_frame_length(mbal_max: usize) -> usize {
+    HEADER_SIZE + 3 * max_block_length(mbal_max)
+}
+
+/// The number of bits in the largest frame for a maximum MBAL length
+pub const fn frame_bits(mbal_max: usize) -> usize {
+    8 * max_frame_length(mbal_max)
+}
+
 const CRC_ALGORITHM: Algorithm<u32> = Algorithm::<u32> {
     width: 32,
     poly: 0xf4acfb13,
@@ -42,40 +76,202 @@ const CRC_ALGORITHM: Algorithm<u32> = Algorithm::<u32> {
 pub const CRC: Crc<u32> = Crc::<u32>::new(&CRC_ALGORITHM);
 
 /// Physical Layer
-pub struct Phl<A: Layer> {
+///
+/// The turbo decoder is sized at compile time for frames with at most `MBAL_MAX` MBAL bytes.
+/// A frame with a longer MBAL can only be read from bytes, and only if its systematic block passes the CRC.
+/// The remaining parameters are the buffer sizes derived from `MBAL_MAX` by the functions of the same name,
+/// e.g. `BLOCK_BITS` is [`block_bits`]`(MBAL_MAX)`, and they are checked when the layer is created.
+pub struct Phl<
+    A: Layer,
+    const MBAL_MAX: usize = { mbal::MBAL_MAX },
+    const BLOCK_LENGTH: usize = { max_block_length(mbal::MBAL_MAX) },
+    const BLOCK_BITS: usize = { block_bits(mbal::MBAL_MAX) },
+    const FIRST_TRELLIS_BITS: usize = { first_trellis_bits(mbal::MBAL_MAX) },
+    const SECOND_TRELLIS_BITS: usize = { second_trellis_bits(mbal::MBAL_MAX) },
+    const TRELLIS_BITS: usize = { all_trellis_bits(mbal::MBAL_MAX) },
+    const FRAME_BITS: usize = { frame_bits(mbal::MBAL_MAX) },
+> {
     above: A,
     encoder: TurboEncoder<catalog::UMTS>,
     decoder: UmtsTurboDecoder<
         catalog::UMTS,
-        MAX_BLOCK_BITS,
-        MAX_FIRST_TRELLIS_BITS,
-        MAX_SECOND_TRELLIS_BITS,
-        MAX_TRELLIS_BITS,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
     >,
     pub max_decode_iterations: usize,
+    /// The llr magnitude assigned to hard decision bits before decoding
+    pub hard_decision_llr: Llr,
+    /// The maximum fraction of the parity bits that may be missing from a truncated frame.
+    /// The missing bits are treated as erasures during decoding.
+    /// A truncated frame is always accepted if the systematic block passes the CRC.
+    pub max_erasure_ratio: f32,
+    /// The maximum number of bits flipped when trying to satisfy the CRC after turbo decoding fails.
+    /// Zero disables the bit flipping.
+    pub max_bit_flips: usize,
+    /// The number of least reliable decoded bits that are considered for flipping.
+    /// At most [`MAX_BIT_FLIP_CANDIDATES`] bits are considered.
+    pub bit_flip_candidates: usize,
+    /// The number of coded header candidates, ranked by distance, that are tried
+    /// until one passes the CRC. At most [`MAX_HEADER_CANDIDATES`] candidates are tried.
+    pub header_candidates: usize,
 }
 
 /// Physical Layer Fields
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct PhlFields {
     pub code_rate: CodeRate,
-    pub header_distance: usize,
-    pub decode_iterations: usize,
-    pub decode_distance: usize,
 }
 
-pub const MAX_FRAME_LENGTH: usize = HEADER_SIZE + 3 * mbal::MBAL_MAX;
+pub const MAX_FRAME_LENGTH: usize = max_frame_length(mbal::MBAL_MAX);
+pub const MAX_BIT_FLIP_CANDIDATES: usize = 16;
+pub const MAX_HEADER_CANDIDATES: usize = 4;
 
 pub fn get_frame_length(buffer: &[u8]) -> Result<usize, ReadError> {
-    if buffer.len() < HEADER_SIZE {
+    let (header, _) = read_header(buffer)?;
+    let frame_length = get_frame_length_from_header(&header);
+    Ok(frame_length)
+}
+
+/// Get the length of the frame header and the systematic block.
+/// The remainder of the frame is parity.
+pub fn get_systematic_length(buffer: &[u8]) -> Result<usize, ReadError> {
+    let (header, _) = read_header(buffer)?;
+    Ok(HEADER_SIZE + header.data_length + 4)
+}
+
+/// Get the frame length from soft bits together with the reliability of the coded header decision.
+/// The llrs are given in the order they are transmitted, and a positive value is in favor of a one bit.
+/// Llrs missing from the end of the coded header are treated as erasures.
+pub fn get_soft_frame_length(llrs: &[Llr]) -> Result<(usize, u32), ReadError> {
+    let (header, reliability) = llrs
+        .get(2..)
+        .and_then(PhyCodedHeader::read_soft)
+        .ok_or(ReadError::NotEnoughBytes)?;
+    Ok((get_frame_length_from_header(&header), reliability))
+}
+
+/// Get the received part of the systematic block without decoding the frame.
+/// The bytes are hard decisions, so they may contain bit errors.
+pub fn peek_block(buffer: &[u8]) -> Result<&[u8], ReadError> {
+    let (header, _) = read_header(buffer)?;
+    let block_end = HEADER_SIZE + header.data_length + 4;
+    buffer
+        .get(HEADER_SIZE..block_end.min(buffer.len()))
+        .ok_or(ReadError::NotEnoughBytes)
+}
+
+/// Get whether the CRC of the systematic block is valid.
+/// The frame can then be read without its parity, so the remainder need not be received.
+pub fn is_systematic_crc_valid(buffer: &[u8]) -> Result<bool, ReadError> {
+    let (header, _) = read_header(buffer)?;
+    let block_end = HEADER_SIZE + header.data_length + 4;
+    let block = buffer
+        .get(HEADER_SIZE..block_end)
+        .ok_or(ReadError::NotEnoughBytes)?;
+    Ok(is_valid_crc(header.data_length, block))
+}
+
+fn read_header(buffer: &[u8]) -> Result<(PhyCodedHeader, usize), ReadError> {
+    if buffer.len() < CODED_HEADER_SIZE {
         return Err(ReadError::NotEnoughBytes);
     }
 
     let mut reader = BitReader::from_slice(buffer);
     reader.read_bits::<usize>(2).unwrap(); // Discard the two padding bits
 
-    let (header, _) = PhyCodedHeader::read(&mut reader).unwrap();
-    let frame_length = get_frame_length_from_header(&header);
-    Ok(frame_length)
+    Ok(PhyCodedHeader::read(&mut reader).unwrap())
+}
+
+fn read_header_candidates(
+    buffer: &[u8],
+    count: usize,
+) -> Result<Vec<(PhyCodedHeader, usize), MAX_HEADER_CANDIDATES>, ReadError> {
+    if buffer.len() < CODED_HEADER_SIZE {
+        return Err(ReadError::NotEnoughBytes);
+    }
+
+    let mut reader = BitReader::from_slice(buffer);
+    reader.read_bits::<usize>(2).unwrap(); // Discard the two padding bits
+
+    let mut candidates = PhyCodedHeader::read_candidates(&mut reader).unwrap();
+    candidates.truncate(count.clamp(1, MAX_HEADER_CANDIDATES));
+    Ok(candidates)
+}
+
+fn read_soft_header(llrs: &[Llr]) -> Result<(PhyCodedHeader, usize), ReadError> {
+    let candidates = read_soft_header_candidates(llrs, 1)?;
+    Ok(candidates[0])
+}
+
+/// Rank the coded header candidates by their correlation with the soft bits.
+/// The candidates are returned with their hard decision distance.
+fn read_soft_header_candidates(
+    llrs: &[Llr],
+    count: usize,
+) -> Result<Vec<(PhyCodedHeader, usize), MAX_HEADER_CANDIDATES>, ReadError> {
+    let code_llrs = llrs
+        .get(2..2 + phycodedheader::CODE_BITS)
+        .ok_or(ReadError::NotEnoughBytes)?;
+
+    let mut candidates =
+        PhyCodedHeader::read_soft_candidates::<MAX_HEADER_CANDIDATES>(code_llrs).unwrap();
+    candidates.truncate(count.clamp(1, MAX_HEADER_CANDIDATES));
+
+    Ok(candidates
+        .into_iter()
+        .map(|(header, _)| (header, header.hard_distance(code_llrs)))
+        .collect())
+}
+
+/// Decode the frame with each header candidate in turn until one passes the CRC.
+/// A candidate whose frame does not fit the buffer or the decoder is skipped.
+/// The error for the closest candidate is returned if none of them do.
+fn read_candidates<R>(
+    candidates: Vec<(PhyCodedHeader, usize), MAX_HEADER_CANDIDATES>,
+    mut read: impl FnMut(PhyCodedHeader, usize, usize) -> Result<R, ReadError>,
+) -> Result<R, ReadError> {
+    let mut first_error = None;
+
+    for (index, (header, distance)) in candidates.into_iter().enumerate() {
+        match read(header, distance, index) {
+            Err(
+                error @ (ReadError::PhlDecodeError
+                | ReadError::NotEnoughBytes
+                | ReadError::Capacity),
+            ) => {
+                first_error.get_or_insert(error);
+            }
+            result => return result,
+        }
+    }
+
+    Err(first_error.unwrap())
+}
+
+/// Get the frame length for receptions that must all have the same coded header
+fn get_combined_frame_length(
+    receptions: impl Iterator<Item = Result<(PhyCodedHeader, usize), ReadError>>,
+) -> Result<usize, ReadError> {
+    let mut combined: Option<PhyCodedHeader> = None;
+
+    for reception in receptions {
+        let (header, received_length) = reception?;
+        if received_length < get_frame_length_from_header(&header) {
+            return Err(ReadError::NotEnoughBytes);
+        }
+
+        match &combined {
+            Some(combined) if *combined != header => return Err(ReadError::ReceptionMismatch),
+            Some(_) => {}
+            None => combined = Some(header),
+        }
+    }
+
+    combined
+        .map(|header| get_frame_length_from_header(&header))
+        .ok_or(ReadError::NotEnoughBytes)
 }
 
 fn get_frame_length_from_header(header: &PhyCodedHeader) -> usize {
@@ -88,14 +284,376 @@ fn get_frame_length_from_header(header: &PhyCodedHeader) -> usize {
     HEADER_SIZE + block_length + (parity_bits + 7) / 8
 }
 
-impl<A: Layer> Phl<A> {
+impl<
+        A: Layer,
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+    >
+    Phl<
+        A,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    >
+{
     pub fn new(above: A) -> Self {
+        const {
+            assert!(BLOCK_LENGTH == max_block_length(MBAL_MAX));
+            assert!(BLOCK_BITS == block_bits(MBAL_MAX));
+            assert!(FIRST_TRELLIS_BITS == first_trellis_bits(MBAL_MAX));
+            assert!(SECOND_TRELLIS_BITS == second_trellis_bits(MBAL_MAX));
+            assert!(TRELLIS_BITS == all_trellis_bits(MBAL_MAX));
+            assert!(FRAME_BITS == frame_bits(MBAL_MAX));
+        }
+
         Self {
             above,
             encoder: TurboEncoder::new(),
             decoder: UmtsTurboDecoder::new(),
             max_decode_iterations: 10,
+            hard_decision_llr: 4,
+            max_erasure_ratio: 0.0,
+            max_bit_flips: 0,
+            bit_flip_candidates: 8,
+            header_candidates: 1,
+        }
+    }
+
+    /// Decode a frame in place and get the MBAL block without passing it to the layer above.
+    /// The systematic block in the buffer is replaced by the corrected block if decoding was needed.
+    pub fn read_in_place<'a>(
+        &self,
+        buffer: &'a mut [u8],
+    ) -> Result<(PhlFields, RxMetadata, &'a [u8]), ReadError> {
+        let candidates = read_header_candidates(buffer, self.header_candidates)?;
+        let (header, rx, decoded) =
+            read_candidates(candidates, |header, header_distance, header_candidate| {
+                self.decode_with_header(buffer, header, header_distance, header_candidate)
+                    .map(|(rx, decoded)| (header, rx, decoded))
+            })?;
+
+        if let Some(decoded) = decoded {
+            buffer[HEADER_SIZE..HEADER_SIZE + decoded.len()].copy_from_slice(&decoded);
+        }
+
+        let buffer: &'a [u8] = buffer;
+        let fields = PhlFields {
+            code_rate: header.rate,
+        };
+        Ok((fields, rx, &buffer[HEADER_SIZE..HEADER_SIZE + header.data_length]))
+    }
+
+    /// Encode a block, including its CRC, and write the frame
+    pub(crate) fn write_block(
+        &self,
+        writer: &mut impl Writer,
+        code_rate: CodeRate,
+        block: &[u8],
+    ) -> Result<(), WriteError> {
+        // Run Turbo encoder
+        let input = block.view_bits::<Msb0>();
+        debug_assert_eq!(8 * block.len(), input.len());
+
+        let interleaver = interleaver::new(input.len()).unwrap();
+        let mut output = TurboEncoderOutput::new(code_rate, input.len());
+        self.encoder.encode(input, &interleaver, &mut output);
+        let result = output.get_result();
+
+        // Prepare header by writing bits
+        let mut header = BitBuffer::<HEADER_SIZE>::new();
+        header.push(true);
+        header.push(true);
+        PhyCodedHeader::new(code_rate, block.len() - 4).write(&mut header);
+
+        let index = header.len();
+        header.resize(header.len() + 2 * 6, false);
+
+        let termination = &mut header.as_mut_bitslice()[index..];
+        termination.store_be(result.termination());
+
+        // Write header
+        assert_eq!(96, header.len());
+        writer.write(header.as_raw_slice())?;
+
+        // Write systematic
+        writer.write(result.systematic.as_raw_slice())?;
+
+        // Write parity
+        writer.write(result.parity.as_raw_slice())?;
+
+        Ok(())
+    }
+
+    /// Read a packet from soft bits.
+    /// There must be one llr for each frame bit in the order they are transmitted,
+    /// and a positive value is in favor of a one bit.
+    pub fn read_soft<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        llrs: &[Llr],
+    ) -> Result<(), ReadError> {
+        self.read_llrs(packet, llrs, 1)
+    }
+
+    /// Read a packet from several receptions of the same frame.
+    /// The receptions are combined into soft bits before decoding,
+    /// and they must all have the same coded header.
+    /// Frames with more than `MBAL_MAX` MBAL bytes can not be combined.
+    pub fn read_combined<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        receptions: &[&[u8]],
+    ) -> Result<(), ReadError> {
+        let frame_length = get_combined_frame_length(
+            receptions
+                .iter()
+                .map(|buffer| read_header(buffer).map(|(header, _)| (header, buffer.len()))),
+        )?;
+
+        let mut combined = Vec::<Llr, FRAME_BITS>::new();
+        combined
+            .resize(8 * frame_length, 0)
+            .map_err(|_| ReadError::Capacity)?;
+        for buffer in receptions {
+            let bits = buffer[..frame_length].view_bits::<Msb0>();
+            for (llr, bit) in combined.iter_mut().zip(bits.iter()) {
+                *llr = llr.saturating_add((*bit).mul(self.hard_decision_llr));
+            }
+        }
+
+        self.read_llrs(packet, &combined, receptions.len())
+    }
+
+    /// Read a packet from several soft bit receptions of the same frame.
+    /// The llrs are added before decoding, and the receptions must all have the same coded header.
+    /// Frames with more than `MBAL_MAX` MBAL bytes can not be combined.
+    pub fn read_soft_combined<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        receptions: &[&[Llr]],
+    ) -> Result<(), ReadError> {
+        let frame_length = get_combined_frame_length(
+            receptions
+                .iter()
+                .map(|llrs| read_soft_header(llrs).map(|(header, _)| (header, llrs.len() / 8))),
+        )?;
+
+        let mut combined = Vec::<Llr, FRAME_BITS>::new();
+        combined
+            .resize(8 * frame_length, 0)
+            .map_err(|_| ReadError::Capacity)?;
+        for llrs in receptions {
+            for (combined, llr) in combined.iter_mut().zip(llrs.iter()) {
+                *combined = combined.saturating_add(*llr);
+            }
+        }
+
+        self.read_llrs(packet, &combined, receptions.len())
+    }
+
+    fn read_llrs<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        llrs: &[Llr],
+        combined_receptions: usize,
+    ) -> Result<(), ReadError> {
+        let candidates = read_soft_header_candidates(llrs, self.header_candidates)?;
+
+        read_candidates(candidates, |header, header_distance, header_candidate| {
+            self.read_llrs_with_header(
+                packet,
+                llrs,
+                header,
+                header_distance,
+                header_candidate,
+                combined_receptions,
+            )
+        })
+    }
+
+    fn read_llrs_with_header<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        llrs: &[Llr],
+        header: PhyCodedHeader,
+        header_distance: usize,
+        header_candidate: usize,
+        combined_receptions: usize,
+    ) -> Result<(), ReadError> {
+        let frame_length = get_frame_length_from_header(&header);
+        let erased_bits = Self::get_erased_bits(&header, llrs.len())?;
+
+        let mut padded = Vec::<Llr, FRAME_BITS>::new();
+        let llrs = if erased_bits > 0 {
+            padded
+                .extend_from_slice(llrs)
+                .map_err(|_| ReadError::Capacity)?;
+            padded
+                .resize(8 * frame_length, 0)
+                .map_err(|_| ReadError::Capacity)?;
+            padded.as_slice()
+        } else {
+            llrs
+        };
+
+        let data_length = header.data_length;
+        let block_length = data_length + 4; // CRC32 is part of the encoded block
+        let block_end = HEADER_SIZE + block_length;
+        let systematic = &llrs[8 * HEADER_SIZE..8 * block_end];
+        let mut block = Vec::<u8, BLOCK_LENGTH>::new();
+        block
+            .resize(block_length, 0)
+            .map_err(|_| ReadError::Capacity)?;
+        hard_decisions(systematic, &mut block);
+
+        let rx = RxMetadata {
+            header_distance,
+            combined_receptions,
+            erased_bits,
+            header_candidate,
+            ..Default::default()
+        };
+
+        if is_valid_crc(data_length, &block) {
+            packet.phl = Some(PhlFields {
+                code_rate: header.rate,
+            });
+            packet.rx = Some(rx);
+
+            self.above.read(packet, &block[..data_length])
+        } else {
+            Self::check_capacity(&header)?;
+            self.check_erasures(&header, erased_bits)?;
+
+            let input = TurboDecoderInput::from_llrs(
+                header.rate,
+                systematic,
+                &llrs[8 * block_end..8 * frame_length],
+                &llrs[TERMINATION_OFFSET..TERMINATION_OFFSET + 6],
+                &llrs[TERMINATION_OFFSET + 6..8 * HEADER_SIZE],
+            );
+
+            let (rx, block) = self.decode(rx, &block, &input)?;
+            packet.phl = Some(PhlFields {
+                code_rate: header.rate,
+            });
+            packet.rx = Some(rx);
+
+            self.above.read(packet, &block[..data_length])
+        }
+    }
+
+    /// Decode the block of a frame with the given coded header.
+    /// No block is returned if the systematic block in the buffer passes the CRC.
+    fn decode_with_header(
+        &self,
+        buffer: &[u8],
+        header: PhyCodedHeader,
+        header_distance: usize,
+        header_candidate: usize,
+    ) -> Result<(RxMetadata, Option<Vec<u8, BLOCK_LENGTH>>), ReadError> {
+        let frame_length = get_frame_length_from_header(&header);
+        let erased_bits = Self::get_erased_bits(&header, 8 * buffer.len())?;
+
+        // The termination is the last 12 bits of the header
+        let termination = u16::from_be_bytes([buffer[HEADER_SIZE - 2], buffer[HEADER_SIZE - 1]]);
+        let first_termination = EncoderTermination((termination as usize >> 6) & 0x3F);
+        let second_termination = EncoderTermination(termination as usize & 0x3F);
+
+        let data_length = header.data_length;
+        let block_length = data_length + 4; // CRC32 is part of the encoded block
+        let block_end = HEADER_SIZE + block_length;
+        let block = &buffer[HEADER_SIZE..block_end];
+
+        let rx = RxMetadata {
+            header_distance,
+            combined_receptions: 1,
+            erased_bits,
+            header_candidate,
+            ..Default::default()
+        };
+
+        if is_valid_crc(data_length, block) {
+            Ok((rx, None))
+        } else {
+            Self::check_capacity(&header)?;
+            self.check_erasures(&header, erased_bits)?;
+
+            let parity = &buffer[block_end..frame_length.min(buffer.len())];
+            let input = TurboDecoderInput::new(
+                header.rate,
+                block,
+                parity,
+                first_termination,
+                second_termination,
+                self.hard_decision_llr,
+            );
+
+            let (rx, block) = self.decode(rx, block, &input)?;
+            Ok((rx, Some(block)))
+        }
+    }
+
+    /// Get the number of parity bits that are missing from a possibly truncated frame.
+    /// The header and the systematic block must be complete.
+    fn get_erased_bits(header: &PhyCodedHeader, received_bits: usize) -> Result<usize, ReadError> {
+        let frame_bits = 8 * get_frame_length_from_header(header);
+        let systematic_bits = 8 * (HEADER_SIZE + header.data_length + 4);
+        if received_bits >= frame_bits {
+            Ok(0)
+        } else if received_bits < systematic_bits {
+            Err(ReadError::NotEnoughBytes)
+        } else {
+            Ok(frame_bits - received_bits)
+        }
+    }
+
+    /// Verify that the encoded block fits into the turbo decoder
+    fn check_capacity(header: &PhyCodedHeader) -> Result<(), ReadError> {
+        if header.data_length > MBAL_MAX {
+            return Err(ReadError::Capacity);
         }
+
+        Ok(())
+    }
+
+    /// Verify that few enough parity bits are missing for the frame to be decoded
+    fn check_erasures(&self, header: &PhyCodedHeader, erased_bits: usize) -> Result<(), ReadError> {
+        let systematic_length = HEADER_SIZE + header.data_length + 4;
+        let parity_bits = 8 * (get_frame_length_from_header(header) - systematic_length);
+        if erased_bits as f32 > self.max_erasure_ratio * parity_bits as f32 {
+            return Err(ReadError::NotEnoughBytes);
+        }
+
+        Ok(())
+    }
+
+    /// Run the turbo decoder and get the corrected block
+    fn decode(
+        &self,
+        mut rx: RxMetadata,
+        block: &[u8],
+        input: &TurboDecoderInput<BLOCK_BITS>,
+    ) -> Result<(RxMetadata, Vec<u8, BLOCK_LENGTH>), ReadError> {
+        let data_length = block.len() - 4;
+        let result = self
+            .run_decoder(data_length, input)
+            .ok_or(ReadError::PhlDecodeError)?;
+
+        rx.decode_iterations = result.1;
+        rx.decode_distance = Self::distance(block, &result.0);
+        rx.bit_flips = result.2;
+
+        Ok((rx, result.0))
     }
 
     fn distance<T: Integral>(first: &[T], second: &[T]) -> usize {
@@ -111,8 +669,8 @@ impl<A: Layer> Phl<A> {
     fn run_decoder(
         &self,
         data_length: usize,
-        input: &TurboDecoderInput<MAX_BLOCK_BITS>,
-    ) -> Option<(Vec<u8, MAX_BLOCK>, usize)> {
+        input: &TurboDecoderInput<BLOCK_BITS>,
+    ) -> Option<(Vec<u8, BLOCK_LENGTH>, usize, usize)> {
         let interleaver = interleaver::new(input.symbols.len())?;
         let mut decoding = self.decoder.decode(
             &input.symbols,
@@ -121,88 +679,89 @@ impl<A: Layer> Phl<A> {
             &input.second_termination,
         );
 
-        let mut hard = BitVec::<u8, Msb0>::with_capacity(input.symbols.len());
+        let mut hard = BitBuffer::<BLOCK_LENGTH>::new();
 
         for iteration in 1..=self.max_decode_iterations {
             decoding.run_decode_iteration();
 
+            hard.clear();
             for llr in decoding.get_result() {
                 hard.push(*llr > 0);
             }
 
             if is_valid_crc(data_length, hard.as_raw_slice()) {
-                return Some((Vec::from_slice(hard.as_raw_slice()).unwrap(), iteration));
+                return Some((Vec::from_slice(hard.as_raw_slice()).unwrap(), iteration, 0));
             }
+        }
 
-            hard.clear();
+        if self.max_bit_flips > 0 && self.max_decode_iterations > 0 {
+            // Try to correct the remaining errors among the least reliable bits
+            let candidates = least_reliable(decoding.get_result(), self.bit_flip_candidates);
+            let bit_flips = flip_until_valid_crc(
+                data_length,
+                hard.as_raw_mut_slice(),
+                &candidates,
+                self.max_bit_flips,
+            )?;
+
+            return Some((
+                Vec::from_slice(hard.as_raw_slice()).unwrap(),
+                self.max_decode_iterations,
+                bit_flips,
+            ));
         }
 
         None
     }
 }
 
-impl<A: Layer> Layer for Phl<A> {
-    fn read<const N: usize>(&self, packet: &mut Packet<N>, buffer: &[u8]) -> Result<(), ReadError> {
-        let mut reader = BitReader::from_slice(buffer);
-        reader
-            .read_bits::<usize>(2)
-            .ok_or(ReadError::NotEnoughBytes)?; // Discard the two padding bits
-
-        let (header, header_distance) =
-            PhyCodedHeader::read(&mut reader).ok_or(ReadError::NotEnoughBytes)?;
-        let frame_length = get_frame_length_from_header(&header);
-        if buffer.len() < frame_length {
-            return Err(ReadError::NotEnoughBytes);
-        }
-
-        let first_termination = EncoderTermination(reader.read_bits(6).unwrap());
-        let second_termination = EncoderTermination(reader.read_bits(6).unwrap());
-
-        let data_length = header.data_length;
-        let block_length = data_length + 4; // CRC32 is part of the encoded block
-        let block_end = HEADER_SIZE + block_length;
-        let block = &buffer[HEADER_SIZE..block_end];
-
-        if is_valid_crc(data_length, block) {
-            packet.phl = Some(PhlFields {
-                code_rate: header.rate,
-                header_distance,
-                decode_iterations: 0,
-                decode_distance: 0,
-            });
-
-            self.above.read(packet, &block[..data_length])
-        } else {
-            let parity = &buffer[HEADER_SIZE + block_length..];
-            // TODO
-            const SNR: Llr = 4;
-            let input = TurboDecoderInput::new(
-                header.rate,
-                block,
-                parity,
-                first_termination,
-                second_termination,
-                SNR,
-            );
-            let result = self
-                .run_decoder(data_length, &input)
-                .ok_or(ReadError::PhlDecodeError)?;
-
+impl<
+        A: Layer,
+        const MBAL_MAX: usize,
+        const BLOCK_LENGTH: usize,
+        const BLOCK_BITS: usize,
+        const FIRST_TRELLIS_BITS: usize,
+        const SECOND_TRELLIS_BITS: usize,
+        const TRELLIS_BITS: usize,
+        const FRAME_BITS: usize,
+    > Layer
+    for Phl<
+        A,
+        MBAL_MAX,
+        BLOCK_LENGTH,
+        BLOCK_BITS,
+        FIRST_TRELLIS_BITS,
+        SECOND_TRELLIS_BITS,
+        TRELLIS_BITS,
+        FRAME_BITS,
+    >
+{
+    type Extension = A::Extension;
+
+    fn read<const N: usize>(
+        &self,
+        packet: &mut Packet<N, A::Extension>,
+        buffer: &[u8],
+    ) -> Result<(), ReadError> {
+        let candidates = read_header_candidates(buffer, self.header_candidates)?;
+
+        read_candidates(candidates, |header, header_distance, header_candidate| {
+            let (rx, decoded) =
+                self.decode_with_header(buffer, header, header_distance, header_candidate)?;
+            let block = decoded.as_deref().unwrap_or(&buffer[HEADER_SIZE..]);
             packet.phl = Some(PhlFields {
                 code_rate: header.rate,
-                header_distance,
-                decode_iterations: result.1,
-                decode_distance: Self::distance(block, &result.0),
             });
+            packet.rx = Some(rx);
 
-            self.above.read(packet, &result.0[..data_length])
-        }
+            self.above.read(packet, &block[..header.data_length])
+        })
     }
 
     fn write<const N: usize>(
         &self,
         writer: &mut impl Writer,
-        packet: &Packet<N>,
+        packet: &Packet<N, A::Extension>,
     ) -> Result<(), WriteError> {
         let fields = packet.phl.as_ref().unwrap();
         let mut block = Vec::<u8, MAX_BLOCK>::new();
@@ -221,38 +780,7 @@ impl<A: Layer> Layer for Phl<A> {
             .extend_from_slice(crc.to_be_bytes().as_slice())
             .map_err(|_| WriteError::Capacity)?;
 
-        // Run Turbo encoder
-        let input = block.view_bits::<Msb0>();
-        debug_assert_eq!(8 * block.len(), input.len());
-
-        let interleaver = interleaver::new(input.len()).unwrap();
-        let mut output = TurboEncoderOutput::new(fields.code_rate, input.len());
-        self.encoder.encode(input, &interleaver, &mut output);
-        let result = output.get_result();
-
-        // Prepare header by writing bits
-        let mut header = BitVec::<u8, Msb0>::with_capacity(96);
-        header.push(true);
-        header.push(true);
-        PhyCodedHeader::new(fields.code_rate, block.len() - 4).write(&mut header);
-
-        let index = header.len();
-        header.resize(header.len() + 2 * 6, false);
-
-        let termination = header.split_at_mut(index).1;
-        termination.store_be(result.termination());
-
-        // Write header
-        assert_eq!(96, header.len());
-        writer.write(header.as_raw_slice())?;
-
-        // Write systematic
-        writer.write(result.systematic.as_raw_slice())?;
-
-        // Write parity
-        writer.write(result.parity.as_raw_slice())?;
-
-        Ok(())
+        self.write_block(writer, fields.code_rate, &block)
     }
 }
 
@@ -268,3 +796,134 @@ fn is_valid_crc(data_length: usize, block: &[u8]) -> bool {
 
     actual == expected
 }
+
+/// Get the indexes of the `count` bits with the smallest llr magnitude, least reliable first
+fn least_reliable<'a>(
+    llrs: impl Iterator<Item = &'a Llr>,
+    count: usize,
+) -> Vec<usize, MAX_BIT_FLIP_CANDIDATES> {
+    let count = count.min(MAX_BIT_FLIP_CANDIDATES);
+    let mut candidates = Vec::<(Llr, usize), MAX_BIT_FLIP_CANDIDATES>::new();
+
+    for (index, llr) in llrs.enumerate() {
+        let reliability = llr.saturating_abs();
+        let position = candidates.partition_point(|(other, _)| *other <= reliability);
+        if position < count {
+            if candidates.len() == count {
+                candidates.pop();
+            }
+            candidates.insert(position, (reliability, index)).unwrap();
+        }
+    }
+
+    candidates.iter().map(|(_, index)| *index).collect()
+}
+
+/// Flip combinations of up to `max_flips` candidate bits in the block until the CRC is valid,
+/// trying fewer flips first. Returns the number of flipped bits.
+fn flip_until_valid_crc(
+    data_length: usize,
+    block: &mut [u8],
+    candidates: &[usize],
+    max_flips: usize,
+) -> Option<usize> {
+    (1..=max_flips.min(candidates.len()))
+        .find(|&flips| flip_combinations(data_length, block, candidates, flips))
+}
+
+fn flip_combinations(
+    data_length: usize,
+    block: &mut [u8],
+    candidates: &[usize],
+    flips: usize,
+) -> bool {
+    if flips == 0 {
+        return is_valid_crc(data_length, block);
+    }
+
+    for (i, &index) in candidates.iter().enumerate() {
+        flip_bit(block, index);
+        if flip_combinations(data_length, block, &candidates[i + 1..], flips - 1) {
+            return true;
+        }
+        flip_bit(block, index);
+    }
+
+    false
+}
+
+fn flip_bit(bytes: &mut [u8], index: usize) {
+    let bits = bytes.view_bits_mut::<Msb0>();
+    let bit = bits[index];
+    bits.set(index, !bit);
+}
+
+fn hard_decisions(llrs: &[Llr], bytes: &mut [u8]) {
+    let bits = bytes.view_bits_mut::<Msb0>();
+    assert_eq!(bits.len(), llrs.len());
+
+    for (mut bit, llr) in bits.iter_mut().zip(llrs) {
+        *bit = *llr > 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(data: &[u8]) -> Vec<u8, MAX_BLOCK> {
+        let mut digest = CRC.digest();
+        digest.update(&[data.len() as u8]);
+        digest.update(data);
+
+        let mut block = Vec::from_slice(data).unwrap();
+        block
+            .extend_from_slice(&digest.finalize().to_be_bytes())
+            .unwrap();
+        block
+    }
+
+    #[test]
+    fn can_get_least_reliable_bits() {
+        // Given
+        let llrs: [Llr; 8] = [5, -1, 7, 0, -3, 2, -6, 4];
+
+        // When
+        let candidates = least_reliable(llrs.iter(), 3);
+
+        // Then
+        assert_eq!([3, 1, 5], candidates.as_slice());
+    }
+
+    #[test]
+    fn can_flip_until_valid_crc() {
+        // Given
+        let expected = block(&[0x12, 0x34, 0x56, 0x78]);
+        let mut block = expected.clone();
+        block[0] ^= 0x80;
+        block[5] ^= 0x01;
+
+        // When
+        let flips = flip_until_valid_crc(4, &mut block, &[3, 0, 20, 47, 9], 2);
+
+        // Then
+        assert_eq!(Some(2), flips);
+        assert_eq!(expected, block);
+    }
+
+    #[test]
+    fn can_not_flip_more_than_max_flips() {
+        // Given
+        let mut block = block(&[0x12, 0x34, 0x56, 0x78]);
+        block[0] ^= 0x80;
+        block[5] ^= 0x01;
+        let corrupted = block.clone();
+
+        // When
+        let flips = flip_until_valid_crc(4, &mut block, &[3, 0, 20, 47, 9], 1);
+
+        // Then
+        assert_eq!(None, flips);
+        assert_eq!(corrupted, block);
+    }
+}
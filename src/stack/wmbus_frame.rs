@@ -0,0 +1,363 @@
+//! Conversion between packets and wireless M-Bus link layer frames.
+//!
+//! The MBAL header carries the same information as the wM-Bus C-, M- and A-fields,
+//! and the M-Bus data starts with the CI-field in both protocols.
+//! The MBAL prioritized flag, the PHL fields and the extension of custom layers have no wM-Bus counterpart.
+//! They are kept in [`LinkIqFields`] when a packet is written, and given back to [`read_frame`],
+//! so that a packet survives the round trip through a wM-Bus frame.
+//! The reception metadata is not part of the conversion.
+
+use crc::{Crc, CRC_16_EN_13757};
+use heapless::Vec;
+use wmbus::WMBusAddress;
+
+use super::{
+    filter::ADDRESS_SIZE,
+    mbal::{MbalCommand, MbalControl, MbalFields, MbalFunctionCode},
+    phl::PhlFields,
+    Packet, ReadError, WriteError, Writer,
+};
+
+/// The C-field of an SND-NR frame
+pub const C_SND_NR: u8 = 0x44;
+
+/// The C-field of an SND-IR frame
+pub const C_SND_IR: u8 = 0x46;
+
+/// The length of the first block with the L-, C-, M- and A-fields
+const FIRST_BLOCK_SIZE: usize = 2 + ADDRESS_SIZE;
+
+/// The maximum number of data bytes in the following blocks of format A
+const BLOCK_SIZE_A: usize = 16;
+
+/// The maximum number of data bytes in the second block of format B
+const SECOND_BLOCK_SIZE_B: usize = 116;
+
+/// The maximum value of the L-field
+const L_MAX: usize = 255;
+
+/// The largest L-field of a format B frame without a third block
+const L_TWO_BLOCKS_B: usize = 127;
+
+const CRC: Crc<u16> = Crc::<u16>::new(&CRC_16_EN_13757);
+
+/// The wM-Bus frame format, which tells how the frame is divided into CRC protected blocks
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FrameFormat {
+    /// A CRC after the first block of 10 bytes and after every following block of 16 bytes
+    A,
+    /// A CRC after the second block and after the optional third block.
+    /// The L-field includes the CRC bytes.
+    B,
+}
+
+/// The fields of a packet that have no counterpart in a wM-Bus frame
+#[derive(Default)]
+pub struct LinkIqFields<X = ()> {
+    pub phl: Option<PhlFields>,
+    pub is_prioritized: bool,
+    /// The fields of custom layers
+    pub extension: X,
+}
+
+impl<X: Clone> LinkIqFields<X> {
+    /// Get the fields of a packet that are lost when it is written as a wM-Bus frame
+    pub fn of<const N: usize>(packet: &Packet<N, X>) -> Self {
+        Self {
+            phl: packet.phl.as_ref().map(|phl| PhlFields {
+                code_rate: phl.code_rate,
+            }),
+            is_prioritized: packet
+                .mbal
+                .as_ref()
+                .is_some_and(|mbal| mbal.control.is_prioritized),
+            extension: packet.extension.clone(),
+        }
+    }
+}
+
+/// Get the C-field of a function code
+pub const fn c_field(function_code: MbalFunctionCode) -> u8 {
+    match function_code {
+        MbalFunctionCode::SendUnsolicitedApplicationData => C_SND_NR,
+        MbalFunctionCode::SendInstallationRequest => C_SND_IR,
+    }
+}
+
+/// Get the function code of a C-field
+pub const fn function_code(c_field: u8) -> Option<MbalFunctionCode> {
+    match c_field {
+        C_SND_NR => Some(MbalFunctionCode::SendUnsolicitedApplicationData),
+        C_SND_IR => Some(MbalFunctionCode::SendInstallationRequest),
+        _ => None,
+    }
+}
+
+/// Get the length of a wM-Bus frame including the L-field and the CRCs from its L-field
+pub fn frame_length(l_field: u8, format: FrameFormat) -> Result<usize, ReadError> {
+    let l_field = l_field as usize;
+    match format {
+        FrameFormat::A if l_field >= FIRST_BLOCK_SIZE - 1 => {
+            let blocks = 1 + (l_field + 1 - FIRST_BLOCK_SIZE).div_ceil(BLOCK_SIZE_A);
+            Ok(1 + l_field + 2 * blocks)
+        }
+        // A third block must have at least one data byte
+        FrameFormat::B
+            if (FIRST_BLOCK_SIZE + 1..=L_TWO_BLOCKS_B).contains(&l_field)
+                || l_field >= L_TWO_BLOCKS_B + 3 =>
+        {
+            Ok(1 + l_field)
+        }
+        _ => Err(ReadError::WMBusLengthError),
+    }
+}
+
+/// Write a packet as a wM-Bus frame.
+/// The fields without a wM-Bus counterpart are not written, see [`LinkIqFields::of`].
+pub fn write_frame<const N: usize, X>(
+    writer: &mut impl Writer,
+    packet: &Packet<N, X>,
+    format: FrameFormat,
+) -> Result<(), WriteError> {
+    let mbal = packet.mbal.as_ref().unwrap();
+    let data = packet.mbus_data.as_slice();
+
+    let l_field = match format {
+        FrameFormat::A => FIRST_BLOCK_SIZE - 1 + data.len(),
+        FrameFormat::B if data.len() <= SECOND_BLOCK_SIZE_B => {
+            FIRST_BLOCK_SIZE - 1 + data.len() + 2
+        }
+        FrameFormat::B => FIRST_BLOCK_SIZE - 1 + data.len() + 4,
+    };
+    if l_field > L_MAX {
+        return Err(WriteError::Capacity);
+    }
+
+    let mut first = [0; FIRST_BLOCK_SIZE];
+    first[0] = l_field as u8;
+    first[1] = c_field(mbal.command.function_code);
+    first[2..].copy_from_slice(mbal.address.get_bytes().as_slice());
+
+    match format {
+        FrameFormat::A => {
+            write_block(writer, &[&first])?;
+            for block in data.chunks(BLOCK_SIZE_A) {
+                write_block(writer, &[block])?;
+            }
+        }
+        FrameFormat::B => {
+            let (second, third) = data.split_at(data.len().min(SECOND_BLOCK_SIZE_B));
+            write_block(writer, &[&first, second])?;
+            if !third.is_empty() {
+                write_block(writer, &[third])?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Write the parts of a block followed by their CRC
+fn write_block(writer: &mut impl Writer, parts: &[&[u8]]) -> Result<(), WriteError> {
+    let mut digest = CRC.digest();
+    for part in parts {
+        digest.update(part);
+        writer.write(part)?;
+    }
+    writer.write(&digest.finalize().to_be_bytes())
+}
+
+/// Read a packet from a wM-Bus frame.
+/// The fields without a wM-Bus counterpart are taken from `fields`, e.g. as they were before the packet was written.
+pub fn read_frame<const N: usize, X>(
+    frame: &[u8],
+    format: FrameFormat,
+    fields: LinkIqFields<X>,
+) -> Result<Packet<N, X>, ReadError> {
+    let l_field = *frame.first().ok_or(ReadError::NotEnoughBytes)?;
+    let length = frame_length(l_field, format)?;
+    let frame = frame.get(..length).ok_or(ReadError::NotEnoughBytes)?;
+
+    // Verify the CRCs and collect the data bytes without them
+    let mut data = Vec::<u8, { 1 + L_MAX }>::new();
+    let mut append = |block: &[u8]| -> Result<(), ReadError> {
+        let (block, crc) = block.split_at(block.len() - 2);
+        if CRC.checksum(block) != u16::from_be_bytes([crc[0], crc[1]]) {
+            return Err(ReadError::WMBusCrcError);
+        }
+        data.extend_from_slice(block).unwrap();
+        Ok(())
+    };
+
+    match format {
+        FrameFormat::A => {
+            let (first, rest) = frame.split_at(FIRST_BLOCK_SIZE + 2);
+            append(first)?;
+            for block in rest.chunks(BLOCK_SIZE_A + 2) {
+                append(block)?;
+            }
+        }
+        FrameFormat::B => {
+            let (blocks, rest) = frame.split_at(frame.len().min(1 + L_TWO_BLOCKS_B));
+            append(blocks)?;
+            if !rest.is_empty() {
+                append(rest)?;
+            }
+        }
+    }
+
+    let function_code = function_code(data[1]).ok_or(ReadError::MBalCommandError)?;
+    let address = WMBusAddress::from_bytes(data[2..FIRST_BLOCK_SIZE].try_into().unwrap())
+        .map_err(|_| ReadError::MBalAddressError)?;
+
+    Ok(Packet {
+        phl: fields.phl,
+        mbal: Some(MbalFields {
+            control: MbalControl {
+                is_prioritized: fields.is_prioritized,
+            },
+            address,
+            command: MbalCommand { function_code },
+        }),
+        mbus_data: Vec::from_slice(&data[FIRST_BLOCK_SIZE..]).map_err(|_| ReadError::Capacity)?,
+        extension: fields.extension,
+        rx: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{fec::CodeRate, stack::PacketBuilder};
+
+    use super::*;
+
+    fn packet(mbus_data: &[u8]) -> Packet {
+        PacketBuilder::new()
+            .code_rate(CodeRate::OneHalf)
+            .prioritized(true)
+            .address(WMBusAddress::new(
+                0x2c2d.try_into().unwrap(),
+                71006389,
+                0x34,
+                0x04.try_into().unwrap(),
+            ))
+            .function_code(MbalFunctionCode::SendInstallationRequest)
+            .mbus_data(mbus_data)
+            .build()
+            .unwrap()
+    }
+
+    #[test]
+    fn can_write_format_a() {
+        // Given
+        let packet = packet(&[0x7A; 20]);
+        let mut frame = Vec::<u8, 64>::new();
+
+        // When
+        write_frame(&mut frame, &packet, FrameFormat::A).unwrap();
+
+        // Then
+        assert_eq!(1 + 9 + 20 + 3 * 2, frame.len());
+        assert_eq!(9 + 20, frame[0]);
+        assert_eq!(C_SND_IR, frame[1]);
+        assert_eq!(
+            [0x2D, 0x2C, 0x89, 0x63, 0x00, 0x71, 0x34, 0x04],
+            frame[2..10]
+        );
+        assert_eq!(CRC.checksum(&frame[..10]).to_be_bytes(), frame[10..12]);
+        assert_eq!(CRC.checksum(&frame[12..28]).to_be_bytes(), frame[28..30]);
+        assert_eq!(CRC.checksum(&frame[30..34]).to_be_bytes(), frame[34..36]);
+        assert_eq!(frame.len(), frame_length(frame[0], FrameFormat::A).unwrap());
+    }
+
+    #[test]
+    fn can_write_format_b() {
+        // Given
+        let packet = packet(&[0x7A; 20]);
+        let mut frame = Vec::<u8, 64>::new();
+
+        // When
+        write_frame(&mut frame, &packet, FrameFormat::B).unwrap();
+
+        // Then
+        assert_eq!(1 + 9 + 20 + 2, frame.len());
+        assert_eq!(9 + 20 + 2, frame[0]);
+        assert_eq!(CRC.checksum(&frame[..30]).to_be_bytes(), frame[30..32]);
+    }
+
+    #[test]
+    fn can_round_trip() {
+        for format in [FrameFormat::A, FrameFormat::B] {
+            for length in [0, 1, 16, 17, 116, 117, 200, 239] {
+                // Given
+                let mbus_data: std::vec::Vec<u8> = (0..length).map(|byte| byte as u8).collect();
+                let packet = packet(&mbus_data);
+                let mut frame = Vec::<u8, 300>::new();
+                write_frame(&mut frame, &packet, format).unwrap();
+
+                // When
+                let read: Packet = read_frame(&frame, format, LinkIqFields::of(&packet)).unwrap();
+
+                // Then
+                assert_eq!(frame.len(), frame_length(frame[0], format).unwrap());
+                assert_eq!(CodeRate::OneHalf, read.phl.unwrap().code_rate);
+                let mbal = read.mbal.unwrap();
+                assert!(mbal.control.is_prioritized);
+                assert_eq!(packet.mbal.as_ref().unwrap().address, mbal.address);
+                assert!(mbal.command.function_code == MbalFunctionCode::SendInstallationRequest);
+                assert_eq!(packet.mbus_data, read.mbus_data);
+            }
+        }
+    }
+
+    #[test]
+    fn can_not_read_corrupted_frame() {
+        for format in [FrameFormat::A, FrameFormat::B] {
+            // Given
+            let mut frame = Vec::<u8, 300>::new();
+            write_frame(&mut frame, &packet(&[0x7A; 150]), format).unwrap();
+            let last = frame.len() - 3;
+            frame[last] ^= 0x01;
+
+            // When
+            let result = read_frame::<300, ()>(&frame, format, LinkIqFields::default());
+
+            // Then
+            assert!(matches!(result, Err(ReadError::WMBusCrcError)));
+        }
+    }
+
+    #[test]
+    fn can_not_read_truncated_frame() {
+        // Given
+        let mut frame = Vec::<u8, 64>::new();
+        write_frame(&mut frame, &packet(&[0x7A; 20]), FrameFormat::A).unwrap();
+
+        // When
+        let result = read_frame::<64, ()>(
+            &frame[..frame.len() - 1],
+            FrameFormat::A,
+            LinkIqFields::default(),
+        );
+
+        // Then
+        assert!(matches!(result, Err(ReadError::NotEnoughBytes)));
+    }
+
+    #[test]
+    fn can_not_write_too_long_frame() {
+        // Given
+        let mut packet = Packet::<300> {
+            mbal: packet(&[]).mbal,
+            ..Default::default()
+        };
+        packet.mbus_data.extend_from_slice(&[0x7A; 243]).unwrap();
+        let mut frame = Vec::<u8, 300>::new();
+
+        // When
+        let result = write_frame(&mut frame, &packet, FrameFormat::B);
+
+        // Then
+        assert!(matches!(result, Err(WriteError::Capacity)));
+    }
+}
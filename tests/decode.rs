@@ -0,0 +1,167 @@
+//! Verify the linkiq-decode command-line tool.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+use linkiq::{
+    framelog::{LogWriter, RecordInfo},
+    pcap::{FrameInfo, PcapWriter},
+    stack::Channel,
+};
+
+const EXAMPLE41: &str = "CC48DE495CD17512402F093201372C341234121B166016617A01002005193229BCE64D651F1DED42687303B29AF6A6805336084A0CC4B4B92371A3CAB9FC9B4FFE09D35FE3FB1E3B5A49A71A342439873007BD8E4178777A827C723B8149BE18745008DB6E1F0133149679AC67A4E3FA083842991831";
+
+fn decode(args: &[&str], input: impl AsRef<[u8]>) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_linkiq-decode"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_ref())
+        .unwrap();
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn can_decode_hex_line_as_json() {
+    // When
+    let output = decode(&["--output", "json"], &format!("# Example 4.1\n{EXAMPLE41}\n"));
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let line: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!("1/2", line["code_rate"]);
+    assert_eq!("KAW", line["address"]["manufacturer"]);
+    assert_eq!("12341234", line["address"]["id"]);
+    assert_eq!(27, line["address"]["version"]);
+    assert_eq!(0x16, line["address"]["device_type"]);
+    assert_eq!("SND-IR", line["function_code"]);
+    assert!(line["payload"].as_str().unwrap().starts_with("7A0100"));
+}
+
+#[test]
+fn can_decode_hex_line_as_text() {
+    // When
+    let output = decode(&[], EXAMPLE41);
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("KAW 12341234 version 27 type 0x16"));
+    assert!(text.contains("SND-IR"));
+}
+
+#[test]
+fn can_dissect_corrupted_frame() {
+    // Given
+    let mut corrupted = EXAMPLE41.to_string();
+    // Clear the two one bits of the last identification byte
+    corrupted.replace_range(36..38, "00");
+
+    // When
+    let output = decode(&["--output", "dissect"], &corrupted);
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let text = String::from_utf8(output.stdout).unwrap();
+    assert!(text.contains("    Coded header [bits 2..84]"));
+    assert!(text.contains(
+        "        Identification [bits 120..152] = 0x34123400: 12341234 (2 bits corrected)"
+    ));
+    assert!(text.contains("  corrected bits: 147 150"));
+}
+
+#[test]
+fn can_report_decode_failure() {
+    // Given
+    let mut corrupted = EXAMPLE41.to_string();
+    corrupted.replace_range(24..120, &"0".repeat(96));
+
+    // When
+    let output = decode(&["--output", "json"], &format!("{EXAMPLE41}\n{corrupted}\n"));
+
+    // Then
+    assert_eq!(Some(1), output.status.code());
+    let lines: Vec<serde_json::Value> = output
+        .stdout
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(2, lines.len());
+    assert!(lines[0]["error"].is_null());
+    assert!(lines[1]["error"].is_string());
+}
+
+#[test]
+fn can_reject_invalid_hex() {
+    // When
+    let output = decode(&[], "CC48DE49ZZ\n");
+
+    // Then
+    assert_eq!(Some(2), output.status.code());
+}
+
+fn example41() -> Vec<u8> {
+    (0..EXAMPLE41.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&EXAMPLE41[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+#[test]
+fn can_decode_pcapng_capture() {
+    // Given
+    let frame = example41();
+    let mut writer = PcapWriter::new(Vec::new()).unwrap();
+    writer
+        .write(&FrameInfo::on_channel(0, Channel::B, Some(-90)), &frame)
+        .unwrap();
+    writer
+        .write(&FrameInfo::on_channel(1, Channel::C, None), &frame)
+        .unwrap();
+
+    // When
+    let output = decode(&["--format", "pcapng", "--output", "json"], writer.into_inner());
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let lines: Vec<serde_json::Value> = output
+        .stdout
+        .split(|&byte| byte == b'\n')
+        .filter(|line| !line.is_empty())
+        .map(|line| serde_json::from_slice(line).unwrap())
+        .collect();
+    assert_eq!(2, lines.len());
+    assert_eq!("KAW", lines[1]["address"]["manufacturer"]);
+}
+
+#[test]
+fn can_decode_frame_log() {
+    // Given
+    let info = RecordInfo {
+        timestamp: 0,
+        channel: Channel::A,
+        rssi: Some(-90),
+        noise_floor: None,
+    };
+    let mut writer = LogWriter::new(Vec::new());
+    writer.write(&info, &example41()).unwrap();
+    let mut log = writer.into_inner();
+    log.extend_from_slice(b"LQ garbage");
+
+    // When
+    let output = decode(&["--format", "log", "--output", "json"], log);
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let line: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert_eq!("SND-IR", line["function_code"]);
+}
@@ -0,0 +1,116 @@
+//! Verify the linkiq-encode command-line tool.
+
+use std::{
+    io::Write,
+    process::{Command, Output, Stdio},
+};
+
+const EXAMPLE41: &str = "CC48DE495CD17512402F093201372C341234121B166016617A01002005193229BCE64D651F1DED42687303B29AF6A6805336084A0CC4B4B92371A3CAB9FC9B4FFE09D35FE3FB1E3B5A49A71A342439873007BD8E4178777A827C723B8149BE18745008DB6E1F0133149679AC67A4E3FA083842991831";
+const EXAMPLE41_ARGS: &[&str] = &[
+    "--code-rate",
+    "1/2",
+    "--prioritized",
+    "--manufacturer",
+    "KAW",
+    "--id",
+    "12341234",
+    "--version",
+    "27",
+    "--device-type",
+    "0x16",
+    "--function-code",
+    "SND-IR",
+    "--payload",
+    "7A01002005193229BCE64D651F1DED42687303B29AF6A6805336084A0CC4B4B92371A3CAB9",
+];
+
+fn run(program: &str, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn encode(args: &[&str], input: &[u8]) -> Output {
+    run(env!("CARGO_BIN_EXE_linkiq-encode"), args, input)
+}
+
+#[test]
+fn can_encode_example_from_args() {
+    // When
+    let output = encode(EXAMPLE41_ARGS, &[]);
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    assert_eq!(format!("{EXAMPLE41}\n"), String::from_utf8(output.stdout).unwrap());
+}
+
+#[test]
+fn can_encode_example_from_json() {
+    // Given
+    let json = r#"{"code_rate":"1/2","is_prioritized":true,"address":{"manufacturer":"KAW","id":"12341234","version":27,"device_type":22},"function_code":"SND-IR","payload":"7A01002005193229BCE64D651F1DED42687303B29AF6A6805336084A0CC4B4B92371A3CAB9"}"#;
+
+    // When
+    let output = encode(&["--json", "-", "--output", "binary"], json.as_bytes());
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let hex: String = output.stdout.iter().map(|byte| format!("{byte:02X}")).collect();
+    assert_eq!(EXAMPLE41, hex);
+}
+
+#[test]
+fn can_inject_bit_errors_that_are_decoded() {
+    // Given
+    let mut args = EXAMPLE41_ARGS.to_vec();
+    args.extend(["--ber", "0.02", "--seed", "1337"]);
+
+    // When
+    let first = encode(&args, &[]);
+    let second = encode(&args, &[]);
+    let decoded = run(
+        env!("CARGO_BIN_EXE_linkiq-decode"),
+        &["--output", "json"],
+        &first.stdout,
+    );
+
+    // Then
+    assert_eq!(Some(0), first.status.code());
+    assert_eq!(first.stdout, second.stdout);
+    assert_ne!(format!("{EXAMPLE41}\n").as_bytes(), first.stdout);
+
+    assert_eq!(Some(0), decoded.status.code());
+    let line: serde_json::Value = serde_json::from_slice(&decoded.stdout).unwrap();
+    assert!(line["decode_iterations"].as_u64().unwrap() > 0);
+}
+
+#[test]
+fn can_truncate_frame() {
+    // Given
+    let mut args = EXAMPLE41_ARGS.to_vec();
+    args.extend(["--truncate", "4"]);
+
+    // When
+    let output = encode(&args, &[]);
+
+    // Then
+    assert_eq!(Some(0), output.status.code());
+    let frame = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(&EXAMPLE41[..2 * (118 - 4)], frame.trim());
+}
+
+#[test]
+fn can_reject_too_long_payload() {
+    // Given
+    let payload = "00".repeat(240);
+
+    // When
+    let output = encode(&["--payload", &payload], &[]);
+
+    // Then
+    assert_eq!(Some(2), output.status.code());
+}
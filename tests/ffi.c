@@ -0,0 +1,71 @@
+/* Encode and decode a packet through the C interface.
+ * The program is built and run by tests/ffi.rs and exits with a non-zero status on failure. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "linkiq.h"
+
+#define CHECK(condition)                                                 \
+    do {                                                                 \
+        if (!(condition)) {                                              \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,       \
+                    __LINE__, #condition);                               \
+            return 1;                                                    \
+        }                                                                \
+    } while (0)
+
+int main(void) {
+    static const uint8_t payload[] = {0x7A, 0x01, 0x00, 0x20, 0x05, 0x19, 0x32, 0x29};
+    static const uint8_t address[] = {0x2D, 0x2C, 0x89, 0x63, 0x00, 0x71, 0x34, 0x04};
+
+    LinkiqStack *stack = linkiq_stack_new();
+    CHECK(stack != NULL);
+    CHECK(linkiq_stack_set_max_iterations(stack, 8) == LINKIQ_OK);
+
+    LinkiqPacket packet = {0};
+    packet.code_rate = LINKIQ_CODE_RATE_ONE_THIRD;
+    packet.is_prioritized = true;
+    memcpy(packet.address, address, sizeof(address));
+    packet.function_code = LINKIQ_FUNCTION_CODE_SND_NR;
+    packet.payload = payload;
+    packet.payload_len = sizeof(payload);
+
+    uint8_t frame[LINKIQ_MAX_FRAME_LENGTH];
+    size_t length = 0;
+    CHECK(linkiq_stack_encode(stack, &packet, frame, sizeof(frame), &length) == LINKIQ_OK);
+
+    size_t frame_length = 0;
+    CHECK(linkiq_frame_length(frame, 12, &frame_length) == LINKIQ_OK);
+    CHECK(frame_length == length);
+
+    /* Too small buffer */
+    uint8_t small[16];
+    CHECK(linkiq_stack_encode(stack, &packet, small, sizeof(small), &length) == LINKIQ_ERROR_CAPACITY);
+
+    /* Invalid function code */
+    LinkiqPacket invalid = packet;
+    invalid.function_code = 0xFF;
+    CHECK(linkiq_stack_encode(stack, &invalid, frame, sizeof(frame), &length) == LINKIQ_ERROR_INVALID_ARGUMENT);
+
+    /* Corrupt the systematic part so that the turbo decoder must run */
+    frame[14] ^= 0xFF;
+
+    LinkiqPacket decoded;
+    CHECK(linkiq_stack_decode(stack, frame, frame_length, &decoded) == LINKIQ_OK);
+    CHECK(decoded.code_rate == LINKIQ_CODE_RATE_ONE_THIRD);
+    CHECK(decoded.is_prioritized);
+    CHECK(memcmp(decoded.address, address, sizeof(address)) == 0);
+    CHECK(decoded.function_code == LINKIQ_FUNCTION_CODE_SND_NR);
+    CHECK(decoded.decode_iterations > 0);
+    CHECK(decoded.payload >= frame && decoded.payload < frame + frame_length);
+    CHECK(decoded.payload_len == sizeof(payload));
+    CHECK(memcmp(decoded.payload, payload, sizeof(payload)) == 0);
+
+    /* Truncated frame */
+    CHECK(linkiq_stack_decode(stack, frame, 20, &decoded) == LINKIQ_ERROR_NOT_ENOUGH_BYTES);
+    CHECK(strcmp(linkiq_status_str(LINKIQ_ERROR_NOT_ENOUGH_BYTES), "not enough bytes") == 0);
+
+    linkiq_stack_free(stack);
+    return 0;
+}
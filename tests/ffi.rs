@@ -0,0 +1,107 @@
+//! Build the library as a static library and verify the C interface with a C program.
+
+use std::{
+    env,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+fn build_static_library(target_dir: &Path) -> PathBuf {
+    // Use a separate target directory so that the build does not wait for the lock held by the running test
+    let status = Command::new(env::var("CARGO").unwrap_or("cargo".into()))
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .args([
+            "rustc",
+            "--lib",
+            "--features",
+            "ffi",
+            "--crate-type",
+            "staticlib",
+        ])
+        .arg("--target-dir")
+        .arg(target_dir)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    target_dir.join("debug").join("liblinkiq.a")
+}
+
+#[test]
+fn can_run_c_program() {
+    // Given
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let target_dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join("ffi");
+    let library = build_static_library(&target_dir);
+    let program = target_dir.join("ffi-test");
+
+    let status = Command::new(env::var("CC").unwrap_or("cc".into()))
+        .arg("-std=c11")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests").join("ffi.c"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .unwrap();
+    assert!(status.success());
+
+    // When
+    let output = Command::new(&program).output().unwrap();
+
+    // Then
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
+
+/// Run with `cargo test --features ffi --test ffi -- --ignored` when cbindgen is installed
+#[test]
+#[ignore = "requires cbindgen"]
+fn header_matches_cbindgen() {
+    // Given
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let generated = Path::new(env!("CARGO_TARGET_TMPDIR")).join("linkiq.h");
+
+    // When
+    let status = Command::new("cbindgen")
+        .current_dir(manifest_dir)
+        .args(["--config", "cbindgen.toml", "--output"])
+        .arg(&generated)
+        .status()
+        .expect("cbindgen is not installed");
+
+    // Then
+    assert!(status.success());
+    assert_eq!(
+        std::fs::read_to_string(&generated).unwrap(),
+        std::fs::read_to_string(manifest_dir.join("include").join("linkiq.h")).unwrap(),
+        "include/linkiq.h is out of date, regenerate it with `cbindgen --config cbindgen.toml --output include/linkiq.h`"
+    );
+}
+
+#[test]
+fn header_defines_max_frame_length() {
+    // Given
+    let header =
+        std::fs::read_to_string(Path::new(env!("CARGO_MANIFEST_DIR")).join("include/linkiq.h"))
+            .unwrap();
+
+    // When
+    let defined = header
+        .lines()
+        .find_map(|line| line.strip_prefix("#define LINKIQ_MAX_FRAME_LENGTH "))
+        .unwrap();
+
+    // Then
+    assert_eq!(
+        linkiq::ffi::LINKIQ_MAX_FRAME_LENGTH.to_string(),
+        defined,
+        "the length in cbindgen.toml does not match the Rust constant"
+    );
+}
@@ -0,0 +1,77 @@
+//! Verify that packets are read and written without heap allocations.
+//! The test has its own binary so that the global allocator can forbid allocations.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use heapless::Vec;
+use linkiq::{
+    fec::CodeRate,
+    stack::{mbal::MbalFunctionCode, Packet, PacketBuilder, Stack},
+};
+use wmbus::WMBusAddress;
+
+struct ForbiddingAllocator;
+
+thread_local! {
+    static FORBIDDEN: Cell<bool> = const { Cell::new(false) };
+}
+
+unsafe impl GlobalAlloc for ForbiddingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if FORBIDDEN.with(|forbidden| forbidden.get()) {
+            // The allocator must not unwind, so abort the test process instead
+            FORBIDDEN.with(|forbidden| forbidden.set(false));
+            eprintln!("Unexpected allocation of {} bytes", layout.size());
+            std::process::abort();
+        }
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: ForbiddingAllocator = ForbiddingAllocator;
+
+fn without_allocations<R>(f: impl FnOnce() -> R) -> R {
+    FORBIDDEN.with(|forbidden| forbidden.set(true));
+    let result = f();
+    FORBIDDEN.with(|forbidden| forbidden.set(false));
+    result
+}
+
+#[test]
+fn can_write_and_read_without_allocations() {
+    // Given
+    let stack = Stack::new();
+    let packet: Packet = PacketBuilder::new()
+        .code_rate(CodeRate::OneThird)
+        .address(WMBusAddress::new(
+            0x2c2d.try_into().unwrap(),
+            71006389,
+            0x34,
+            0x04.try_into().unwrap(),
+        ))
+        .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+        .mbus_data(&[0x7A, 0x01, 0x00, 0x20, 0x05, 0x19, 0x32, 0x29])
+        .build()
+        .unwrap();
+    let mut frame = Vec::<u8, 400>::new();
+
+    // When
+    without_allocations(|| stack.write(&mut frame, &packet)).unwrap();
+
+    // Corrupt the systematic part so that the turbo decoder must run
+    frame[14] ^= 0xFF;
+    let read = without_allocations(|| stack.read(&frame));
+
+    // Then
+    let read = read.unwrap();
+    assert!(read.rx.unwrap().decode_iterations > 0);
+    assert_eq!(packet.mbus_data, read.mbus_data);
+}
@@ -0,0 +1,132 @@
+//! Verify that packets survive a round trip through JSON and a compact binary format.
+
+use linkiq::{
+    fec::CodeRate,
+    stack::{mbal::MbalFunctionCode, Channel, Packet, PacketBuilder, Stack},
+};
+use wmbus::WMBusAddress;
+
+fn packet() -> Packet {
+    let stack = Stack::new();
+    let mut frame = std::vec::Vec::new();
+    let packet: Packet = PacketBuilder::new()
+        .code_rate(CodeRate::OneThird)
+        .prioritized(true)
+        .address(address())
+        .function_code(MbalFunctionCode::SendInstallationRequest)
+        .mbus_data(&[0x7A, 0x01, 0x00, 0x20, 0x05, 0x19, 0x32, 0x29])
+        .build()
+        .unwrap();
+    stack.write(&mut frame, &packet).unwrap();
+
+    // Read the packet back so that the reception metadata is present
+    stack.read(&frame).unwrap()
+}
+
+fn address() -> WMBusAddress {
+    WMBusAddress::new(
+        0x2c2d.try_into().unwrap(),
+        71006389,
+        0x34,
+        0x04.try_into().unwrap(),
+    )
+}
+
+#[test]
+fn can_serialize_to_json_with_stable_names() {
+    // Given
+    let packet = packet();
+
+    // When
+    let json = serde_json::to_value(&packet).unwrap();
+
+    // Then
+    assert_eq!("one_third", json["phl"]["code_rate"]);
+    assert_eq!(true, json["mbal"]["control"]["is_prioritized"]);
+    assert_eq!(
+        "send_installation_request",
+        json["mbal"]["command"]["function_code"]
+    );
+    assert_eq!("KAM", json["mbal"]["address"]["manufacturer"]);
+    assert_eq!("71006389", json["mbal"]["address"]["id"]);
+    assert_eq!(0x34, json["mbal"]["address"]["version"]);
+    assert_eq!(0x04, json["mbal"]["address"]["device_type"]);
+    assert_eq!(0x7A, json["mbus_data"][0]);
+    assert_eq!(1, json["rx"]["combined_receptions"]);
+}
+
+#[test]
+fn can_round_trip_json() {
+    // Given
+    let packet = packet();
+    let json = serde_json::to_string(&packet).unwrap();
+
+    // When
+    let deserialized: Packet = serde_json::from_str(&json).unwrap();
+
+    // Then
+    assert_eq!(address(), deserialized.mbal.as_ref().unwrap().address);
+    assert_eq!(packet.mbus_data, deserialized.mbus_data);
+    assert_eq!(json, serde_json::to_string(&deserialized).unwrap());
+}
+
+#[test]
+fn can_round_trip_postcard() {
+    // Given
+    let packet = packet();
+    let bytes = postcard::to_allocvec(&packet).unwrap();
+
+    // When
+    let deserialized: Packet = postcard::from_bytes(&bytes).unwrap();
+
+    // Then
+    assert_eq!(address(), deserialized.mbal.as_ref().unwrap().address);
+    assert_eq!(
+        serde_json::to_value(&packet).unwrap(),
+        serde_json::to_value(&deserialized).unwrap()
+    );
+}
+
+#[test]
+fn can_round_trip_channel() {
+    for channel in [Channel::A, Channel::B, Channel::C, Channel::D] {
+        // When
+        let json = serde_json::to_string(&channel).unwrap();
+        let bytes = postcard::to_allocvec(&channel).unwrap();
+
+        // Then
+        assert_eq!(channel, serde_json::from_str::<Channel>(&json).unwrap());
+        assert_eq!(channel, postcard::from_bytes::<Channel>(&bytes).unwrap());
+    }
+}
+
+#[test]
+fn can_not_deserialize_invalid_manufacturer() {
+    // Given
+    let mut json = serde_json::to_value(packet()).unwrap();
+    json["mbal"]["address"]["manufacturer"] = "K4M".into();
+
+    // When
+    let result = serde_json::from_value::<Packet>(json);
+
+    // Then
+    assert!(result.is_err());
+}
+
+#[test]
+fn can_round_trip_manufacturer_beyond_z() {
+    // Given
+    let mut json = serde_json::to_value(packet()).unwrap();
+    json["mbal"]["address"]["manufacturer"] = "[_@".into();
+
+    // When
+    let deserialized: Packet = serde_json::from_value(json.clone()).unwrap();
+
+    // Then
+    let bytes = deserialized.mbal.as_ref().unwrap().address.get_bytes();
+    assert_eq!(
+        (27 << 10) | (31 << 5),
+        u16::from_le_bytes([bytes.as_slice()[0], bytes.as_slice()[1]])
+    );
+    assert_eq!(json, serde_json::to_value(&deserialized).unwrap());
+}
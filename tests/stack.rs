@@ -2,9 +2,14 @@ use self::examples::*;
 use assert_hex::assert_eq_hex;
 use bitvec::prelude::*;
 use heapless::Vec;
-use linkiq::stack::{
-    mbal::{self, MbalFunctionCode},
-    phl, Packet, Stack,
+use linkiq::{
+    fec::{CodeRate, Llr},
+    stack::{
+        apl::{self, Apl},
+        filter::Whitelist,
+        mbal::{self, MbalFunctionCode},
+        phl, Layer, Packet, PacketBuilder, ReadError, Stack, WriteError, Writer,
+    },
 };
 use rand::prelude::*;
 
@@ -68,7 +73,40 @@ fn can_read_example_case(
 ) {
     // Given
     let stack = Stack::new();
-    let mut frame = Vec::<u8, 400>::from_slice(vector.frame).unwrap();
+    let frame = with_bit_errors(vector.frame, ber);
+
+    // When
+    let packet: Packet<239> = stack.read(&frame).unwrap();
+
+    // Then
+    assert_eq!(vector.frame.len(), phl::get_frame_length(&frame).unwrap());
+
+    assert_eq!(vector.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
+    assert_eq!(
+        (header_distance, decode_iterations, decode_distance),
+        (
+            rx.header_distance,
+            rx.decode_iterations,
+            rx.decode_distance
+        )
+    );
+
+    assert_eq!(1, rx.combined_receptions);
+
+    let mbal = packet.mbal.unwrap();
+    assert_eq!(vector.is_prioritized, mbal.control.is_prioritized);
+    assert_eq!(vector.address, mbal.address);
+    assert_eq!(
+        vector.is_installation,
+        mbal.command.function_code == MbalFunctionCode::SendInstallationRequest
+    );
+
+    assert_eq!(vector.mbus_data, packet.mbus_data);
+}
+
+fn with_bit_errors(frame: &[u8], ber: f64) -> Vec<u8, 400> {
+    let mut frame = Vec::<u8, 400>::from_slice(frame).unwrap();
 
     if ber > 0.0 {
         let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
@@ -81,32 +119,761 @@ fn can_read_example_case(
         }
     }
 
+    frame
+}
+
+#[test]
+fn can_read_examples_into_packet() {
+    can_read_example_into_packet_case(&EXAMPLE41, 5, 4, 32, 0.07);
+    can_read_example_into_packet_case(&EXAMPLE42, 7, 5, 71, 0.08);
+    can_read_example_into_packet_case(&EXAMPLE43, 11, 8, 85, 0.14);
+    can_read_example_into_packet_case(&EXAMPLE44, 11, 5, 69, 0.14);
+}
+
+fn can_read_example_into_packet_case(
+    vector: &ExampleVector,
+    header_distance: usize,
+    decode_iterations: usize,
+    decode_distance: usize,
+    ber: f64,
+) {
+    // Given
+    let stack = Stack::new();
+    let frame = with_bit_errors(vector.frame, ber);
+    let mut packet = Packet::<239>::default();
+
     // When
-    let packet: Packet<239> = stack.read(&frame).unwrap();
+    stack.read_into(&mut packet, &frame).unwrap();
 
     // Then
-    assert_eq!(vector.frame.len(), phl::get_frame_length(&frame).unwrap());
-
-    let phl = packet.phl.unwrap();
-    assert_eq!(vector.code_rate, phl.code_rate);
+    assert_eq!(vector.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
     assert_eq!(
         (header_distance, decode_iterations, decode_distance),
         (
-            phl.header_distance,
-            phl.decode_iterations,
-            phl.decode_distance
+            rx.header_distance,
+            rx.decode_iterations,
+            rx.decode_distance
         )
     );
 
     let mbal = packet.mbal.unwrap();
-    assert_eq!(vector.is_prioritized, mbal.control.is_prioritized);
     assert_eq!(vector.address, mbal.address);
+    assert_eq!(vector.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_read_soft_examples() {
+    can_read_soft_example_case(&EXAMPLE41);
+    can_read_soft_example_case(&EXAMPLE42);
+    can_read_soft_example_case(&EXAMPLE43);
+    can_read_soft_example_case(&EXAMPLE44);
+}
+
+fn can_read_soft_example_case(vector: &ExampleVector) {
+    // Given
+    let stack = Stack::new();
+    let mut llrs: std::vec::Vec<Llr> = vector
+        .frame
+        .view_bits::<Msb0>()
+        .iter()
+        .map(|bit| if *bit { 4 } else { -4 })
+        .collect();
+
+    // Weaken the systematic part of the first block so that the decoder must run
+    for llr in llrs[8 * phl::HEADER_SIZE..8 * phl::HEADER_SIZE + 16].iter_mut() {
+        *llr = -*llr / 4;
+    }
+
+    // When
+    let packet = stack.read_soft(&llrs).unwrap();
+
+    // Then
+    assert_eq!(vector.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
+    assert_eq!(0, rx.header_distance);
+    assert!(rx.decode_iterations > 0);
+
+    let mbal = packet.mbal.unwrap();
+    assert_eq!(vector.address, mbal.address);
+    assert_eq!(vector.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_flip_residual_errors_after_decoding() {
+    // Given
+    let mut stack = Stack::new();
+    let mut llrs: std::vec::Vec<Llr> = EXAMPLE41
+        .frame
+        .view_bits::<Msb0>()
+        .iter()
+        .map(|bit| if *bit { 4 } else { -4 })
+        .collect();
+
+    // Without parity the decoder can not correct the two weakly inverted systematic bits
+    let systematic_length = phl::get_systematic_length(EXAMPLE41.frame).unwrap();
+    for llr in llrs[8 * systematic_length..].iter_mut() {
+        *llr = 0;
+    }
+    for index in [8 * phl::HEADER_SIZE + 3, 8 * phl::HEADER_SIZE + 21] {
+        llrs[index] = -llrs[index] / 4;
+    }
+
+    assert!(matches!(
+        stack.read_soft(&llrs),
+        Err(ReadError::PhlDecodeError)
+    ));
+
+    // When
+    stack.phl_mut().max_bit_flips = 2;
+    let packet = stack.read_soft(&llrs).unwrap();
+
+    // Then
+    let rx = packet.rx.unwrap();
+    assert_eq!(stack.phl().max_decode_iterations, rx.decode_iterations);
+    assert_eq!(2, rx.bit_flips);
+    assert_eq!(EXAMPLE41.address, packet.mbal.unwrap().address);
+    assert_eq!(EXAMPLE41.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_read_truncated_soft_longest_frame() {
+    // Given
+    let mut stack = Stack::new();
+    stack.phl_mut().max_erasure_ratio = 0.5;
+    let frame = longest_frame();
+    let mut llrs: std::vec::Vec<Llr> = frame
+        .view_bits::<Msb0>()
+        .iter()
+        .map(|bit| if *bit { 4 } else { -4 })
+        .collect();
+
+    // Drop the last quarter of the parity and weaken the start of the systematic part
+    let systematic_length = phl::get_systematic_length(&frame).unwrap();
+    let missing = (frame.len() - systematic_length) / 4;
+    llrs.truncate(8 * (frame.len() - missing));
+    for llr in llrs[8 * phl::HEADER_SIZE..8 * phl::HEADER_SIZE + 16].iter_mut() {
+        *llr = -*llr / 4;
+    }
+
+    // When
+    let packet = stack.read_soft(&llrs).unwrap();
+
+    // Then
+    let rx = packet.rx.unwrap();
+    assert!(rx.decode_iterations > 0);
+    assert_eq!(8 * missing, rx.erased_bits);
+    assert_eq!(&LONGEST_MBUS_DATA[..], packet.mbus_data);
+}
+
+#[test]
+fn can_read_combined_examples() {
+    can_read_combined_example_case(&EXAMPLE41);
+    can_read_combined_example_case(&EXAMPLE42);
+    can_read_combined_example_case(&EXAMPLE43);
+    can_read_combined_example_case(&EXAMPLE44);
+}
+
+fn can_read_combined_example_case(vector: &ExampleVector) {
+    // Given
+    let stack = Stack::new();
+
+    // Invert a different third of the bits following the header in each reception
+    let length = vector.frame.len() - phl::HEADER_SIZE;
+    let receptions: std::vec::Vec<std::vec::Vec<u8>> = (0..3)
+        .map(|index| {
+            let mut frame = vector.frame.to_vec();
+            let start = phl::HEADER_SIZE + index * length / 3;
+            let end = phl::HEADER_SIZE + (index + 1) * length / 3;
+            for byte in frame[start..end].iter_mut() {
+                *byte = !*byte;
+            }
+            frame
+        })
+        .collect();
+    let receptions: std::vec::Vec<&[u8]> = receptions.iter().map(|frame| frame.as_slice()).collect();
+
+    assert!(stack.read(receptions[0]).is_err());
+
+    // When
+    let packet = stack.read_combined(&receptions).unwrap();
+
+    // Then
+    assert_eq!(vector.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
+    assert_eq!(0, rx.decode_iterations);
+    assert_eq!(3, rx.combined_receptions);
+
+    let mbal = packet.mbal.unwrap();
+    assert_eq!(vector.address, mbal.address);
+    assert_eq!(vector.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_read_combined_receptions_that_fail_alone() {
+    // Given
+    let stack = Stack::new();
+
+    // Flip a disjoint eighth of the bits following the header in each reception.
+    // This is too many errors for the rate 1/2 code, but the combined bits only have erasures.
+    let mut rng = rand_chacha::ChaCha8Rng::seed_from_u64(0x1337);
+    let mut first = EXAMPLE41.frame.to_vec();
+    let mut second = EXAMPLE41.frame.to_vec();
+    for index in 8 * phl::HEADER_SIZE..8 * EXAMPLE41.frame.len() {
+        let value = rng.random::<f64>();
+        let frame = if value < 0.125 {
+            &mut first
+        } else if value < 0.25 {
+            &mut second
+        } else {
+            continue;
+        };
+        let bits = frame.view_bits_mut::<Msb0>();
+        let bit = bits[index];
+        bits.set(index, !bit);
+    }
+
+    assert!(stack.read(&first).is_err());
+    assert!(stack.read(&second).is_err());
+
+    // When
+    let packet = stack.read_combined(&[first.as_slice(), second.as_slice()]).unwrap();
+
+    // Then
+    let rx = packet.rx.unwrap();
+    assert!(rx.decode_iterations > 0);
+    assert_eq!(2, rx.combined_receptions);
+    assert_eq!(EXAMPLE41.mbus_data, packet.mbus_data);
+}
+
+/// The M-Bus data of the longest frame
+const LONGEST_MBUS_DATA: [u8; apl::MBUS_DATA_MAX] = [0x5A; apl::MBUS_DATA_MAX];
+
+/// Get the rate 1/3 frame with the longest MBAL block
+fn longest_frame() -> std::vec::Vec<u8> {
+    let packet: Packet = PacketBuilder::new()
+        .code_rate(CodeRate::OneThird)
+        .address(EXAMPLE41.address.clone())
+        .function_code(MbalFunctionCode::SendUnsolicitedApplicationData)
+        .mbus_data(&LONGEST_MBUS_DATA)
+        .build()
+        .unwrap();
+    let mut frame = std::vec::Vec::new();
+    Stack::new().write(&mut frame, &packet).unwrap();
+    assert_eq!(phl::HEADER_SIZE + 3 * (mbal::MBAL_MAX + 4), frame.len());
+    frame
+}
+
+#[test]
+fn can_read_combined_longest_frame() {
+    // Given
+    let stack = Stack::new();
+    let frame = longest_frame();
+
+    // When
+    let packet = stack.read_combined(&[frame.as_slice(), frame.as_slice()]).unwrap();
+
+    // Then
+    assert_eq!(2, packet.rx.unwrap().combined_receptions);
+    assert_eq!(&LONGEST_MBUS_DATA[..], packet.mbus_data);
+}
+
+#[test]
+fn can_not_combine_different_frames() {
+    // Given
+    let stack = Stack::new();
+
+    // When
+    let result = stack.read_combined(&[EXAMPLE41.frame, EXAMPLE42.frame]);
+
+    // Then
+    assert!(matches!(result, Err(ReadError::ReceptionMismatch)));
+}
+
+#[test]
+fn can_read_truncated_examples() {
+    can_read_truncated_example_case(&EXAMPLE41);
+    can_read_truncated_example_case(&EXAMPLE42);
+    can_read_truncated_example_case(&EXAMPLE43);
+    can_read_truncated_example_case(&EXAMPLE44);
+}
+
+fn can_read_truncated_example_case(vector: &ExampleVector) {
+    // Given
+    let mut stack = Stack::new();
+    stack.phl_mut().max_erasure_ratio = 0.5;
+
+    // Drop the last quarter of the parity and invert the first systematic byte
+    let systematic_length = phl::get_systematic_length(vector.frame).unwrap();
+    let missing = (vector.frame.len() - systematic_length) / 4;
+    let mut frame = vector.frame[..vector.frame.len() - missing].to_vec();
+    frame[phl::HEADER_SIZE] = !frame[phl::HEADER_SIZE];
+
+    assert!(matches!(
+        Stack::new().read(&frame),
+        Err(ReadError::NotEnoughBytes)
+    ));
+
+    // When
+    let packet = stack.read(&frame).unwrap();
+
+    // Then
+    assert_eq!(vector.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
+    assert!(rx.decode_iterations > 0);
+    assert_eq!(8 * missing, rx.erased_bits);
+
+    let mbal = packet.mbal.unwrap();
+    assert_eq!(vector.address, mbal.address);
+    assert_eq!(vector.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_read_examples_without_parity() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let stack = Stack::new();
+        let systematic_length = phl::get_systematic_length(vector.frame).unwrap();
+        let frame = &vector.frame[..systematic_length];
+        assert!(phl::is_systematic_crc_valid(frame).unwrap());
+
+        // When
+        let packet = stack.read(frame).unwrap();
+
+        // Then
+        let rx = packet.rx.unwrap();
+        assert_eq!(0, rx.decode_iterations);
+        assert_eq!(8 * (vector.frame.len() - systematic_length), rx.erased_bits);
+        assert_eq!(vector.mbus_data, packet.mbus_data);
+    }
+}
+
+#[test]
+fn can_not_read_truncated_systematic_part() {
+    // Given
+    let mut stack = Stack::new();
+    stack.phl_mut().max_erasure_ratio = 1.0;
+    let systematic_length = phl::get_systematic_length(EXAMPLE41.frame).unwrap();
+
+    // When
+    let result = stack.read(&EXAMPLE41.frame[..systematic_length - 1]);
+
+    // Then
+    assert!(matches!(result, Err(ReadError::NotEnoughBytes)));
+}
+
+#[test]
+fn can_read_alternate_header_candidate() {
+    // Given
+    let mut stack = Stack::new();
+    stack.phl_mut().header_candidates = phl::MAX_HEADER_CANDIDATES;
+
+    // Move the coded header just past half way towards the coded header of another example
+    let mut frame = EXAMPLE41.frame.to_vec();
+    let other = EXAMPLE43.frame;
+    let differing: std::vec::Vec<usize> = (2..84)
+        .filter(|&index| frame.view_bits::<Msb0>()[index] != other.view_bits::<Msb0>()[index])
+        .collect();
+    for &index in &differing[..differing.len() / 2 + 1] {
+        let bits = frame.view_bits_mut::<Msb0>();
+        let bit = bits[index];
+        bits.set(index, !bit);
+    }
+
+    assert!(Stack::new().read(&frame).is_err());
+
+    // When
+    let packet = stack.read(&frame).unwrap();
+
+    // Then
+    assert_eq!(EXAMPLE41.code_rate, packet.phl.unwrap().code_rate);
+    let rx = packet.rx.unwrap();
+    assert!(rx.header_candidate > 0);
+    assert_eq!(differing.len() / 2 + 1, rx.header_distance);
+
+    let mbal = packet.mbal.unwrap();
+    assert_eq!(EXAMPLE41.address, mbal.address);
+    assert_eq!(EXAMPLE41.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_skip_oversized_header_candidate() {
+    // Given
+    let mut stack = <linkiq::sized_stack!(Apl, 64)>::new_sized();
+
+    // Move the coded header just past half way towards the coded header of a frame too long for the stack
+    let mut frame = EXAMPLE41.frame.to_vec();
+    let other = EXAMPLE42.frame;
+    let differing: std::vec::Vec<usize> = (2..84)
+        .filter(|&index| frame.view_bits::<Msb0>()[index] != other.view_bits::<Msb0>()[index])
+        .collect();
+    for &index in &differing[..differing.len() / 2 + 1] {
+        let bits = frame.view_bits_mut::<Msb0>();
+        let bit = bits[index];
+        bits.set(index, !bit);
+    }
+
+    stack.phl_mut().header_candidates = 1;
+    assert!(matches!(stack.read(&frame), Err(ReadError::Capacity)));
+    stack.phl_mut().header_candidates = phl::MAX_HEADER_CANDIDATES;
+
+    // When
+    let packet = stack.read(&frame).unwrap();
+
+    // Then
+    assert!(packet.rx.unwrap().header_candidate > 0);
+    assert_eq!(EXAMPLE41.address, packet.mbal.unwrap().address);
+    assert_eq!(EXAMPLE41.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_get_frame_length_from_partial_header() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let llrs: std::vec::Vec<Llr> = vector.frame[..8]
+            .view_bits::<Msb0>()
+            .iter()
+            .map(|bit| if *bit { 4 } else { -4 })
+            .collect();
+
+        // When
+        let hard = phl::get_frame_length(&vector.frame[..phl::CODED_HEADER_SIZE]).unwrap();
+        let (soft, reliability) = phl::get_soft_frame_length(&llrs).unwrap();
+
+        // Then
+        assert_eq!(vector.frame.len(), hard);
+        assert_eq!(vector.frame.len(), soft);
+        assert!(reliability > 0);
+    }
+}
+
+#[test]
+fn can_peek_address() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let stack = Stack::new();
+        let partial = &vector.frame[..phl::HEADER_SIZE + 9];
+
+        // When
+        let address = stack.peek_address(partial).unwrap();
+
+        // Then
+        assert_eq!(vector.address, address);
+    }
+}
+
+#[test]
+fn can_read_filtered() {
+    // Given
+    let stack = Stack::new();
+    let addresses = [EXAMPLE41.address.clone()];
+    let filter = Whitelist::new(&addresses);
+
+    // When
+    let accepted = stack.read_filtered(EXAMPLE41.frame, &filter);
+    let rejected = stack.read_filtered(EXAMPLE42.frame, &filter);
+
+    // Then
+    assert_eq!(EXAMPLE41.mbus_data, accepted.unwrap().mbus_data);
+    assert!(matches!(rejected, Err(ReadError::Filtered)));
+}
+
+#[test]
+fn can_read_into_reused_packet() {
+    // Given
+    let stack = Stack::new();
+    let mut packet: Packet = Packet::default();
+
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // When
+        stack.read_into(&mut packet, vector.frame).unwrap();
+
+        // Then
+        assert_eq!(vector.address, packet.mbal.as_ref().unwrap().address);
+        assert_eq!(vector.mbus_data, packet.mbus_data);
+    }
+}
+
+#[test]
+fn can_not_read_into_too_small_packet() {
+    // Given
+    let stack = Stack::new();
+    let mut packet = Packet::<16>::default();
+
+    // When
+    let result = stack.read_into(&mut packet, EXAMPLE41.frame);
+
+    // Then
+    assert!(matches!(result, Err(ReadError::Capacity)));
+}
+
+#[test]
+fn can_read_examples_in_place() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let stack = Stack::new();
+        let systematic_length = phl::get_systematic_length(vector.frame).unwrap();
+        let mut frame = vector.frame.to_vec();
+        frame[phl::HEADER_SIZE] = !frame[phl::HEADER_SIZE];
+        let frame_range = frame.as_ptr_range();
+
+        // When
+        let packet = stack.read_ref(&mut frame).unwrap();
+
+        // Then
+        assert!(packet.rx.decode_iterations > 0);
+        assert_eq!(vector.address, packet.mbal.address);
+        assert_eq!(vector.mbus_data, packet.mbus_data);
+        assert!(frame_range.contains(&packet.mbus_data.as_ptr()));
+        assert_eq!(&vector.frame[..systematic_length], &frame[..systematic_length]);
+    }
+}
+
+#[test]
+fn can_read_examples_without_mbal() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let stack = Stack::phl_only();
+        let mut packet = Packet::<{ mbal::MBAL_MAX }>::default();
+        let block_end = phl::get_systematic_length(vector.frame).unwrap() - 4;
+
+        // When
+        stack.read_into(&mut packet, vector.frame).unwrap();
+
+        // Then
+        assert!(packet.phl.is_some());
+        assert!(packet.mbal.is_none());
+        assert_eq!(&vector.frame[phl::HEADER_SIZE..block_end], packet.mbus_data);
+    }
+}
+
+#[test]
+fn can_read_longest_frame_without_mbal() {
+    // Given
+    let stack = Stack::phl_only();
+    let frame = longest_frame();
+
+    // When
+    let too_small = stack.read(&frame);
+    let mut packet = Packet::<{ mbal::MBAL_MAX }>::default();
+    stack.read_into(&mut packet, &frame).unwrap();
+
+    // Then
+    assert!(matches!(too_small, Err(ReadError::Capacity)));
+    assert_eq!(mbal::MBAL_MAX, packet.mbus_data.len());
     assert_eq!(
-        vector.is_installation,
-        mbal.command.function_code == MbalFunctionCode::SendInstallationRequest
+        &frame[phl::HEADER_SIZE..phl::HEADER_SIZE + mbal::MBAL_MAX],
+        packet.mbus_data
     );
+}
 
-    assert_eq!(vector.mbus_data, packet.mbus_data);
+#[test]
+fn can_dissect_examples() {
+    for vector in [&EXAMPLE41, &EXAMPLE42, &EXAMPLE43, &EXAMPLE44] {
+        // Given
+        let stack = Stack::new();
+
+        // When
+        let dissection = stack.dissect(vector.frame).unwrap();
+
+        // Then
+        assert_eq!(vector.frame, dissection.corrected_frame());
+        assert!(dissection.fields.iter().all(|field| field.corrected_bits == 0));
+
+        // The top level fields cover the whole frame
+        let top: std::vec::Vec<_> = dissection
+            .fields
+            .iter()
+            .filter(|field| field.depth == 0)
+            .collect();
+        let names: std::vec::Vec<_> = top.iter().map(|field| field.name).collect();
+        assert_eq!(["PHY header", "Systematic block", "Parity"], names[..]);
+        assert_eq!(0, top[0].bit_offset);
+        for pair in top.windows(2) {
+            assert_eq!(pair[0].bit_offset + pair[0].bit_length, pair[1].bit_offset);
+        }
+        assert_eq!(8 * vector.frame.len(), top[2].bit_offset + top[2].bit_length);
+    }
+}
+
+#[test]
+fn can_dissect_corrected_bits() {
+    // Given
+    let stack = Stack::new();
+    let mut frame = EXAMPLE41.frame.to_vec();
+    frame[1] ^= 0x01; // Coded header
+    frame[20] ^= 0x80; // Device type
+    frame[100] ^= 0x10; // Parity
+
+    // When
+    let dissection = stack.dissect(&frame).unwrap();
+
+    // Then
+    assert_eq!(EXAMPLE41.frame, dissection.corrected_frame());
+    assert_eq!(&frame[..], dissection.received_frame());
+    assert!(dissection.is_corrected(15));
+    assert!(dissection.is_corrected(160));
+    assert!(dissection.is_corrected(803));
+    assert!(!dissection.is_corrected(161));
+
+    let field = |name: &str| {
+        dissection
+            .fields
+            .iter()
+            .find(|field| field.name == name)
+            .unwrap()
+    };
+    assert_eq!(1, field("Coded header").corrected_bits);
+    assert_eq!(1, field("Device type").corrected_bits);
+    assert_eq!(Some(0x96), field("Device type").value);
+    assert_eq!("0x16", field("Device type").interpretation);
+    assert_eq!("KAW", field("Manufacturer").interpretation);
+    assert_eq!("SND-IR", field("Command").interpretation);
+    assert_eq!("1/2", field("Code rate").interpretation);
+    assert_eq!(1, field("Parity").corrected_bits);
+    assert_eq!(0, field("CRC-32").corrected_bits);
+}
+
+#[test]
+fn can_not_read_unknown_mbal_command() {
+    // Given
+    let mut header = [0; mbal::HEADER_SIZE];
+    header[1..9].copy_from_slice(EXAMPLE41.address.get_bytes().as_slice());
+    header[9] = 0x00; // Function code 0
+    let crc = crc::Crc::<u16>::new(&crc::CRC_16_EN_13757).checksum(&header[..10]);
+    header[10..].copy_from_slice(&crc.to_be_bytes());
+
+    // When
+    let result = mbal::read_fields(&header);
+
+    // Then
+    assert!(matches!(result, Err(ReadError::MBalCommandError)));
+}
+
+#[test]
+fn can_dissect_longest_frame() {
+    // Given
+    let stack = Stack::new();
+    let transmitted = longest_frame();
+    let mut frame = transmitted.clone();
+    let last = frame.len() - 1;
+    frame[last] ^= 0x01; // Parity
+    frame.extend_from_slice(&[0xFF; 8]);
+
+    // When
+    let dissection = stack.dissect(&frame).unwrap();
+
+    // Then
+    assert_eq!(&transmitted[..], dissection.corrected_frame());
+    assert_eq!(&frame[..transmitted.len()], dissection.received_frame());
+    assert!(dissection.is_corrected(8 * transmitted.len() - 1));
+}
+
+#[test]
+fn can_not_dissect_frame_beyond_mbal_max() {
+    // Given
+    let stack = <linkiq::sized_stack!(Apl, 64)>::new_sized();
+
+    // When
+    let result = stack.dissect(&longest_frame());
+
+    // Then
+    assert!(matches!(result, Err(ReadError::Capacity)));
+}
+
+/// A custom application layer that parses the CI field into the packet extension
+struct CiLayer;
+
+impl Layer for CiLayer {
+    type Extension = Option<u8>;
+
+    fn read<const N: usize>(
+        &self,
+        packet: &mut Packet<N, Option<u8>>,
+        buffer: &[u8],
+    ) -> Result<(), ReadError> {
+        let (ci, data) = buffer.split_first().ok_or(ReadError::NotEnoughBytes)?;
+        packet.extension = Some(*ci);
+        packet.mbus_data = Vec::from_slice(data).map_err(|_| ReadError::Capacity)?;
+        Ok(())
+    }
+
+    fn write<const N: usize>(
+        &self,
+        writer: &mut impl Writer,
+        packet: &Packet<N, Option<u8>>,
+    ) -> Result<(), WriteError> {
+        writer.write(&[packet.extension.unwrap()])?;
+        writer.write(&packet.mbus_data)
+    }
+}
+
+#[test]
+fn can_read_with_custom_layer() {
+    // Given
+    let stack = Stack::with_layer(CiLayer);
+
+    // When
+    let packet = stack.read(EXAMPLE41.frame).unwrap();
+
+    // Then
+    assert_eq!(Some(EXAMPLE41.mbus_data[0]), packet.extension);
+    assert_eq!(&EXAMPLE41.mbus_data[1..], packet.mbus_data);
+    assert_eq!(EXAMPLE41.address, packet.mbal.unwrap().address);
+}
+
+#[test]
+fn can_read_with_smaller_decoder() {
+    // Given
+    let stack = <linkiq::sized_stack!(Apl, 64)>::new_sized();
+    let mut frame = EXAMPLE41.frame.to_vec();
+    frame[phl::HEADER_SIZE] = !frame[phl::HEADER_SIZE];
+
+    // When
+    let packet = stack.read(&frame).unwrap();
+
+    // Then
+    assert!(packet.rx.unwrap().decode_iterations > 0);
+    assert_eq!(EXAMPLE41.mbus_data, packet.mbus_data);
+}
+
+#[test]
+fn can_not_decode_beyond_decoder_capacity() {
+    // Given
+    let stack = <linkiq::sized_stack!(Apl, 32)>::new_sized();
+    let mut frame = EXAMPLE41.frame.to_vec();
+
+    // When
+    let valid = stack.read(&frame);
+    frame[phl::HEADER_SIZE] = !frame[phl::HEADER_SIZE];
+    let invalid = stack.read(&frame);
+
+    // Then
+    assert_eq!(EXAMPLE41.mbus_data, valid.unwrap().mbus_data);
+    assert!(matches!(invalid, Err(ReadError::Capacity)));
+}
+
+#[test]
+fn stack_size_follows_mbal_max() {
+    // Given
+    let mbal_max = [32, 64, 128, mbal::MBAL_MAX];
+
+    // When
+    let sizes = [
+        core::mem::size_of::<linkiq::sized_stack!(Apl, 32)>(),
+        core::mem::size_of::<linkiq::sized_stack!(Apl, 64)>(),
+        core::mem::size_of::<linkiq::sized_stack!(Apl, 128)>(),
+        core::mem::size_of::<Stack>(),
+    ];
+
+    // Then
+    for (mbal_max, size) in mbal_max.into_iter().zip(sizes) {
+        assert!(size >= phl::block_bits(mbal_max));
+    }
+    for pair in mbal_max.windows(2).zip(sizes.windows(2)) {
+        let grown_bits = phl::block_bits(pair.0[1]) - phl::block_bits(pair.0[0]);
+        assert!(pair.1[1] - pair.1[0] >= grown_bits);
+    }
 }
 
 #[test]
@@ -121,30 +888,17 @@ fn can_write_example_case(vector: &ExampleVector) {
     // Given
     let stack = Stack::new();
     let mut writer = Vec::<u8, 400>::new();
-    let packet: Packet<300> = Packet {
-        rssi: None,
-        phl: Some(phl::PhlFields {
-            code_rate: vector.code_rate,
-            header_distance: 0,
-            decode_iterations: 0,
-            decode_distance: 0,
-        }),
-        mbal: Some(mbal::MbalFields {
-            control: mbal::MbalControl {
-                is_prioritized: vector.is_prioritized,
-            },
-            address: vector.address.clone(),
-            command: match vector.is_installation {
-                true => mbal::MbalCommand {
-                    function_code: mbal::MbalFunctionCode::SendInstallationRequest,
-                },
-                false => mbal::MbalCommand {
-                    function_code: mbal::MbalFunctionCode::SendUnsolicitedApplicationData,
-                },
-            },
-        }),
-        mbus_data: Vec::from_slice(vector.mbus_data).unwrap(),
-    };
+    let packet: Packet<300> = PacketBuilder::new()
+        .code_rate(vector.code_rate)
+        .prioritized(vector.is_prioritized)
+        .address(vector.address.clone())
+        .function_code(match vector.is_installation {
+            true => MbalFunctionCode::SendInstallationRequest,
+            false => MbalFunctionCode::SendUnsolicitedApplicationData,
+        })
+        .mbus_data(vector.mbus_data)
+        .build()
+        .unwrap();
 
     // When
     stack.write(&mut writer, &packet).unwrap();